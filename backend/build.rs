@@ -0,0 +1,35 @@
+use std::{process::Command, time::SystemTime};
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+fn main() {
+    // Builds without the repository, e.g. in docker, can pass the hash themselves
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+    let hash = std::env::var("GIT_HASH")
+        .ok()
+        .or_else(|| git(&["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=GIT_HASH={hash}");
+
+    // Rebuild when HEAD moves to another commit
+    for path in ["HEAD", "refs/heads"] {
+        if let Some(path) = git(&["rev-parse", "--git-path", path]) {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+
+    let built_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={built_at}");
+}
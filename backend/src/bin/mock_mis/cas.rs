@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Form, Router,
+};
+use axum_extra::extract::CookieJar;
+
+use crate::state::{random_token, MockState};
+
+const LOGIN_PAGE: &str = include_str!("../../../fixtures/mis/cas_login.html");
+
+const INVALID_CREDENTIALS: &str =
+    "The credentials you provided cannot be determined to be authentic.";
+
+pub fn router() -> Router<MockState> {
+    Router::new().route("/login", get(login_page).post(login))
+}
+
+async fn login_page(
+    State(state): State<MockState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let jsession_id = random_token("JSESSION");
+    let service = query.get("service").cloned().unwrap_or_default();
+
+    (
+        [(
+            header::SET_COOKIE,
+            format!("JSESSIONID={jsession_id}; Path=/cas; HttpOnly"),
+        )],
+        render_login_page(&state, &jsession_id, &service, None),
+    )
+        .into_response()
+}
+
+async fn login(
+    State(state): State<MockState>,
+    Query(query): Query<HashMap<String, String>>,
+    jar: CookieJar,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let service = query.get("service").cloned().unwrap_or_default();
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+
+    let jsession_id = match jar.get("JSESSIONID") {
+        Some(cookie) => cookie.value().to_owned(),
+        None => {
+            tracing::debug!("login attempt without JSESSIONID");
+            return render_login_page(&state, "", &service, Some(INVALID_CREDENTIALS))
+                .into_response();
+        }
+    };
+
+    if !state.consume_login_ticket(field("lt")) {
+        tracing::debug!("login attempt with unknown lt '{}'", field("lt"));
+        return render_login_page(&state, &jsession_id, &service, Some(INVALID_CREDENTIALS))
+            .into_response();
+    }
+
+    let user = state.user();
+    if field("username") != user.email || field("password") != user.password {
+        tracing::debug!(
+            "login attempt with wrong credentials for '{}'",
+            field("username")
+        );
+        return render_login_page(&state, &jsession_id, &service, Some(INVALID_CREDENTIALS))
+            .into_response();
+    }
+
+    let separator = if service.contains('?') { '&' } else { '?' };
+    let location = format!(
+        "{service}{separator}ticket={}",
+        state.issue_service_ticket()
+    );
+
+    (StatusCode::FOUND, [(header::LOCATION, location)]).into_response()
+}
+
+fn render_login_page(
+    state: &MockState,
+    jsession_id: &str,
+    service: &str,
+    error: Option<&str>,
+) -> Html<String> {
+    let errors = error
+        .map(|e| format!(r#"<div id="msg" class="errors">{e}</div>"#))
+        .unwrap_or_default();
+
+    Html(
+        LOGIN_PAGE
+            .replace("{{jsessionid}}", jsession_id)
+            .replace("{{service}}", service)
+            .replace("{{errors}}", &errors)
+            .replace("{{lt}}", &state.issue_login_ticket()),
+    )
+}
@@ -0,0 +1,83 @@
+//! A stand-in for Online MIS and the PENS CAS server.
+//!
+//! Serves the pages under `fixtures/mis` so the backend and the frontend can be
+//! developed without student credentials or network access to PENS. Point the
+//! backend at it with `MIS_BASE_URL=http://127.0.0.1:9090` and
+//! `CAS_BASE_URL=http://127.0.0.1:9090/cas`.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use axum::Router;
+use clap::Parser;
+use state::{MockState, MockUser};
+use tokio::net::TcpListener;
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+mod cas;
+mod mis;
+mod state;
+
+#[derive(clap::Parser)]
+struct MockConfig {
+    /// The address the mock server binds to
+    #[clap(long, env = "MOCK_ADDRESS", default_value = "127.0.0.1")]
+    address: Ipv4Addr,
+
+    /// The port the mock server listens on
+    #[clap(long, env = "MOCK_PORT", default_value_t = 9090)]
+    port: u16,
+
+    /// The email accepted by the mock CAS login
+    #[clap(
+        long,
+        env = "MOCK_EMAIL",
+        default_value = "mahasiswa@it.student.pens.ac.id"
+    )]
+    email: String,
+
+    /// The password accepted by the mock CAS login
+    #[clap(long, env = "MOCK_PASSWORD", default_value = "password")]
+    password: String,
+
+    /// The NRP of the mock student
+    #[clap(long, env = "MOCK_NRP", default_value = "3122600000")]
+    nrp: String,
+
+    /// The name of the mock student
+    #[clap(long, env = "MOCK_NAME", default_value = "MAHASISWA CONTOH")]
+    name: String,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "mock_mis=debug,tower_http=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer().with_file(false))
+        .init();
+
+    let cfg = MockConfig::parse();
+    let state = MockState::new(MockUser {
+        email: cfg.email,
+        password: cfg.password,
+        nrp: cfg.nrp,
+        name: cfg.name,
+    });
+
+    let app = Router::new()
+        .nest("/cas", cas::router())
+        .merge(mis::router())
+        .with_state(state)
+        .layer(TraceLayer::new_for_http());
+
+    let listener = TcpListener::bind(SocketAddr::from((cfg.address, cfg.port))).await?;
+    tracing::info!("mock online mis listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
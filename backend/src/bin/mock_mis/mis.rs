@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Form, Router,
+};
+use axum_extra::extract::CookieJar;
+
+use crate::state::{LogbookEntry, LogbookWeek, MockState};
+
+const HOME_PAGE: &str = include_str!("../../../fixtures/mis/mEntry_Logbook_KP1.html");
+const FRS_PAGE: &str = include_str!("../../../fixtures/mis/FRS_mbkm.html");
+const ABSEN_PAGE: &str = include_str!("../../../fixtures/mis/absen.html");
+const JADWAL_PAGE: &str = include_str!("../../../fixtures/mis/jadwal_kul.html");
+const NILAI_PAGE: &str = include_str!("../../../fixtures/mis/nilai_sem.html");
+const LOGBOOK_PAGE: &str = include_str!("../../../fixtures/mis/entry_logbook_kp1.html");
+const LOGBOOK_SAVED_PAGE: &str = include_str!("../../../fixtures/mis/logbook_saved.html");
+const SESSION_EXPIRED_PAGE: &str = include_str!("../../../fixtures/mis/session_expired.html");
+
+const LOGBOOK_ROWS_START: &str = "<!-- logbook:rows -->";
+const LOGBOOK_ROWS_END: &str = "<!-- /logbook:rows -->";
+
+/// The current period shown on the home page, matching the fixtures
+const CURRENT_WEEK: LogbookWeek = (2024, 2, 5);
+
+pub fn router() -> Router<MockState> {
+    Router::new()
+        .route("/index.php", get(index))
+        .route("/mEntry_Logbook_KP1.php", get(home))
+        .route("/FRS_mbkm.php", get(|s, j| static_page(s, j, FRS_PAGE)))
+        .route("/absen.php", get(|s, j| static_page(s, j, ABSEN_PAGE)))
+        .route(
+            "/jadwal_kul.php",
+            get(|s, j| static_page(s, j, JADWAL_PAGE)),
+        )
+        .route("/nilai_sem.php", get(|s, j| static_page(s, j, NILAI_PAGE)))
+        .route(
+            "/entry_logbook_kp1.php",
+            get(logbook_detail).post(logbook_create),
+        )
+}
+
+fn has_session(state: &MockState, jar: &CookieJar) -> bool {
+    jar.get("PHPSESSID")
+        .is_some_and(|c| state.has_session(c.value()))
+}
+
+async fn index(
+    State(state): State<MockState>,
+    jar: CookieJar,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    match query.get("ticket") {
+        Some(ticket) if state.consume_service_ticket(ticket) => {
+            let session_id = state.create_session();
+            tracing::debug!("validated service ticket, created session '{session_id}'");
+
+            (
+                [(
+                    header::SET_COOKIE,
+                    format!("PHPSESSID={session_id}; path=/"),
+                )],
+                render_home(&state),
+            )
+                .into_response()
+        }
+        _ if has_session(&state, &jar) => render_home(&state).into_response(),
+        _ => Html(SESSION_EXPIRED_PAGE).into_response(),
+    }
+}
+
+async fn home(State(state): State<MockState>, jar: CookieJar) -> Response {
+    if !has_session(&state, &jar) {
+        return Html(SESSION_EXPIRED_PAGE).into_response();
+    }
+
+    render_home(&state).into_response()
+}
+
+async fn static_page(
+    State(state): State<MockState>,
+    jar: CookieJar,
+    page: &'static str,
+) -> Html<&'static str> {
+    if !has_session(&state, &jar) {
+        return Html(SESSION_EXPIRED_PAGE);
+    }
+
+    Html(page)
+}
+
+async fn logbook_detail(
+    State(state): State<MockState>,
+    jar: CookieJar,
+    Query(query): Query<HashMap<String, String>>,
+) -> Html<String> {
+    // Online MIS answers the logbook pages with an empty body once the session is gone
+    if !has_session(&state, &jar) {
+        return Html(String::new());
+    }
+
+    let week = logbook_week(&query);
+
+    if query.contains_key("Hapus") {
+        let id = query
+            .get("nokplogbook")
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_default();
+        let deleted = state.delete_logbook_entry(week, id);
+        tracing::debug!("deleting logbook {id} in {week:?}, deleted: {deleted}");
+    }
+
+    render_logbook(&state, week)
+}
+
+async fn logbook_create(
+    State(state): State<MockState>,
+    jar: CookieJar,
+    Form(form): Form<HashMap<String, String>>,
+) -> Html<String> {
+    if !has_session(&state, &jar) {
+        return Html(String::new());
+    }
+
+    let field = |name: &str| form.get(name).cloned().unwrap_or_default();
+
+    if field("tanggal").is_empty() || field("kegiatan").is_empty() {
+        return Html(LOGBOOK_SAVED_PAGE.replace("Simpan Data Berhasil", "Simpan Data Gagal"));
+    }
+
+    let matakuliah = match field("matakuliah").as_str() {
+        "4521" => Some("Pemrograman Web".to_owned()),
+        "4522" => Some("Basis Data Lanjut".to_owned()),
+        _ => None,
+    };
+
+    state.add_logbook_entry(
+        logbook_week(&form),
+        LogbookEntry {
+            id: 0,
+            tanggal: field("tanggal"),
+            jam_mulai: field("jam_mulai"),
+            jam_selesai: field("jam_selesai"),
+            kegiatan: field("kegiatan"),
+            matakuliah,
+            deletable: true,
+        },
+    );
+
+    Html(LOGBOOK_SAVED_PAGE.to_owned())
+}
+
+fn logbook_week(params: &HashMap<String, String>) -> LogbookWeek {
+    let (year, semester, week) = CURRENT_WEEK;
+
+    (
+        params
+            .get("valTahun")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(year),
+        params
+            .get("valSemester")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(semester),
+        params
+            .get("valMinggu")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(week),
+    )
+}
+
+fn render_home(state: &MockState) -> Html<String> {
+    let user = state.user();
+    let (year, semester, week) = CURRENT_WEEK;
+
+    Html(
+        HOME_PAGE
+            .replace("{{year}}", &year.to_string())
+            .replace("{{semester}}", &semester.to_string())
+            .replace("{{week}}", &week.to_string())
+            .replace("{{name}}", &user.name)
+            .replace("{{nrp}}", &user.nrp),
+    )
+}
+
+fn render_logbook(state: &MockState, week: LogbookWeek) -> Html<String> {
+    let rows: String = state
+        .logbook_entries(week)
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            format!(
+                r##"
+    <tr>
+      <td>{no}</td>
+      <td>{tanggal}</td>
+      <td>{jam_mulai}</td>
+      <td>{jam_selesai}</td>
+      <td>{kegiatan}</td>
+      <td>{matakuliah}</td>
+      <td><a href="#"></a></td>
+      <td><a href="upload_logbook/foto_{id}.jpg">Lihat</a></td>
+      <td><a href="cetak_logbook_kp1.php?id={id}">Cetak</a></td>
+      <td>{hapus}</td>
+    </tr>"##,
+                no = i + 1,
+                id = e.id,
+                tanggal = e.tanggal,
+                jam_mulai = e.jam_mulai,
+                jam_selesai = e.jam_selesai,
+                kegiatan = e.kegiatan,
+                matakuliah = e.matakuliah.as_deref().unwrap_or("-"),
+                hapus = if e.deletable {
+                    format!(
+                        r#"<img src="images/hapus.png" alt="hapus" onclick="hapusLogbook({})">"#,
+                        e.id
+                    )
+                } else {
+                    "&nbsp;".to_owned()
+                },
+            )
+        })
+        .collect();
+
+    let start = LOGBOOK_PAGE
+        .find(LOGBOOK_ROWS_START)
+        .map(|i| i + LOGBOOK_ROWS_START.len())
+        .unwrap_or_default();
+    let end = LOGBOOK_PAGE.find(LOGBOOK_ROWS_END).unwrap_or(start);
+
+    Html(format!(
+        "{}{}\n    {}",
+        &LOGBOOK_PAGE[..start],
+        rows,
+        &LOGBOOK_PAGE[end..]
+    ))
+}
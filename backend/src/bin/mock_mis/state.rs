@@ -0,0 +1,148 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub struct MockUser {
+    pub email: String,
+    pub password: String,
+    pub nrp: String,
+    pub name: String,
+}
+
+#[derive(Clone)]
+pub struct LogbookEntry {
+    pub id: u32,
+    pub tanggal: String,
+    pub jam_mulai: String,
+    pub jam_selesai: String,
+    pub kegiatan: String,
+    pub matakuliah: Option<String>,
+    pub deletable: bool,
+}
+
+/// Year, semester and week of a logbook page
+pub type LogbookWeek = (u16, u8, u8);
+
+#[derive(Clone)]
+pub struct MockState(Arc<Inner>);
+
+struct Inner {
+    user: MockUser,
+    login_tickets: Mutex<HashSet<String>>,
+    service_tickets: Mutex<HashSet<String>>,
+    sessions: Mutex<HashSet<String>>,
+    logbook: Mutex<BTreeMap<LogbookWeek, Vec<LogbookEntry>>>,
+    next_logbook_id: AtomicU32,
+}
+
+impl MockState {
+    pub fn new(user: MockUser) -> Self {
+        let seed = vec![
+            LogbookEntry {
+                id: 55501,
+                tanggal: "3 Maret 2025".to_owned(),
+                jam_mulai: "08:00".to_owned(),
+                jam_selesai: "16:00".to_owned(),
+                kegiatan: "Orientasi dan pengenalan lingkungan kerja".to_owned(),
+                matakuliah: None,
+                deletable: false,
+            },
+            LogbookEntry {
+                id: 55502,
+                tanggal: "4 Maret 2025".to_owned(),
+                jam_mulai: "08:30".to_owned(),
+                jam_selesai: "15:30".to_owned(),
+                kegiatan: "Membuat halaman login aplikasi internal".to_owned(),
+                matakuliah: Some("Pemrograman Web".to_owned()),
+                deletable: true,
+            },
+        ];
+
+        Self(Arc::new(Inner {
+            user,
+            login_tickets: Mutex::default(),
+            service_tickets: Mutex::default(),
+            sessions: Mutex::default(),
+            logbook: Mutex::new(BTreeMap::from([((2024, 2, 5), seed)])),
+            next_logbook_id: AtomicU32::new(55503),
+        }))
+    }
+
+    pub fn user(&self) -> &MockUser {
+        &self.0.user
+    }
+
+    pub fn issue_login_ticket(&self) -> String {
+        let lt = random_token("LT");
+        self.0.login_tickets.lock().unwrap().insert(lt.clone());
+        lt
+    }
+
+    pub fn consume_login_ticket(&self, lt: &str) -> bool {
+        self.0.login_tickets.lock().unwrap().remove(lt)
+    }
+
+    pub fn issue_service_ticket(&self) -> String {
+        let st = random_token("ST");
+        self.0.service_tickets.lock().unwrap().insert(st.clone());
+        st
+    }
+
+    pub fn consume_service_ticket(&self, st: &str) -> bool {
+        self.0.service_tickets.lock().unwrap().remove(st)
+    }
+
+    pub fn create_session(&self) -> String {
+        let session_id = random_token("mock");
+        self.0.sessions.lock().unwrap().insert(session_id.clone());
+        session_id
+    }
+
+    pub fn has_session(&self, session_id: &str) -> bool {
+        self.0.sessions.lock().unwrap().contains(session_id)
+    }
+
+    pub fn logbook_entries(&self, week: LogbookWeek) -> Vec<LogbookEntry> {
+        self.0
+            .logbook
+            .lock()
+            .unwrap()
+            .get(&week)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn add_logbook_entry(&self, week: LogbookWeek, mut entry: LogbookEntry) {
+        entry.id = self.0.next_logbook_id.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .logbook
+            .lock()
+            .unwrap()
+            .entry(week)
+            .or_default()
+            .push(entry);
+    }
+
+    pub fn delete_logbook_entry(&self, week: LogbookWeek, id: u32) -> bool {
+        let mut logbook = self.0.logbook.lock().unwrap();
+        let Some(entries) = logbook.get_mut(&week) else {
+            return false;
+        };
+
+        let len = entries.len();
+        entries.retain(|e| !(e.id == id && e.deletable));
+        entries.len() != len
+    }
+}
+
+pub fn random_token(prefix: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let hash = RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{prefix}-{hash:016x}")
+}
@@ -1,26 +1,262 @@
+use std::path::PathBuf;
+
+use time::UtcOffset;
+
+use crate::core::{rate_limit::RateLimit, ttl::TtlPolicy};
+
 #[derive(clap::Parser)]
 pub struct AppConfig {
-    /// The url to the redis server
+    /// Where scraped pages, sessions and credentials are kept
+    #[clap(long, env, value_enum, default_value_t = CacheBackend::Redis)]
+    pub cache_backend: CacheBackend,
+
+    /// How many pages the memory cache backend keeps before evicting the least recently used.
+    /// Sessions, credentials, feed tokens and rate limit counts are kept until they expire.
+    #[clap(long, env, default_value_t = 10_000)]
+    pub cache_capacity: usize,
+
+    /// The UTC offset days are counted in by the `daily` cache ttl policies, e.g. `+07:00`.
+    /// It is fixed, so a timezone with daylight saving time is off by an hour half the year.
+    #[clap(long, env, default_value = "+07:00", value_parser = parse_utc_offset)]
+    pub cache_utc_offset: UtcOffset,
+
+    /// How long `/academic/absen` is cached. Either `<n>[s|m|h|d]`, `daily`,
+    /// `daily@<hour>` or `tanggal-penting:<during>/<otherwise>`
+    #[clap(long, env, default_value = "15m")]
+    pub cache_ttl_absen: TtlPolicy,
+
+    /// How long `/academic/frs` is cached, see `CACHE_TTL_ABSEN`
+    #[clap(long, env, default_value = "tanggal-penting:1m/6h")]
+    pub cache_ttl_frs: TtlPolicy,
+
+    /// How long `/academic/jadwal` is cached, see `CACHE_TTL_ABSEN`
+    #[clap(long, env, default_value = "daily")]
+    pub cache_ttl_jadwal: TtlPolicy,
+
+    /// How long `/academic/nilai` is cached, see `CACHE_TTL_ABSEN`
+    #[clap(long, env, default_value = "daily")]
+    pub cache_ttl_nilai: TtlPolicy,
+
+    /// How long `/academic/logbook` is cached, see `CACHE_TTL_ABSEN`
+    #[clap(long, env, default_value = "daily")]
+    pub cache_ttl_logbook: TtlPolicy,
+
+    /// How long an expired page is still served, while it is fetched again in the
+    /// background, in seconds. `0` always waits for Online MIS once the ttl is over
+    #[clap(long, env, default_value_t = 24 * 60 * 60)]
+    pub cache_stale_ttl: u64,
+
+    /// How long a request fetching a page makes the concurrent requests for the same page,
+    /// on every replica, wait for it before they fetch it too, in seconds. Defaults to
+    /// the longest fetching a page may take, five times `UPSTREAM_DEADLINE`, plus 5
+    /// seconds.
     #[clap(long, env)]
+    pub cache_lock_ttl: Option<u64>,
+
+    /// How many `/login` and `/refresh` requests a client ip may send. Either
+    /// `<requests>/<window>`, with the window written as `<n>[s|m|h|d]`, or `off`
+    #[clap(long, env, default_value = "20/1m")]
+    pub rate_limit_login_ip: RateLimit,
+
+    /// How many `/login` requests may log in to the same email, see `RATE_LIMIT_LOGIN_IP`.
+    /// Logins are counted per email since the nrp is only known once CAS accepted them.
+    #[clap(long, env, default_value = "5/5m")]
+    pub rate_limit_login_nrp: RateLimit,
+
+    /// How many `/academic/*` requests a client ip may send, see `RATE_LIMIT_LOGIN_IP`
+    #[clap(long, env, default_value = "300/1m")]
+    pub rate_limit_academic_ip: RateLimit,
+
+    /// How many `/academic/*` requests a student may send, see `RATE_LIMIT_LOGIN_IP`
+    #[clap(long, env, default_value = "30/1m")]
+    pub rate_limit_academic_nrp: RateLimit,
+
+    /// How many requests to the other routes a client ip may send, see
+    /// `RATE_LIMIT_LOGIN_IP`
+    #[clap(long, env, default_value = "600/1m")]
+    pub rate_limit_default_ip: RateLimit,
+
+    /// How many requests to the other routes a student may send, see `RATE_LIMIT_LOGIN_IP`
+    #[clap(long, env, default_value = "120/1m")]
+    pub rate_limit_default_nrp: RateLimit,
+
+    /// Take the client ip from the last `X-Forwarded-For` entry, only set it behind a
+    /// reverse proxy that appends to it
+    #[clap(long, env)]
+    pub rate_limit_forwarded_for: bool,
+
+    /// Also check that Online MIS and CAS can be reached in `/readyz`, at most once every
+    /// this many seconds. Only the cache is checked when unset
+    #[clap(long, env)]
+    pub readyz_upstream_ttl: Option<u64>,
+
+    /// The bearer token `/metrics` requires. Anyone can read the metrics when unset, the
+    /// reverse proxy must then keep `/metrics` from being reached from outside
+    #[clap(long, env)]
+    pub metrics_token: Option<String>,
+
+    /// How logs are written
+    #[clap(long, env, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+
+    /// How long in-flight requests are waited for once the server is asked to stop, in
+    /// seconds
+    #[clap(long, env, default_value_t = 25)]
+    pub shutdown_timeout: u64,
+
+    /// The OTLP/HTTP collector traces are exported to, e.g. `http://localhost:4318`.
+    /// Traces aren't exported when unset
+    #[clap(long, env)]
+    pub otlp_endpoint: Option<String>,
+
+    /// The service name the exported traces are reported under
+    #[clap(long, env, default_value = "online-mis-backend")]
+    pub otlp_service_name: String,
+
+    /// The salt nrps are hashed with before being exported, a random one is used when
+    /// unset so the same student can't be followed across restarts
+    #[clap(long, env)]
+    pub otlp_nrp_salt: Option<String>,
+
+    /// The url to the redis server
+    #[clap(long, env, default_value = "127.0.0.1:6379")]
     pub redis_address: String,
 
     /// The password to use for the redis server
-    #[clap(long, env)]
+    #[clap(long, env, default_value = "")]
     pub redis_password: String,
 
     /// The username to use for the redis server
-    #[clap(long, env)]
+    #[clap(long, env, default_value = "default")]
     pub redis_user: String,
 
     /// The proxy url to use for the request client
     #[clap(long, env)]
     pub proxy_url: Option<String>,
 
-    /// The proxy url to use for the request client
+    /// How long connecting to Online MIS and CAS may take, in seconds
+    #[clap(long, env, default_value_t = 3)]
+    pub upstream_connect_timeout: u64,
+
+    /// How long Online MIS and CAS may go without sending anything, in seconds
+    #[clap(long, env, default_value_t = 8)]
+    pub upstream_read_timeout: u64,
+
+    /// How long a request to Online MIS or CAS may take in total, retries included, in
+    /// seconds. At worst a request to the API makes five of them, the page, the three of
+    /// logging in again and the page again, which must fit in the 60 seconds it may take.
+    #[clap(long, env, default_value_t = 10)]
+    pub upstream_deadline: u64,
+
+    /// How many requests to Online MIS and CAS may be in flight at once
+    #[clap(long, env, default_value_t = 64)]
+    pub upstream_concurrency: usize,
+
+    /// How many requests to Online MIS of a single nrp may be in flight at once
+    #[clap(long, env, default_value_t = 4)]
+    pub upstream_concurrency_per_nrp: usize,
+
+    /// How many times a `GET` to Online MIS is retried when it times out or gets a 502,
+    /// 503 or 504
+    #[clap(long, env, default_value_t = 2)]
+    pub upstream_retries: u32,
+
+    /// How many failed requests in a row make Online MIS considered down, failing
+    /// requests right away with a 503 or serving them from the cache
+    #[clap(long, env, default_value_t = 5)]
+    pub upstream_breaker_threshold: u32,
+
+    /// How long Online MIS is considered down before a request is let through again,
+    /// in seconds
+    #[clap(long, env, default_value_t = 30)]
+    pub upstream_breaker_cooldown: u64,
+
+    /// The address the http server binds to
     #[clap(long, env)]
     pub server_address: Option<String>,
 
-    /// The proxy url to use for the request client
+    /// The port the http server listens on
     #[clap(long, env)]
     pub server_port: Option<u16>,
+
+    /// The base url of the Online MIS portal
+    #[clap(long, env, default_value = "https://online.mis.pens.ac.id")]
+    pub mis_base_url: String,
+
+    /// The base url of the CAS server used to log in to Online MIS
+    #[clap(long, env, default_value = "https://login.pens.ac.id/cas")]
+    pub cas_base_url: String,
+
+    /// A toml or json file overriding the css selectors used to scrape Online MIS
+    #[clap(long, env)]
+    pub selector_file: Option<PathBuf>,
+
+    /// Comma separated base64 encoded 32 bytes keys used to encrypt the `SESSION_ID` cookie.
+    /// The first key encrypts new cookies, the others only decrypt cookies issued before a
+    /// rotation. A random key is used when unset, logging everyone out on restart
+    #[clap(long, env, value_delimiter = ',')]
+    pub session_keys: Vec<String>,
+
+    /// How long a session is kept after it was last used, in seconds
+    #[clap(long, env, default_value_t = 30 * 24 * 60 * 60)]
+    pub session_ttl: u64,
+
+    /// How long an access token returned by `/login` and `/refresh` is valid, in seconds
+    #[clap(long, env, default_value_t = 15 * 60)]
+    pub access_token_ttl: u64,
+
+    /// The `SameSite` attribute of the session cookies
+    #[clap(long, env, value_enum, default_value_t = CookieSameSite::Lax)]
+    pub cookie_same_site: CookieSameSite,
+
+    /// Only send the session cookies over https
+    #[clap(long, env)]
+    pub cookie_secure: bool,
+
+    /// A base64 encoded 32 bytes key used to encrypt the credentials of users who opt in
+    /// to automatic re-login, the feature is disabled when unset
+    #[clap(long, env)]
+    pub credential_key: Option<String>,
+
+    /// How long the credentials of users who opt in to automatic re-login are kept, in seconds
+    #[clap(long, env, default_value_t = 7 * 24 * 60 * 60)]
+    pub credential_ttl: u64,
+}
+
+fn parse_utc_offset(s: &str) -> Result<UtcOffset, String> {
+    let invalid = || format!("Invalid UTC offset '{s}', expected e.g. '+07:00'");
+
+    let (sign, rest) = match s.split_at_checked(1) {
+        Some(("+", rest)) => (1, rest),
+        Some(("-", rest)) => (-1, rest),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i8 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i8 = minutes.parse().map_err(|_| invalid())?;
+
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).map_err(|_| invalid())
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum CacheBackend {
+    /// A redis server, shared by every replica and kept across restarts
+    Redis,
+    /// An in process cache, lost on restart
+    Memory,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Pretty,
+    /// A json object per line, with the fields of every span the event is in
+    Json,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
 }
@@ -1,13 +1,13 @@
 use aide::OperationIo;
 use axum::{
-    http::{HeaderName, StatusCode},
+    http::{header, HeaderName, StatusCode},
     response::{AppendHeaders, IntoResponse, Response},
     Json,
 };
 use schemars::JsonSchema;
 use serde::Serialize;
 
-use super::error::Error;
+use super::{error::Error, request_id};
 
 #[derive(Debug, Serialize, OperationIo, JsonSchema)]
 #[serde(rename_all = "camelCase")]
@@ -17,6 +17,12 @@ pub struct ValidationErrorCause {
     pub received_value: String,
 }
 
+#[derive(Debug, Serialize, OperationIo, JsonSchema, Default)]
+pub struct LayoutChangeCause {
+    pub endpoint: String,
+    pub missing: Vec<String>,
+}
+
 #[derive(Debug, Serialize, OperationIo, JsonSchema)]
 pub struct SuccessApiResponseBody<T>
 where
@@ -27,16 +33,33 @@ where
 }
 
 #[derive(Debug, Serialize, OperationIo, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorApiResponseBody {
     pub success: bool,
     pub message: String,
+    /// The `X-Request-Id` of the request, to find it in the logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, OperationIo, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorValidationApiResponseBody {
     pub success: bool,
     pub message: String,
     pub cause: Vec<ValidationErrorCause>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, OperationIo, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorLayoutChangedApiResponseBody {
+    pub success: bool,
+    pub message: String,
+    pub cause: LayoutChangeCause,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, OperationIo, JsonSchema)]
@@ -55,6 +78,7 @@ where
 pub type SuccessApiResponse<T> = ApiResponse<SuccessApiResponseBody<T>>;
 pub type ErrorApiResponse = ApiResponse<ErrorApiResponseBody>;
 pub type ErrorValidationApiResponse = ApiResponse<ErrorValidationApiResponseBody>;
+pub type ErrorLayoutChangedApiResponse = ApiResponse<ErrorLayoutChangedApiResponseBody>;
 
 pub trait ApiResponseTrait<T>
 where
@@ -119,14 +143,22 @@ impl From<Error> for ErrorApiResponse {
     fn from(value: Error) -> Self {
         let code = value.status_code();
         let msg = value.to_string();
+        let headers = match value {
+            Error::TooManyRequests { retry_after } => Some(AppendHeaders(vec![(
+                header::RETRY_AFTER,
+                retry_after.to_string(),
+            )])),
+            _ => None,
+        };
 
         Self {
             body: ErrorApiResponseBody {
                 success: false,
                 message: msg,
+                request_id: request_id::current(),
             },
             code,
-            headers: None,
+            headers,
         }
     }
 }
@@ -142,6 +174,7 @@ impl From<Error> for ErrorValidationApiResponse {
                     success: false,
                     message: msg,
                     cause: e,
+                    request_id: request_id::current(),
                 },
                 code,
                 headers: None,
@@ -152,6 +185,38 @@ impl From<Error> for ErrorValidationApiResponse {
                     success: false,
                     message: msg,
                     cause: vec![],
+                    request_id: request_id::current(),
+                },
+                code,
+                headers: None,
+            },
+        }
+    }
+}
+
+impl From<Error> for ErrorLayoutChangedApiResponse {
+    fn from(value: Error) -> Self {
+        let code = value.status_code();
+        let msg = value.to_string();
+
+        match value {
+            Error::LayoutChanged(cause) => Self {
+                body: ErrorLayoutChangedApiResponseBody {
+                    success: false,
+                    message: msg,
+                    cause,
+                    request_id: request_id::current(),
+                },
+                code,
+                headers: None,
+            },
+            // It should be impossible to reach this point
+            _ => Self {
+                body: ErrorLayoutChangedApiResponseBody {
+                    success: false,
+                    message: msg,
+                    cause: LayoutChangeCause::default(),
+                    request_id: request_id::current(),
                 },
                 code,
                 headers: None,
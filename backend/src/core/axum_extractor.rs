@@ -4,19 +4,23 @@ use anyhow::anyhow;
 use axum::{
     extract::{
         rejection::{FormRejection, JsonRejection},
-        FromRequest, FromRequestParts, Query, Request,
+        FromRef, FromRequest, FromRequestParts, Query, Request,
     },
-    http::request::Parts,
+    http::{header, request::Parts},
     Form, Json,
 };
 use axum_extra::extract::CookieJar;
-use base64::Engine;
 use jsonschema::{error::ValidationErrorKind, ValidationError};
 use schemars::{schema_for, JsonSchema};
-use scraper::{Html, Selector};
+use scraper::Html;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use super::error::Error;
+use super::{
+    error::Error,
+    rate_limit::NrpRateLimit,
+    selector::{SelectorEntry, SelectorRegistry},
+    session::{SessionStore, TokenKind},
+};
 
 #[derive(FromRequestParts, OperationIo)]
 #[from_request(via(axum::extract::Path), rejection(Error))]
@@ -107,8 +111,50 @@ where
     }
 }
 
-#[derive(OperationIo, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+pub struct CacheQuery {
+    /// Fetches the page from Online MIS instead of serving it from the cache
+    #[serde(default)]
+    pub fresh: bool,
+}
+
+/// Whether a request asked for fresh data, with `?fresh=true` or `Cache-Control: no-cache`
+#[derive(OperationIo)]
+#[aide(input_with = "axum::extract::Query<CacheQuery>")]
+pub struct CacheControl {
+    pub fresh: bool,
+}
+
+impl<S> FromRequestParts<S> for CacheControl
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        let Query(query) = Query::<CacheQuery>::from_request_parts(parts, state).await?;
+        let no_cache = parts
+            .headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"));
+
+        Ok(Self {
+            fresh: query.fresh || no_cache,
+        })
+    }
+}
+
+/// The session an `Authorization: Bearer` access token or a `SESSION_ID` cookie points to
+#[derive(OperationIo)]
 pub struct ValidatedCookieJar {
+    /// The opaque token the session is stored under
+    pub token: String,
+    /// The public id of the session
+    pub id: String,
+    /// The Online MIS `PHPSESSID`
     pub session_id: String,
     pub nrp: String,
 }
@@ -116,35 +162,73 @@ pub struct ValidatedCookieJar {
 impl<S> FromRequestParts<S> for ValidatedCookieJar
 where
     S: Send + Sync,
+    SessionStore: FromRef<S>,
 {
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
-        let jar = CookieJar::from_request_parts(parts, state)
-            .await
-            .map_err(|_| anyhow!("Failed to extract cookies"))?;
-
-        let base64 = jar
-            .get("SESSION_ID")
-            .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?
-            .value();
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|value| (value.trim().to_owned(), TokenKind::Access));
+
+        let (value, kind) = match bearer {
+            Some(bearer) => bearer,
+            None => {
+                let jar = CookieJar::from_request_parts(parts, state)
+                    .await
+                    .map_err(|_| anyhow!("Failed to extract cookies"))?;
+
+                let value = jar
+                    .get("SESSION_ID")
+                    .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?
+                    .value()
+                    .to_owned();
+
+                (value, TokenKind::Cookie)
+            }
+        };
 
-        if base64.is_empty() {
+        if value.is_empty() {
             return Err(Error::Unauthorized("Unauthorized".to_string()));
         }
 
-        let decoded_base64 = base64::engine::general_purpose::STANDARD
-            .decode(base64)
-            .map_err(|e| anyhow!(e.to_string()))
-            .and_then(|e| String::from_utf8(e).map_err(|e| anyhow!(e.to_string())))?;
-
-        let cookie_value: ValidatedCookieJar =
-            serde_json::from_str(&decoded_base64).map_err(|e| anyhow!(e.to_string()))?;
+        let session = SessionStore::from_ref(state)
+            .resolve(&value, kind)
+            .await
+            .map_err(|e| match e {
+                Error::Redis(_) => e,
+                e => {
+                    tracing::debug!("Rejected {kind:?} token: {e}");
+                    Error::Unauthorized("Unauthorized".to_string())
+                }
+            })?;
+
+        if let Some((_, record)) = &session {
+            if let Some(limit) = parts.extensions.get::<NrpRateLimit>() {
+                limit.check(&record.nrp).await?;
+            }
+        }
 
-        Ok(cookie_value)
+        match session {
+            Some((token, record)) => Ok(Self {
+                token,
+                id: record.id,
+                session_id: record.session_id,
+                nrp: record.nrp,
+            }),
+            None => Err(Error::Unauthorized("Unauthorized".to_string())),
+        }
     }
 }
 
+pub const SELECTORS: &[SelectorEntry] = &[(
+    "mis.session_expired",
+    "option[value='ociexecute(): ORA-00936: missing expression']",
+)];
+
 fn validate_schema<T>(value: &T) -> Result<()>
 where
     T: DeserializeOwned + JsonSchema + Serialize,
@@ -156,9 +240,9 @@ where
     )
     .map_err(|_| anyhow!("Error creating validator"))?;
 
-    if !validator.is_valid(&instance) {
+    if !validator.is_valid(instance) {
         let err = validator
-            .iter_errors(&instance)
+            .iter_errors(instance)
             .map(|e| ValidationErrorCause {
                 field: e.instance_path.to_string(),
                 message: validation_error_message(&e),
@@ -256,20 +340,113 @@ fn validation_error_message(err: &ValidationError) -> String {
     }
 }
 
-pub fn validate_html(html: &Html) -> Result<()> {
-    let selector = Selector::parse("option[value='ociexecute(): ORA-00936: missing expression']")
-        .map_err(|_| anyhow!("Error parsing selector"))?;
+pub fn validate_html(html: &Html, selectors: &SelectorRegistry) -> Result<()> {
+    let selector = selectors.get("mis.session_expired")?;
 
-    match html.select(&selector).next() {
-        Some(s) => match s.text().next() {
-            Some(e) => {
-                tracing::debug!("Got error from online mis: {}", e);
-                return Err(Error::Unauthorized("Unauthorized".to_string()));
-            }
-            _ => (),
-        },
-        _ => (),
-    };
+    if let Some(e) = html.select(selector).next().and_then(|s| s.text().next()) {
+        tracing::debug!("Got error from online mis: {}", e);
+        return Err(Error::Unauthorized("Unauthorized".to_string()));
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::FromRequestParts,
+        http::{request::Parts, Request},
+    };
+    use std::sync::Arc;
+
+    use base64::Engine;
+    use scraper::Html;
+
+    use super::{validate_html, ValidatedCookieJar};
+    use crate::core::{
+        cache::MemoryCache,
+        crypto::Keyring,
+        error::Error,
+        helper::test_helper::{fixture, selectors},
+        session::{SessionStore, TokenKind},
+    };
+
+    fn store(keyring: Keyring, access_ttl: u64) -> SessionStore {
+        SessionStore::new(Arc::new(MemoryCache::new(16)), keyring, 60, access_ttl)
+    }
+
+    async fn assert_rejected(store: &SessionStore, mut parts: Parts) {
+        let err = ValidatedCookieJar::from_request_parts(&mut parts, store)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Unauthorized(_)));
+    }
+
+    fn cookie(value: &str) -> Parts {
+        let request = Request::builder().header("Cookie", format!("SESSION_ID={value}"));
+        request.body(()).unwrap().into_parts().0
+    }
+
+    fn bearer(value: &str) -> Parts {
+        let request = Request::builder().header("Authorization", format!("Bearer {value}"));
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_and_forged_cookies() {
+        let keyring = Keyring::generate();
+        let store = store(keyring.clone(), 60);
+        let sealed = store.seal("token", TokenKind::Cookie).unwrap();
+
+        let mut tampered = base64::engine::general_purpose::STANDARD
+            .decode(&sealed)
+            .unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = base64::engine::general_purpose::STANDARD.encode(tampered);
+
+        let forged = base64::engine::general_purpose::STANDARD
+            .encode(r#"{"session_id":"phpsessid","nrp":"3122600001"}"#);
+
+        let other_key = self::store(Keyring::generate(), 60)
+            .seal("token", TokenKind::Cookie)
+            .unwrap();
+
+        for value in [tampered, forged, other_key, String::new()] {
+            assert_rejected(&store, cookie(&value)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn only_accepts_tokens_where_their_kind_is() {
+        let store = store(Keyring::generate(), 60);
+
+        let access = store.seal("token", TokenKind::Access).unwrap();
+        let refresh = store.seal("token", TokenKind::Refresh).unwrap();
+        let cookie_value = store.seal("token", TokenKind::Cookie).unwrap();
+
+        assert_rejected(&store, cookie(&access)).await;
+        assert_rejected(&store, bearer(&refresh)).await;
+        assert_rejected(&store, bearer(&cookie_value)).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_access_tokens() {
+        let store = store(Keyring::generate(), 0);
+        let access = store.seal("token", TokenKind::Access).unwrap();
+
+        assert_rejected(&store, bearer(&access)).await;
+    }
+
+    #[test]
+    fn rejects_pages_of_an_expired_session() {
+        let expired = Html::parse_document(&fixture("session_expired.html"));
+        let err = validate_html(&expired, &selectors()).unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+
+        for page in ["absen.html", "FRS_mbkm.html", "nilai_sem.html"] {
+            let html = Html::parse_document(&fixture(page));
+            assert!(validate_html(&html, &selectors()).is_ok(), "{page}");
+        }
+    }
+}
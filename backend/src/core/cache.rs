@@ -0,0 +1,584 @@
+use std::{collections::HashMap, num::NonZeroUsize, sync::Mutex, time::SystemTime};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bb8::Pool;
+use bb8_redis::RedisConnectionManager;
+use lru::LruCache;
+use redis::AsyncCommands;
+
+use super::{
+    metrics::{MEMORY_CACHE_GAUGE, REDIS_POOL_GAUGE},
+    result::Result,
+};
+
+/// When a cached value expires
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    Never,
+    /// After the given number of seconds
+    In(u64),
+    /// At the given unix timestamp, in seconds
+    At(u64),
+}
+
+impl Expiry {
+    /// The unix timestamp the value expires at, in seconds
+    pub fn timestamp(self) -> Option<u64> {
+        match self {
+            Self::Never => None,
+            Self::In(secs) => Some(now() + secs),
+            Self::At(at) => Some(at),
+        }
+    }
+
+    /// The same expiry, `secs` seconds later
+    pub fn extended(self, secs: u64) -> Self {
+        match self {
+            Self::Never => Self::Never,
+            Self::In(s) => Self::In(s + secs),
+            Self::At(at) => Self::At(at + secs),
+        }
+    }
+}
+
+/// A key value store for the scraped pages, sessions and credentials
+#[async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    async fn set(&self, key: &str, value: String, expiry: Expiry) -> Result<()>;
+
+    /// Sets `key` unless it already has a value, returning whether it was set
+    async fn set_nx(&self, key: &str, value: String, expiry: Expiry) -> Result<bool>;
+
+    async fn del(&self, key: &str) -> Result<()>;
+
+    /// Deletes `key` only while it holds `value`, returning whether it did
+    async fn del_if(&self, key: &str, value: &str) -> Result<bool>;
+
+    /// Increments the counter at `key`, starting it at 1 with `expiry` when there is none,
+    /// and returns its new value
+    async fn incr(&self, key: &str, expiry: Expiry) -> Result<u64>;
+
+    /// Lists the keys matching `pattern`, where `*` matches any number of characters
+    async fn keys(&self, pattern: &str) -> Result<Vec<String>>;
+
+    /// Checks that the cache can be reached
+    async fn ping(&self) -> Result<()>;
+
+    /// Updates the gauges describing the cache, before they are scraped
+    fn record_metrics(&self) {}
+}
+
+/// The prefixes of the keys holding scraped Online MIS pages, each followed by the nrp
+/// they were scraped for, e.g. `absen:{nrp}:{year}:{semester}`
+pub const PAGE_PREFIXES: [&str; 5] = ["absen", "frs", "jadwal", "nilai", "logbook"];
+
+/// Forgets the pages cached for `nrp`.
+///
+/// Only the keys under [`PAGE_PREFIXES`] are deleted, the sessions, credentials, feed
+/// tokens and rate limit counts of the user are kept.
+pub async fn invalidate_pages(cache: &dyn Cache, nrp: &str) -> Result<()> {
+    for prefix in PAGE_PREFIXES {
+        for key in cache.keys(&format!("{prefix}:{nrp}:*")).await? {
+            cache.del(&key).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub struct RedisCache {
+    pool: Pool<RedisConnectionManager>,
+}
+
+impl RedisCache {
+    pub fn new(pool: Pool<RedisConnectionManager>) -> Self {
+        Self { pool }
+    }
+
+    async fn conn(&self) -> Result<bb8::PooledConnection<'_, RedisConnectionManager>> {
+        Ok(self.pool.get().await.map_err(|e| anyhow!(e.to_string()))?)
+    }
+}
+
+fn set_cmd(key: &str, value: String, expiry: Expiry) -> redis::Cmd {
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(key).arg(value);
+    match expiry {
+        Expiry::Never => {}
+        Expiry::In(secs) => {
+            cmd.arg("EX").arg(secs.max(1));
+        }
+        Expiry::At(at) => {
+            cmd.arg("EXAT").arg(at);
+        }
+    }
+    cmd
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.conn().await?.get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: String, expiry: Expiry) -> Result<()> {
+        Ok(set_cmd(key, value, expiry)
+            .query_async(&mut *self.conn().await?)
+            .await?)
+    }
+
+    async fn set_nx(&self, key: &str, value: String, expiry: Expiry) -> Result<bool> {
+        let set: Option<String> = set_cmd(key, value, expiry)
+            .arg("NX")
+            .query_async(&mut *self.conn().await?)
+            .await?;
+
+        Ok(set.is_some())
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        Ok(self.conn().await?.del(key).await?)
+    }
+
+    async fn del_if(&self, key: &str, value: &str) -> Result<bool> {
+        // Compared and deleted in a single step, so the key can't change in between
+        let script = redis::Script::new(
+            r#"
+            if redis.call("GET", KEYS[1]) == ARGV[1] then
+                return redis.call("DEL", KEYS[1])
+            end
+            return 0
+            "#,
+        );
+        let deleted: u64 = script
+            .key(key)
+            .arg(value)
+            .invoke_async(&mut *self.conn().await?)
+            .await?;
+
+        Ok(deleted == 1)
+    }
+
+    async fn incr(&self, key: &str, expiry: Expiry) -> Result<u64> {
+        // INCR keeps the expiry of the counter created by the SET
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .add_command(set_cmd(key, "0".to_owned(), expiry).arg("NX").to_owned())
+            .ignore()
+            .incr(key, 1)
+            .query_async(&mut *self.conn().await?)
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let mut conn = self.conn().await?;
+        let mut iter = conn.scan_match::<&str, String>(pattern).await?;
+
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    fn record_metrics(&self) {
+        let state = self.pool.state();
+        metrics::gauge!(REDIS_POOL_GAUGE, "state" => "idle").set(state.idle_connections);
+        metrics::gauge!(REDIS_POOL_GAUGE, "state" => "active")
+            .set(state.connections - state.idle_connections);
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let _: String = redis::cmd("PING")
+            .query_async(&mut *self.conn().await?)
+            .await?;
+
+        Ok(())
+    }
+}
+
+struct Entry {
+    value: String,
+    expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// Where the memory cache keeps a kind of values
+trait Entries {
+    /// Reads an entry, marking it as used
+    fn get(&mut self, key: &str) -> Option<&mut Entry>;
+    fn put(&mut self, key: String, entry: Entry);
+    fn pop(&mut self, key: &str);
+}
+
+impl Entries for LruCache<String, Entry> {
+    fn get(&mut self, key: &str) -> Option<&mut Entry> {
+        self.get_mut(key)
+    }
+
+    fn put(&mut self, key: String, entry: Entry) {
+        LruCache::put(self, key, entry);
+    }
+
+    fn pop(&mut self, key: &str) {
+        LruCache::pop(self, key);
+    }
+}
+
+impl Entries for HashMap<String, Entry> {
+    fn get(&mut self, key: &str) -> Option<&mut Entry> {
+        self.get_mut(key)
+    }
+
+    fn put(&mut self, key: String, entry: Entry) {
+        self.insert(key, entry);
+    }
+
+    fn pop(&mut self, key: &str) {
+        self.remove(key);
+    }
+}
+
+/// How often the values that are never evicted are swept for expired ones, in seconds
+const SWEEP_INTERVAL: u64 = 60;
+
+/// The values that are never evicted, only dropped once expired
+#[derive(Default)]
+struct Kept {
+    entries: HashMap<String, Entry>,
+    swept_at: u64,
+}
+
+impl Kept {
+    fn sweep(&mut self, now: u64) {
+        if self.swept_at + SWEEP_INTERVAL > now {
+            return;
+        }
+        self.entries.retain(|_, entry| !entry.expired(now));
+        self.swept_at = now;
+    }
+}
+
+/// Whether `key` holds a scraped page, see [`PAGE_PREFIXES`]
+fn is_page(key: &str) -> bool {
+    key.split_once(':')
+        .is_some_and(|(prefix, _)| PAGE_PREFIXES.contains(&prefix))
+}
+
+/// Keeps at most `capacity` pages in process, evicting the least recently used one first.
+///
+/// Everything else, the sessions, credentials, feed tokens, locks and rate limit counts,
+/// is never evicted, so that page traffic can't log users out or reset their limits. It
+/// is dropped once expired. Expired values are dropped when they are next read.
+pub struct MemoryCache {
+    pages: Mutex<LruCache<String, Entry>>,
+    kept: Mutex<Kept>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pages: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+            kept: Mutex::new(Kept::default()),
+        }
+    }
+
+    fn pages(&self) -> std::sync::MutexGuard<'_, LruCache<String, Entry>> {
+        // A panic while holding the lock can't leave the cache half written
+        self.pages.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn kept(&self) -> std::sync::MutexGuard<'_, Kept> {
+        self.kept.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `f` with where the value of `key` is kept
+    fn with_entries<R>(&self, key: &str, f: impl FnOnce(&mut dyn Entries) -> R) -> R {
+        if is_page(key) {
+            return f(&mut *self.pages());
+        }
+
+        let mut kept = self.kept();
+        kept.sweep(now());
+        f(&mut kept.entries)
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.with_entries(key, |entries| {
+            match entries.get(key) {
+                Some(entry) if entry.expired(now()) => {}
+                Some(entry) => return Some(entry.value.clone()),
+                None => return None,
+            }
+            entries.pop(key);
+            None
+        }))
+    }
+
+    async fn set(&self, key: &str, value: String, expiry: Expiry) -> Result<()> {
+        let expires_at = expiry.timestamp();
+        self.with_entries(key, |entries| {
+            entries.put(key.to_owned(), Entry { value, expires_at })
+        });
+
+        Ok(())
+    }
+
+    async fn set_nx(&self, key: &str, value: String, expiry: Expiry) -> Result<bool> {
+        Ok(self.with_entries(key, |entries| {
+            if entries.get(key).is_some_and(|entry| !entry.expired(now())) {
+                return false;
+            }
+
+            let expires_at = expiry.timestamp();
+            entries.put(key.to_owned(), Entry { value, expires_at });
+            true
+        }))
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.with_entries(key, |entries| entries.pop(key));
+
+        Ok(())
+    }
+
+    async fn del_if(&self, key: &str, value: &str) -> Result<bool> {
+        Ok(self.with_entries(key, |entries| {
+            let holds = entries
+                .get(key)
+                .is_some_and(|entry| !entry.expired(now()) && entry.value == value);
+            if holds {
+                entries.pop(key);
+            }
+            holds
+        }))
+    }
+
+    async fn incr(&self, key: &str, expiry: Expiry) -> Result<u64> {
+        let now = now();
+
+        Ok(self.with_entries(key, |entries| match entries.get(key) {
+            Some(entry) if !entry.expired(now) => {
+                let count = entry.value.parse::<u64>().unwrap_or_default() + 1;
+                entry.value = count.to_string();
+                count
+            }
+            _ => {
+                let expires_at = expiry.timestamp();
+                entries.put(
+                    key.to_owned(),
+                    Entry {
+                        value: "1".to_owned(),
+                        expires_at,
+                    },
+                );
+                1
+            }
+        }))
+    }
+
+    async fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let now = now();
+        let matching = |(key, entry): (&String, &Entry)| {
+            (!entry.expired(now) && glob_match(pattern, key)).then(|| key.clone())
+        };
+
+        let mut keys: Vec<String> = self.pages().iter().filter_map(matching).collect();
+        keys.extend(self.kept().entries.iter().filter_map(matching));
+        Ok(keys)
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn record_metrics(&self) {
+        let entries = self.pages().len() + self.kept().entries.len();
+        metrics::gauge!(MEMORY_CACHE_GAUGE).set(entries as f64);
+    }
+}
+
+/// Matches `text` against a pattern where `*` matches any number of characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, invalidate_pages, Cache, Expiry, MemoryCache};
+
+    #[tokio::test]
+    async fn memory_cache_expires_values() {
+        let cache = MemoryCache::new(8);
+        cache
+            .set("fresh", "1".into(), Expiry::In(60))
+            .await
+            .unwrap();
+        cache.set("stale", "2".into(), Expiry::In(0)).await.unwrap();
+        cache.set("past", "3".into(), Expiry::At(1)).await.unwrap();
+        cache.set("kept", "4".into(), Expiry::Never).await.unwrap();
+
+        assert_eq!(cache.get("fresh").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("stale").await.unwrap(), None);
+        assert_eq!(cache.get("past").await.unwrap(), None);
+        assert_eq!(cache.get("kept").await.unwrap().as_deref(), Some("4"));
+
+        let mut keys = cache.keys("*").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["fresh", "kept"]);
+
+        assert_eq!(cache.incr("count", Expiry::In(60)).await.unwrap(), 1);
+        assert_eq!(cache.incr("count", Expiry::In(60)).await.unwrap(), 2);
+        cache.set("count", "5".into(), Expiry::In(0)).await.unwrap();
+        assert_eq!(cache.incr("count", Expiry::In(60)).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn memory_cache_evicts_least_recently_used_pages() {
+        let cache = MemoryCache::new(2);
+        cache
+            .set("absen:a", "1".into(), Expiry::Never)
+            .await
+            .unwrap();
+        cache
+            .set("absen:b", "2".into(), Expiry::Never)
+            .await
+            .unwrap();
+        cache.get("absen:a").await.unwrap();
+        cache
+            .set("absen:c", "3".into(), Expiry::Never)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.get("absen:b").await.unwrap(), None);
+        assert!(cache.get("absen:a").await.unwrap().is_some());
+        assert!(cache.get("absen:c").await.unwrap().is_some());
+
+        cache.del("absen:a").await.unwrap();
+        assert_eq!(cache.get("absen:a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn memory_cache_never_evicts_what_isnt_a_page() {
+        let cache = MemoryCache::new(2);
+        cache
+            .set("session:token", "1".into(), Expiry::Never)
+            .await
+            .unwrap();
+        cache
+            .set("feed:token", "3122600001".into(), Expiry::Never)
+            .await
+            .unwrap();
+        cache
+            .incr("ratelimit:login:ip:10.0.0.1:60", Expiry::In(60))
+            .await
+            .unwrap();
+        for page in ["frs:a", "frs:b", "frs:c", "nilai:a", "jadwal:a"] {
+            cache.set(page, "page".into(), Expiry::Never).await.unwrap();
+        }
+
+        assert!(cache.get("session:token").await.unwrap().is_some());
+        assert!(cache.get("feed:token").await.unwrap().is_some());
+        assert_eq!(
+            cache
+                .incr("ratelimit:login:ip:10.0.0.1:60", Expiry::In(60))
+                .await
+                .unwrap(),
+            2
+        );
+        let mut pages = cache.keys("*:a").await.unwrap();
+        pages.sort();
+        assert_eq!(pages, ["jadwal:a", "nilai:a"]);
+    }
+
+    #[tokio::test]
+    async fn invalidates_only_the_pages_of_the_user() {
+        let cache = MemoryCache::new(32);
+        let pages = [
+            "absen:3122600001:2024:2",
+            "frs:3122600001:2024:2",
+            "jadwal:3122600001:2024:2",
+            "nilai:3122600001:2024:1",
+            "logbook:3122600001:2024:2:5",
+        ];
+        let kept = [
+            "absen:3122600002:2024:2",
+            "session:token",
+            "sessions:3122600001:id",
+            "credential:3122600001",
+            "feeds:3122600001",
+            "feeds:3122600001:jadwal",
+            "ratelimit:academic:nrp:3122600001:60",
+            "lock:absen:3122600001:2024:2",
+        ];
+        for key in pages.iter().chain(&kept) {
+            cache.set(key, "1".into(), Expiry::Never).await.unwrap();
+        }
+
+        invalidate_pages(&cache, "3122600001").await.unwrap();
+
+        for key in pages {
+            assert_eq!(cache.get(key).await.unwrap(), None, "{key}");
+        }
+        for key in kept {
+            assert!(cache.get(key).await.unwrap().is_some(), "{key}");
+        }
+    }
+
+    #[test]
+    fn matches_glob_patterns() {
+        assert!(glob_match("*3122600001*", "absen:3122600001:2024:1"));
+        assert!(glob_match(
+            "sessions:3122600001:*",
+            "sessions:3122600001:abc"
+        ));
+        assert!(glob_match("frs:*:2024", "frs:3122600001:2024"));
+        assert!(glob_match("exact", "exact"));
+
+        assert!(!glob_match("*3122600001*", "absen:3122600002:2024:1"));
+        assert!(!glob_match("sessions:1:*", "session:1:abc"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+}
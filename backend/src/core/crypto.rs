@@ -0,0 +1,151 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, bail};
+use base64::Engine;
+use std::sync::Arc;
+
+use super::result::Result;
+
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM cipher for values the backend keeps on behalf of a user.
+///
+/// Ciphertexts are the random nonce followed by the sealed value, base64 encoded.
+#[derive(Clone)]
+pub struct Cipher(Aes256Gcm);
+
+impl Cipher {
+    /// Builds the cipher from a base64 encoded 32 bytes key
+    pub fn from_base64_key(key: &str) -> anyhow::Result<Self> {
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(key.trim())
+            .map_err(|e| anyhow!("Invalid base64 key: {e}"))?;
+        if key.len() != 32 {
+            bail!("Key must be 32 bytes long, got {} bytes", key.len());
+        }
+
+        Ok(Self(
+            Aes256Gcm::new_from_slice(&key).map_err(|e| anyhow!(e.to_string()))?,
+        ))
+    }
+
+    /// Builds the cipher from a random key that only lives as long as the process
+    pub fn generate() -> Self {
+        Self(Aes256Gcm::new(&Aes256Gcm::generate_key(OsRng)))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Failed to encrypt value"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(base64::engine::general_purpose::STANDARD.encode(sealed))
+    }
+
+    pub fn decrypt(&self, sealed: &str) -> Result<Vec<u8>> {
+        let sealed = base64::engine::general_purpose::STANDARD
+            .decode(sealed)
+            .map_err(|e| anyhow!(e.to_string()))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("Encrypted value is too short").into());
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        Ok(self
+            .0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt value"))?)
+    }
+}
+
+/// The current key and the keys it replaced.
+///
+/// Values are sealed with the first key and opened with any of them, so a key is rotated
+/// by putting the new one first and dropping the old one once nothing sealed with it is
+/// still in use.
+#[derive(Clone)]
+pub struct Keyring(Arc<Vec<Cipher>>);
+
+impl Keyring {
+    pub fn from_base64_keys(keys: &[String]) -> anyhow::Result<Self> {
+        if keys.is_empty() {
+            bail!("At least one key is required");
+        }
+
+        let ciphers = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                Cipher::from_base64_key(key).map_err(|e| anyhow!("Invalid key #{}: {e}", i + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self(Arc::new(ciphers)))
+    }
+
+    pub fn generate() -> Self {
+        Self(Arc::new(vec![Cipher::generate()]))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        self.0[0].encrypt(plaintext)
+    }
+
+    pub fn decrypt(&self, sealed: &str) -> Result<Vec<u8>> {
+        self.0
+            .iter()
+            .find_map(|cipher| cipher.decrypt(sealed).ok())
+            .ok_or_else(|| anyhow!("Failed to decrypt value with any key").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+
+    use super::{Cipher, Keyring};
+
+    fn key(byte: u8) -> String {
+        base64::engine::general_purpose::STANDARD.encode([byte; 32])
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypts() {
+        let cipher = Cipher::from_base64_key(&key(7)).unwrap();
+        let sealed = cipher.encrypt(b"secret").unwrap();
+
+        assert_ne!(sealed, cipher.encrypt(b"secret").unwrap());
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn rejects_values_sealed_with_another_key() {
+        let sealed = Cipher::from_base64_key(&key(7))
+            .unwrap()
+            .encrypt(b"secret")
+            .unwrap();
+
+        assert!(Cipher::from_base64_key(&key(8))
+            .unwrap()
+            .decrypt(&sealed)
+            .is_err());
+        assert!(Cipher::from_base64_key("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn opens_values_sealed_with_a_rotated_key() {
+        let old = Keyring::from_base64_keys(&[key(1)]).unwrap();
+        let rotated = Keyring::from_base64_keys(&[key(2), key(1)]).unwrap();
+        let sealed = old.encrypt(b"session").unwrap();
+
+        assert_eq!(rotated.decrypt(&sealed).unwrap(), b"session");
+        assert!(old.decrypt(&rotated.encrypt(b"session").unwrap()).is_err());
+        assert!(Keyring::from_base64_keys(&[]).is_err());
+    }
+}
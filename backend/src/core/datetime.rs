@@ -0,0 +1,180 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use time::{macros::format_description, Date, Month, Time};
+
+const BULAN: [&str; 12] = [
+    "januari",
+    "februari",
+    "maret",
+    "april",
+    "mei",
+    "juni",
+    "juli",
+    "agustus",
+    "september",
+    "oktober",
+    "november",
+    "desember",
+];
+
+/// Parses a month name such as `Februari`, or its first three letters such as `Feb`
+fn parse_bulan(s: &str) -> Option<Month> {
+    let s = s.trim().to_lowercase();
+    let position = match s.len() {
+        3 => BULAN.iter().position(|bulan| bulan.starts_with(&s)),
+        _ => BULAN.iter().position(|bulan| *bulan == s),
+    }?;
+
+    Month::try_from(position as u8 + 1).ok()
+}
+
+/// Parses an Online MIS date such as `3 Februari 2025`
+pub fn parse_tanggal(s: &str) -> Option<Date> {
+    let mut parts = s.split_whitespace();
+    let day = parts.next()?.parse().ok()?;
+    let month = parse_bulan(parts.next()?)?;
+    let year = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+/// Parses a range of Online MIS dates such as `3 Februari 2025 sd 30 Juni 2025`
+pub fn parse_tanggal_range(s: &str) -> Option<(Date, Date)> {
+    let (from, to) = s.split_once(" sd ")?;
+    Some((parse_tanggal(from)?, parse_tanggal(to)?))
+}
+
+/// Parses an ISO 8601 date such as `2025-02-03`
+pub fn parse_iso_date(s: &str) -> Option<Date> {
+    let mut parts = s.trim().split('-');
+    let (Some(year), Some(month), Some(day), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+
+    let month = Month::try_from(month.parse::<u8>().ok()?).ok()?;
+    Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()
+}
+
+/// Parses a time such as `08:00`, or `08.00`
+pub fn parse_jam(s: &str) -> Option<Time> {
+    let (hour, minute) = s.trim().split_once([':', '.'])?;
+    if hour.is_empty() || hour.len() > 2 || minute.len() != 2 {
+        return None;
+    }
+
+    Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+/// Parses a range of times such as `08:00-10:30` or `08:00 - 10:30`
+pub fn parse_jam_range(s: &str) -> Option<(Time, Time)> {
+    let (from, to) = s.split_once('-')?;
+    Some((parse_jam(from)?, parse_jam(to)?))
+}
+
+/// Formats a time as ISO 8601 without seconds, e.g. `08:00`
+pub fn iso_time(time: Time) -> String {
+    time.format(format_description!("[hour]:[minute]"))
+        .unwrap_or_default()
+}
+
+/// A range of dates as ISO 8601, e.g. `2025-02-03`
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct IsoDateRange {
+    pub from: String,
+    pub to: String,
+}
+
+impl IsoDateRange {
+    pub fn new((from, to): (Date, Date)) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+}
+
+/// A range of times as ISO 8601 without seconds, e.g. `08:00`
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct IsoTimeRange {
+    pub start: String,
+    pub end: String,
+}
+
+impl IsoTimeRange {
+    pub fn new((start, end): (Time, Time)) -> Self {
+        Self {
+            start: iso_time(start),
+            end: iso_time(end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, time};
+
+    use super::{
+        iso_time, parse_iso_date, parse_jam, parse_jam_range, parse_tanggal, parse_tanggal_range,
+    };
+
+    #[test]
+    fn parses_indonesian_dates() {
+        assert_eq!(
+            parse_tanggal("3 Februari 2025"),
+            Some(date!(2025 - 02 - 03))
+        );
+        assert_eq!(
+            parse_tanggal("17 AGUSTUS 2024"),
+            Some(date!(2024 - 08 - 17))
+        );
+        assert_eq!(parse_tanggal("1 Des 2024"), Some(date!(2024 - 12 - 01)));
+        assert_eq!(
+            parse_tanggal("31 Januari 2025"),
+            Some(date!(2025 - 01 - 31))
+        );
+        for invalid in [
+            "",
+            "3 Februari",
+            "30 Februari 2025",
+            "3 Feburari 2025",
+            "3 Mei 2025 x",
+        ] {
+            assert_eq!(parse_tanggal(invalid), None, "{invalid}");
+        }
+
+        assert_eq!(
+            parse_tanggal_range("3 Februari 2025 sd 30 Juni 2025"),
+            Some((date!(2025 - 02 - 03), date!(2025 - 06 - 30)))
+        );
+        assert_eq!(parse_iso_date("2025-03-04"), Some(date!(2025 - 03 - 04)));
+        assert_eq!(parse_iso_date("2025-02-30"), None);
+        assert_eq!(parse_iso_date("4 Maret 2025"), None);
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_jam("08:00"), Some(time!(08:00)));
+        assert_eq!(parse_jam(" 8.30 "), Some(time!(08:30)));
+        assert_eq!(parse_jam("24:00"), None);
+        assert_eq!(parse_jam("0800"), None);
+
+        assert_eq!(
+            parse_jam_range("08:00-10:30"),
+            Some((time!(08:00), time!(10:30)))
+        );
+        assert_eq!(
+            parse_jam_range("13:00 - 16:20"),
+            Some((time!(13:00), time!(16:20)))
+        );
+        assert_eq!(parse_jam_range(""), None);
+        assert_eq!(iso_time(time!(08:05)), "08:05");
+    }
+}
@@ -1,6 +1,8 @@
-use crate::core::api_response::{ErrorApiResponse, ErrorValidationApiResponse};
+use crate::core::api_response::{
+    ErrorApiResponse, ErrorLayoutChangedApiResponse, ErrorValidationApiResponse,
+};
 
-use super::api_response::{ApiResponse, ValidationErrorCause};
+use super::api_response::{ApiResponse, LayoutChangeCause, ValidationErrorCause};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -32,6 +34,18 @@ pub enum Error {
     #[error("Validation Error")]
     Validation(Vec<ValidationErrorCause>),
 
+    #[error("Online MIS layout changed")]
+    LayoutChanged(LayoutChangeCause),
+
+    #[error("Online MIS is unavailable, try again later")]
+    UpstreamUnavailable,
+
+    #[error("Not ready: {}", _0)]
+    NotReady(String),
+
+    #[error("Too many requests, try again in {retry_after} seconds")]
+    TooManyRequests { retry_after: u64 },
+
     #[error("Invalid form request")]
     AxumFormRejection(#[from] axum::extract::rejection::FormRejection),
 
@@ -46,12 +60,26 @@ pub enum Error {
 }
 
 impl Error {
+    /// Whether Online MIS could not be reached at all, rather than answered with an error
+    pub fn is_upstream_down(&self) -> bool {
+        match self {
+            Self::UpstreamUnavailable => true,
+            Self::Reqwest(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
     pub fn status_code(&self) -> StatusCode {
         match self {
             Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             Self::BadRequest(_) => StatusCode::BAD_GATEWAY,
             Self::Forbidden => StatusCode::FORBIDDEN,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::LayoutChanged(_) => StatusCode::FAILED_DEPENDENCY,
+            Self::UpstreamUnavailable | Self::NotReady(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Reqwest(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            Self::Reqwest(e) if e.is_connect() => StatusCode::SERVICE_UNAVAILABLE,
             Self::Anyhow(_) | Self::Reqwest(_) | Self::Redis(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
@@ -66,12 +94,24 @@ impl Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        tracing::error!("{:?}", self);
+        let status = self.status_code();
+        // A changed layout means an extractor needs fixing, even though it isn't answered
+        // as a server error
+        if status.is_server_error() || matches!(self, Self::LayoutChanged(_)) {
+            tracing::error!(status = status.as_u16(), error = ?self, "{self}");
+        } else {
+            tracing::debug!(status = status.as_u16(), "{self}");
+        }
+
         match self {
             Self::Validation(_) => {
                 let body: ErrorValidationApiResponse = ApiResponse::from(self);
                 body.into_response()
             }
+            Self::LayoutChanged(_) => {
+                let body: ErrorLayoutChangedApiResponse = ApiResponse::from(self);
+                body.into_response()
+            }
             _ => {
                 let body: ErrorApiResponse = ApiResponse::from(self);
                 body.into_response()
@@ -5,7 +5,10 @@ use axum::Json;
 use schemars::JsonSchema;
 use serde::Serialize;
 
-use super::api_response::{ApiResponseTrait, ErrorApiResponseBody, ErrorValidationApiResponseBody};
+use super::api_response::{
+    ApiResponseTrait, ErrorApiResponseBody, ErrorLayoutChangedApiResponseBody,
+    ErrorValidationApiResponseBody, LayoutChangeCause,
+};
 
 pub fn generate_response<H, I, O, T, S, R>(
     _handler: H,
@@ -25,32 +28,85 @@ where
     let owned_tag = tag.to_owned();
 
     move |op: TransformOperation| {
-        let doc = op
-            .response_with::<200, Json<<O::Inner as ApiResponseTrait<R>>::Body>, _>(|res| {
+        let doc =
+            op.response_with::<200, Json<<O::Inner as ApiResponseTrait<R>>::Body>, _>(|res| {
                 res.example(<<O::Inner as ApiResponseTrait<R>>::Body>::default())
                     .description("Success Response")
+            });
+
+        with_errors(doc, &owned_tag, with_security)
+    }
+}
+
+/// Documents a handler answering with an iCalendar file rather than JSON
+pub fn generate_ics_response(
+    tag: &str,
+    with_security: bool,
+) -> impl FnOnce(TransformOperation) -> TransformOperation {
+    let owned_tag = tag.to_owned();
+
+    move |op: TransformOperation| with_errors(op, &owned_tag, with_security)
+}
+
+fn with_errors<'t>(
+    op: TransformOperation<'t>,
+    tag: &str,
+    with_security: bool,
+) -> TransformOperation<'t> {
+    let doc = op
+        .response_with::<404, Json<ErrorApiResponseBody>, _>(|res| {
+            res.example(ErrorApiResponseBody {
+                success: false,
+                message: "Not Found".to_owned(),
+                request_id: Some("5f0c7a2e3c1d4b9e".to_owned()),
             })
-            .response_with::<404, Json<ErrorApiResponseBody>, _>(|res| {
-                res.example(ErrorApiResponseBody {
-                    success: false,
-                    message: "Not Found".to_owned(),
-                })
-                .description("Not Found")
+            .description("Not Found")
+        })
+        .response_with::<400, Json<ErrorValidationApiResponseBody>, _>(|res| {
+            res.example(ErrorValidationApiResponseBody {
+                success: false,
+                message: "Validation Error".to_owned(),
+                cause: vec![],
+                request_id: Some("5f0c7a2e3c1d4b9e".to_owned()),
             })
-            .response_with::<400, Json<ErrorValidationApiResponseBody>, _>(|res| {
-                res.example(ErrorValidationApiResponseBody {
-                    success: false,
-                    message: "Validation Error".to_owned(),
-                    cause: vec![],
-                })
-                .description("Validation Error")
+            .description("Validation Error")
+        })
+        .response_with::<424, Json<ErrorLayoutChangedApiResponseBody>, _>(|res| {
+            res.example(ErrorLayoutChangedApiResponseBody {
+                success: false,
+                message: "Online MIS layout changed".to_owned(),
+                cause: LayoutChangeCause {
+                    endpoint: "frs".to_owned(),
+                    missing: vec!["frs.dosen".to_owned()],
+                },
+                request_id: Some("5f0c7a2e3c1d4b9e".to_owned()),
             })
-            .tag(&owned_tag);
-
-        if with_security {
-            return doc.security_requirement("CookieSessionId");
-        }
+            .description("Online MIS returned a page the backend can no longer scrape")
+        })
+        .response_with::<429, Json<ErrorApiResponseBody>, _>(|res| {
+            res.example(ErrorApiResponseBody {
+                success: false,
+                message: "Too many requests, try again in 42 seconds".to_owned(),
+                request_id: Some("5f0c7a2e3c1d4b9e".to_owned()),
+            })
+            .description("Rate limited, retry after the `Retry-After` header seconds")
+        })
+        .response_with::<503, Json<ErrorApiResponseBody>, _>(|res| {
+            res.example(ErrorApiResponseBody {
+                success: false,
+                message: "Online MIS is unavailable, try again later".to_owned(),
+                request_id: Some("5f0c7a2e3c1d4b9e".to_owned()),
+            })
+            .description("Online MIS is down and nothing is cached")
+        })
+        .tag(tag);
 
-        doc
+    if with_security {
+        // Each requirement is an alternative, either one authenticates the request
+        return doc
+            .security_requirement("CookieSessionId")
+            .security_requirement("BearerToken");
     }
+
+    doc
 }
@@ -1,80 +1,654 @@
-use super::result::Result;
+use std::{sync::Arc, time::SystemTime};
+
+use super::{error::Error, result::Result};
 use anyhow::anyhow;
-use redis::{AsyncCommands, RedisError, ToRedisArgs};
-use serde::{de::DeserializeOwned, Serialize};
+use async_trait::async_trait;
+use axum::{
+    http::{header, HeaderName},
+    response::AppendHeaders,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use time::OffsetDateTime;
 
-use super::{error::Error, helper};
+use super::{
+    cache::Cache,
+    helper,
+    metrics::{CACHE_COUNTER, EXTRACTOR_FAILED_COUNTER},
+    request_id,
+    single_flight::SingleFlight,
+    ttl::{Ttl, TtlWindows},
+    upstream::Upstream,
+};
 
-pub struct RedisHandler<'a, K>
-where
-    K: ToRedisArgs + Clone + Send + Sync,
-{
-    pub redis_pool: bb8::PooledConnection<'a, bb8_redis::RedisConnectionManager>,
-    pub key: K,
+/// The value stored in the cache, remembering when it was fetched and until when it is
+/// fresh. It is kept for `Ttl::stale` more seconds to be served while being refreshed.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    fetched_at: u64,
+    fresh_until: Option<u64>,
+    data: T,
 }
 
-impl<K> RedisHandler<'_, K>
-where
-    K: ToRedisArgs + Clone + Send + Sync,
-{
-    pub async fn get_value(&mut self) -> Result<redis::Value> {
-        let redis_val = self
-            .redis_pool
-            .get::<K, redis::Value>(self.key.clone())
-            .await
-            .unwrap_or_else(|_| redis::Value::Nil);
+impl<T> CacheEntry<T> {
+    fn status(&self, now: u64) -> CacheStatus {
+        let age = now.saturating_sub(self.fetched_at);
+        match self.fresh_until {
+            Some(fresh_until) if fresh_until <= now => CacheStatus::Stale {
+                age,
+                stale: now - fresh_until,
+            },
+            fresh_until => CacheStatus::Hit {
+                age,
+                ttl: fresh_until.map_or(u64::MAX, |at| at - now),
+            },
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub struct RedisHandler {
+    pub cache: Arc<dyn Cache>,
+    /// Coalesces the requests fetching the same key
+    pub flights: Arc<SingleFlight>,
+    pub key: String,
+    pub ttl: Ttl,
+    /// Skips the cached value, e.g. when the user asks for fresh data
+    pub fresh: bool,
+}
 
-        Ok(redis_val)
+impl RedisHandler {
+    /// The endpoint the key caches, e.g. `absen`
+    fn endpoint(&self) -> &str {
+        self.key.split(':').next().unwrap_or_default()
     }
 
-    pub async fn set_value<V>(&mut self, value: V) -> Result<()>
+    #[tracing::instrument(name = "cache_get", skip_all, fields(endpoint = self.endpoint()))]
+    async fn get_value<T>(&self) -> Result<Option<CacheEntry<T>>>
     where
-        V: Serialize,
+        T: DeserializeOwned,
     {
-        let v = serde_json::to_string(&value).map_err(|_| anyhow!("Error serializing absen"))?;
-        Ok(helper::cache_helper::cache_set(self.key.clone(), v, &mut *self.redis_pool).await?)
+        let Some(value) = self.cache.get(&self.key).await.unwrap_or_default() else {
+            return Ok(None);
+        };
+
+        // Values cached in an older format are fetched again
+        Ok(serde_json::from_str(&value).ok())
     }
+
+    #[tracing::instrument(name = "cache_set", skip_all, fields(endpoint = self.endpoint()))]
+    async fn set_value<V>(&self, value: &V) -> Result<u64>
+    where
+        V: Serialize + TtlWindows,
+    {
+        let now = now();
+        let expiry = self
+            .ttl
+            .expiry(OffsetDateTime::now_utc(), &value.ttl_windows());
+        let fresh_until = expiry.timestamp();
+
+        let entry = CacheEntry {
+            fetched_at: now,
+            fresh_until,
+            data: value,
+        };
+        let v = serde_json::to_string(&entry)
+            .map_err(|e| anyhow!("Error serializing the value cached at '{}': {e}", self.key))?;
+        self.cache
+            .set(&self.key, v, expiry.extended(self.ttl.stale))
+            .await?;
+
+        Ok(fresh_until.map_or(u64::MAX, |at| at.saturating_sub(now)))
+    }
+}
+
+/// Logs the user in again once Online MIS rejected their session, for the refreshes done
+/// in the background after the request that started them was answered
+#[async_trait]
+pub trait Relogin: Send + Sync {
+    /// Returns the session to retry with, or `None` when the user can't be logged in again
+    async fn relogin(&self, expired: &str) -> Result<Option<String>>;
 }
 
-pub struct HttpHandler<'a> {
+pub struct HttpHandler {
     pub url: String,
-    pub session_id: &'a str,
-    pub client: &'a reqwest::Client,
+    pub session_id: String,
+    pub nrp: String,
+    pub upstream: Arc<Upstream>,
+    /// Used by the background refresh of a stale value when the session expired
+    pub relogin: Option<Arc<dyn Relogin>>,
+}
+
+impl HttpHandler {
+    async fn get(&self) -> Result<String> {
+        helper::http_helper::http_get_request(
+            &self.upstream,
+            &self.nrp,
+            self.url.clone(),
+            &self.session_id,
+        )
+        .await
+    }
+}
+
+/// How a response was answered, sent back in the `Cache-Status` and `Age` headers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheStatus {
+    /// Served from the cache while fresh, for `ttl` more seconds
+    Hit { age: u64, ttl: u64 },
+    /// Served from the cache `stale` seconds after it expired, while being refreshed
+    Stale { age: u64, stale: u64 },
+    /// Fetched from Online MIS since nothing usable was cached
+    Miss { ttl: u64 },
+    /// Fetched from Online MIS by a concurrent request this one waited for
+    Collapsed { age: u64, ttl: u64 },
+    /// Fetched from Online MIS since the request asked for fresh data
+    Bypass { ttl: u64 },
+}
+
+impl CacheStatus {
+    /// The `result` label of the cache counter
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheStatus::Hit { .. } => "hit",
+            CacheStatus::Stale { .. } => "stale",
+            CacheStatus::Miss { .. } => "miss",
+            CacheStatus::Collapsed { .. } => "collapsed",
+            CacheStatus::Bypass { .. } => "bypass",
+        }
+    }
+
+    /// How many seconds ago the page was fetched from Online MIS
+    pub fn age(self) -> u64 {
+        match self {
+            CacheStatus::Hit { age, .. }
+            | CacheStatus::Stale { age, .. }
+            | CacheStatus::Collapsed { age, .. } => age,
+            CacheStatus::Miss { .. } | CacheStatus::Bypass { .. } => 0,
+        }
+    }
+
+    pub fn headers(self) -> AppendHeaders<Vec<(HeaderName, String)>> {
+        let (status, age) = match self {
+            CacheStatus::Hit { age, ttl } => (format!("online-mis; hit; ttl={ttl}"), age),
+            CacheStatus::Stale { age, stale } => {
+                (format!("online-mis; hit; ttl=-{stale}; fwd=stale"), age)
+            }
+            CacheStatus::Miss { ttl } => (format!("online-mis; fwd=miss; stored; ttl={ttl}"), 0),
+            CacheStatus::Collapsed { age, ttl } => {
+                (format!("online-mis; fwd=miss; collapsed; ttl={ttl}"), age)
+            }
+            CacheStatus::Bypass { ttl } => {
+                (format!("online-mis; fwd=request; stored; ttl={ttl}"), 0)
+            }
+        };
+
+        AppendHeaders(vec![
+            (HeaderName::from_static("cache-status"), status),
+            (header::AGE, age.to_string()),
+        ])
+    }
+}
+
+/// Data scraped from Online MIS, and where it came from
+pub struct Cached<T> {
+    pub data: T,
+    pub status: CacheStatus,
+}
+
+/// Fetches the page, then caches the extracted value and returns it with its ttl
+async fn fetch<T, F>(
+    redis_handler: &RedisHandler,
+    http_handler: &HttpHandler,
+    html_extractor: &F,
+) -> Result<(T, u64)>
+where
+    T: Serialize + TtlWindows,
+    F: Fn(String) -> Result<T>,
+{
+    let body = http_handler.get().await?;
+    let span = tracing::info_span!("html_extractor", endpoint = redis_handler.endpoint());
+    let data = span.in_scope(|| html_extractor(body)).inspect_err(|_| {
+        metrics::counter!(EXTRACTOR_FAILED_COUNTER, "endpoint" => redis_handler.endpoint().to_owned())
+            .increment(1);
+    })?;
+    let ttl = redis_handler.set_value(&data).await?;
+
+    Ok((data, ttl))
 }
 
-pub async fn online_mis_handler<T, F, K>(
-    mut redis_handler: RedisHandler<'_, K>,
-    http_handler: HttpHandler<'_>,
+/// Fetches a stale value again, logging in again first when Online MIS rejected the
+/// session
+async fn refresh<T, F>(
+    redis_handler: &RedisHandler,
+    mut http_handler: HttpHandler,
+    html_extractor: &F,
+) -> Result<()>
+where
+    T: Serialize + TtlWindows,
+    F: Fn(String) -> Result<T>,
+{
+    let res = match fetch(redis_handler, &http_handler, html_extractor).await {
+        Err(Error::Unauthorized(message)) => {
+            let Some(relogin) = http_handler.relogin.clone() else {
+                return Err(Error::Unauthorized(message));
+            };
+            match relogin.relogin(&http_handler.session_id).await? {
+                Some(session_id) => {
+                    http_handler.session_id = session_id;
+                    fetch(redis_handler, &http_handler, html_extractor).await
+                }
+                None => Err(Error::Unauthorized(message)),
+            }
+        }
+        res => res,
+    };
+
+    res.map(|_| ())
+}
+
+/// Serves the cached value instead of failing while Online MIS is down
+fn or_cached<T>(err: Error, cached: Option<CacheEntry<T>>) -> Result<Cached<T>> {
+    match (err, cached) {
+        (err, Some(entry)) if err.is_upstream_down() => Ok(Cached {
+            status: entry.status(now()),
+            data: entry.data,
+        }),
+        (err, _) => Err(err),
+    }
+}
+
+/// Serves the cached value while it is fresh, otherwise fetches the page and caches the
+/// extracted value.
+///
+/// A value that expired less than `Ttl::stale` seconds ago is still served right away,
+/// and refreshed in the background. Concurrent requests for the same key share a single
+/// fetch. While Online MIS is down, requests asking for fresh data get the cached value.
+#[tracing::instrument(skip_all, fields(endpoint = redis_handler.endpoint(), cache))]
+pub async fn online_mis_handler<T, F>(
+    redis_handler: RedisHandler,
+    http_handler: HttpHandler,
     html_extractor: F,
-) -> Result<T>
+) -> Result<Cached<T>>
 where
-    T: Serialize + DeserializeOwned,
-    F: FnOnce(String) -> Result<T>,
-    K: ToRedisArgs + Clone + Send + Sync,
+    T: Serialize + DeserializeOwned + TtlWindows + Send + Sync + 'static,
+    F: Fn(String) -> Result<T> + Send + Sync + 'static,
 {
-    match redis_handler.get_value().await? {
-        redis::Value::Nil => {
-            let body = helper::http_helper::http_get_request(
-                &http_handler.client,
-                http_handler.url,
-                http_handler.session_id,
+    let endpoint = redis_handler.endpoint().to_owned();
+    let res = serve(redis_handler, http_handler, html_extractor).await;
+
+    let result = match &res {
+        Ok(cached) => cached.status.name(),
+        Err(_) => "error",
+    };
+    tracing::Span::current().record("cache", result);
+    metrics::counter!(CACHE_COUNTER, "endpoint" => endpoint, "result" => result).increment(1);
+
+    res
+}
+
+async fn serve<T, F>(
+    redis_handler: RedisHandler,
+    http_handler: HttpHandler,
+    html_extractor: F,
+) -> Result<Cached<T>>
+where
+    T: Serialize + DeserializeOwned + TtlWindows + Send + Sync + 'static,
+    F: Fn(String) -> Result<T> + Send + Sync + 'static,
+{
+    let cached = redis_handler.get_value::<T>().await?;
+    let (cached, fallback) = match redis_handler.fresh {
+        true => (None, cached),
+        false => (cached, None),
+    };
+
+    let Some(entry) = cached else {
+        let started_at = now();
+        let flights = redis_handler.flights.clone();
+        let Some(flight) = flights.lead_or_wait(&redis_handler.key).await? else {
+            // Only use what the request we waited for fetched, it may have failed
+            let fetched = redis_handler
+                .get_value::<T>()
+                .await?
+                .filter(|entry| entry.fetched_at >= started_at);
+            if let Some(entry) = fetched {
+                let now = now();
+                return Ok(Cached {
+                    data: entry.data,
+                    status: CacheStatus::Collapsed {
+                        age: now.saturating_sub(entry.fetched_at),
+                        ttl: entry
+                            .fresh_until
+                            .map_or(u64::MAX, |at| at.saturating_sub(now)),
+                    },
+                });
+            }
+
+            return match fetch(&redis_handler, &http_handler, &html_extractor).await {
+                Ok((data, ttl)) => Ok(Cached {
+                    data,
+                    status: CacheStatus::Miss { ttl },
+                }),
+                Err(e) => or_cached(e, fallback),
+            };
+        };
+
+        let res = fetch(&redis_handler, &http_handler, &html_extractor).await;
+        flight.release().await?;
+        let (data, ttl) = match res {
+            Ok(res) => res,
+            Err(e) => return or_cached(e, fallback),
+        };
+
+        let status = match redis_handler.fresh {
+            true => CacheStatus::Bypass { ttl },
+            false => CacheStatus::Miss { ttl },
+        };
+
+        return Ok(Cached { data, status });
+    };
+
+    let status = entry.status(now());
+    if let CacheStatus::Stale { .. } = status {
+        // Still logged under the request that found the value stale
+        tokio::spawn(request_id::inherit(async move {
+            let res = async {
+                // Another request is already refreshing it
+                let Some(flight) = redis_handler.flights.try_lead(&redis_handler.key).await? else {
+                    return Ok(());
+                };
+
+                let res = refresh(&redis_handler, http_handler, &html_extractor).await;
+                flight.release().await?;
+                res
+            };
+            match res.await {
+                Err(e) if e.is_upstream_down() => {
+                    tracing::debug!("Failed to refresh '{}': {e}", redis_handler.key);
+                }
+                Err(e) => tracing::warn!("Failed to refresh '{}': {e}", redis_handler.key),
+                Ok(()) => {}
+            }
+        }));
+    }
+
+    Ok(Cached {
+        data: entry.data,
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use axum::http::HeaderMap;
+    use serde::{Deserialize, Serialize};
+    use time::UtcOffset;
+
+    use super::{online_mis_handler, CacheEntry, CacheStatus, HttpHandler, RedisHandler, Relogin};
+    use crate::core::{
+        cache::{Cache, Expiry, MemoryCache},
+        error::Error,
+        result::Result,
+        single_flight::SingleFlight,
+        ttl::{Ttl, TtlPolicy, TtlWindows},
+        upstream::{Upstream, UpstreamConfig},
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Page(String);
+
+    impl TtlWindows for Page {}
+
+    struct Server {
+        url: String,
+        cache: Arc<MemoryCache>,
+        flights: Arc<SingleFlight>,
+        upstream: Arc<Upstream>,
+    }
+
+    /// Serves the number of requests it got so far, a bit slowly, or `expired` to the
+    /// `expired` session
+    async fn server() -> Server {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::get(move |headers: HeaderMap| async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if headers[axum::http::header::COOKIE] == "PHPSESSID=expired;" {
+                    return "expired".to_owned();
+                }
+                (hits.fetch_add(1, Ordering::SeqCst) + 1).to_string()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let cache = Arc::new(MemoryCache::new(8));
+        Server {
+            url,
+            flights: Arc::new(SingleFlight::new(cache.clone(), 5)),
+            cache,
+            upstream: Arc::new(Upstream::new(
+                reqwest::Client::new(),
+                UpstreamConfig {
+                    concurrency: 4,
+                    concurrency_per_user: 4,
+                    retries: 0,
+                    breaker_threshold: 1,
+                    breaker_cooldown: Duration::from_secs(60),
+                    connect_timeout: Duration::from_secs(1),
+                    read_timeout: Duration::from_secs(1),
+                    deadline: Duration::from_secs(5),
+                },
+            )),
+        }
+    }
+
+    fn extract(body: String) -> Result<Page> {
+        match body.as_str() {
+            "expired" => Err(Error::Unauthorized("Unauthorized".to_owned())),
+            _ => Ok(Page(body)),
+        }
+    }
+
+    fn redis_handler(server: &Server, fresh: bool) -> RedisHandler {
+        RedisHandler {
+            cache: server.cache.clone(),
+            flights: server.flights.clone(),
+            key: "page".to_owned(),
+            ttl: Ttl {
+                policy: TtlPolicy::Fixed(60),
+                utc_offset: UtcOffset::UTC,
+                stale: 60,
+            },
+            fresh,
+        }
+    }
+
+    fn http_handler(server: &Server, session_id: &str) -> HttpHandler {
+        HttpHandler {
+            url: server.url.clone(),
+            session_id: session_id.to_owned(),
+            nrp: "3122600001".to_owned(),
+            upstream: server.upstream.clone(),
+            relogin: None,
+        }
+    }
+
+    async fn try_fetch(server: &Server, fresh: bool) -> Result<(Page, CacheStatus)> {
+        let cached = online_mis_handler(
+            redis_handler(server, fresh),
+            http_handler(server, "phpsessid"),
+            extract,
+        )
+        .await?;
+        Ok((cached.data, cached.status))
+    }
+
+    async fn cache_stale_page(server: &Server) {
+        let entry = CacheEntry {
+            fetched_at: Expiry::In(0).timestamp().unwrap() - 90,
+            fresh_until: Some(Expiry::In(0).timestamp().unwrap() - 30),
+            data: Page("old".into()),
+        };
+        server
+            .cache
+            .set(
+                "page",
+                serde_json::to_string(&entry).unwrap(),
+                Expiry::In(30),
             )
-            .await?;
+            .await
+            .unwrap();
+    }
+
+    /// Logs in to the `renewed` session
+    #[derive(Default)]
+    struct CountingRelogin(AtomicUsize);
+
+    #[async_trait]
+    impl Relogin for CountingRelogin {
+        async fn relogin(&self, expired: &str) -> Result<Option<String>> {
+            assert_eq!(expired, "expired");
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Some("renewed".to_owned()))
+        }
+    }
+
+    async fn fetch(server: &Server, fresh: bool) -> (Page, CacheStatus) {
+        try_fetch(server, fresh).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_fresh_values_from_the_cache() {
+        let server = server().await;
+
+        let (page, status) = fetch(&server, false).await;
+        assert_eq!(page, Page("1".into()));
+        assert_eq!(status, CacheStatus::Miss { ttl: 60 });
+
+        let (page, status) = fetch(&server, false).await;
+        assert_eq!(page, Page("1".into()));
+        assert!(matches!(status, CacheStatus::Hit { .. }));
+
+        let (page, status) = fetch(&server, true).await;
+        assert_eq!(page, Page("2".into()));
+        assert_eq!(status, CacheStatus::Bypass { ttl: 60 });
+    }
 
-            let data = html_extractor(body)?;
+    #[tokio::test]
+    async fn serves_stale_values_while_refreshing_them() {
+        let server = server().await;
+        cache_stale_page(&server).await;
 
-            redis_handler.set_value(&data).await?;
+        let (page, status) = fetch(&server, false).await;
+        assert_eq!(page, Page("old".into()));
+        assert!(matches!(
+            status,
+            CacheStatus::Stale {
+                age: 90..,
+                stale: 30..
+            }
+        ));
 
-            Ok(data)
+        for _ in 0..50 {
+            let (page, status) = fetch(&server, false).await;
+            if page != Page("old".into()) {
+                assert!(matches!(status, CacheStatus::Hit { .. }));
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
-        redis::Value::ServerError(e) => {
-            return Err(Error::Redis(RedisError::from(e)));
+        panic!("the stale value was never refreshed");
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_to_refresh_stale_values() {
+        let server = server().await;
+        cache_stale_page(&server).await;
+
+        let relogin = Arc::new(CountingRelogin::default());
+        let mut http_handler = http_handler(&server, "expired");
+        http_handler.relogin = Some(relogin.clone());
+        let cached = online_mis_handler(redis_handler(&server, false), http_handler, extract)
+            .await
+            .unwrap();
+        assert_eq!(cached.data, Page("old".into()));
+
+        for _ in 0..50 {
+            let value = server.cache.get("page").await.unwrap().unwrap();
+            let entry: CacheEntry<Page> = serde_json::from_str(&value).unwrap();
+            if entry.data != Page("old".into()) {
+                assert_eq!(entry.data, Page("1".into()));
+                assert_eq!(relogin.0.load(Ordering::SeqCst), 1);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
-        value => {
-            let redis_str: String = redis::FromRedisValue::from_redis_value(&value)
-                .map_err(|e| anyhow!(e.to_string()))?;
-            Ok(serde_json::from_str::<T>(&redis_str)
-                .map_err(|_| anyhow!("Deserialization error"))?)
+        panic!("the stale value was never refreshed");
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_misses() {
+        let server = server().await;
+
+        let (first, second, third) = tokio::join!(
+            fetch(&server, false),
+            fetch(&server, false),
+            fetch(&server, false)
+        );
+
+        let mut statuses = Vec::new();
+        for (page, status) in [first, second, third] {
+            assert_eq!(page, Page("1".into()));
+            statuses.push(status);
         }
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|s| matches!(s, CacheStatus::Collapsed { .. }))
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_cached_values_while_online_mis_is_down() {
+        let mut server = server().await;
+        let (page, _) = fetch(&server, false).await;
+        assert_eq!(page, Page("1".into()));
+
+        // Nothing listens there anymore
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        server.url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        // The first failure opens the circuit breaker
+        for _ in 0..2 {
+            let (page, status) = fetch(&server, true).await;
+            assert_eq!(page, Page("1".into()));
+            assert!(matches!(status, CacheStatus::Hit { .. }));
+        }
+
+        server.cache.del("page").await.unwrap();
+        assert!(matches!(
+            try_fetch(&server, false).await,
+            Err(Error::UpstreamUnavailable)
+        ));
     }
 }
@@ -1,56 +1,43 @@
 pub mod http_helper {
-    use crate::core::result::Result;
-    use reqwest::Client;
+    use crate::{
+        core::{result::Result, upstream::Upstream},
+        telemetry::hash_nrp,
+    };
 
+    #[tracing::instrument(
+        skip_all,
+        fields(url = url.split('?').next(), nrp = hash_nrp(nrp))
+    )]
     pub async fn http_get_request(
-        client: &Client,
+        upstream: &Upstream,
+        nrp: &str,
         url: String,
         session_id: &str,
     ) -> Result<String> {
-        Ok(client
+        let request = upstream
+            .client()
             .get(url)
-            .header("Cookie", format!("PHPSESSID={};", session_id))
-            .send()
-            .await?
-            .text()
-            .await?)
+            .header("Cookie", format!("PHPSESSID={};", session_id));
+
+        upstream.text(nrp, request).await
     }
 }
 
-pub mod cache_helper {
-    use crate::core::result::Result;
-    use anyhow::anyhow;
-    use bb8::{Pool, PooledConnection};
-    use bb8_redis::RedisConnectionManager;
-    use redis::{aio::ConnectionLike, ToRedisArgs};
-    use std::time::SystemTime;
-
-    pub async fn cache_set<K, V>(key: K, value: V, conn: &mut impl ConnectionLike) -> Result<()>
-    where
-        K: ToRedisArgs,
-        V: ToRedisArgs,
-    {
-        let midnight = {
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+#[cfg(test)]
+pub mod test_helper {
+    use std::collections::HashMap;
 
-            now - (now % (24 * 60 * 60)) + (17 * 60 * 60)
-        };
+    use crate::core::selector::SelectorRegistry;
 
-        Ok(redis::cmd("SET")
-            .arg(key)
-            .arg(value)
-            .arg("EXAT")
-            .arg(midnight)
-            .query_async(conn)
-            .await?)
+    /// Reads a hand-written Online MIS page from `fixtures/mis`
+    pub fn fixture(name: &str) -> String {
+        let path = format!("{}/fixtures/mis/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("reading {path}: {e}"))
     }
 
-    pub async fn get_conn(
-        pool: &Pool<RedisConnectionManager>,
-    ) -> Result<PooledConnection<'_, RedisConnectionManager>> {
-        Ok(pool.get().await.map_err(|e| anyhow!(e.to_string()))?)
+    /// Builds the selector registry with every default selector and no overrides
+    pub fn selectors() -> SelectorRegistry {
+        SelectorRegistry::new(crate::http::default_selectors(), HashMap::new())
+            .expect("default selectors should compile")
     }
 }
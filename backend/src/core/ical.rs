@@ -0,0 +1,326 @@
+use aide::{
+    generate::GenContext,
+    openapi::{MediaType, Operation, Response as OpenApiResponse},
+    OperationOutput,
+};
+use axum::{
+    http::{header, HeaderName},
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use time::{
+    macros::{format_description, offset},
+    Date, Duration, OffsetDateTime, PrimitiveDateTime, Time,
+};
+
+/// The timezone of every date in the calendars, Online MIS only shows times in WIB
+pub const TIMEZONE: &str = "Asia/Jakarta";
+
+/// Lines longer than this many octets are folded
+const MAX_LINE: usize = 75;
+
+/// A recurring or one-off event, in `TIMEZONE`
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Stays the same between renders, so calendars update the event instead of adding
+    /// another
+    pub uid: String,
+    pub summary: String,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub start: PrimitiveDateTime,
+    pub end: PrimitiveDateTime,
+    /// Only the dates of `start` and `end` matter, both days included
+    pub all_day: bool,
+    /// Repeats the event every week until the end of this date
+    pub weekly_until: Option<Date>,
+    /// Reminds of the event this long before it ends
+    pub remind_before_end: Option<Duration>,
+}
+
+/// An iCalendar file, as described by RFC 5545
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    name: String,
+    description: Option<String>,
+    events: Vec<Event>,
+}
+
+impl Calendar {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            events: vec![],
+        }
+    }
+
+    pub fn with_description(self, description: impl Into<String>) -> Self {
+        Self {
+            description: Some(description.into()),
+            ..self
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Renders the calendar, `stamp` being when it was generated
+    pub fn render(&self, stamp: OffsetDateTime) -> String {
+        let mut out = String::new();
+        let stamp = utc(stamp);
+
+        line(&mut out, "BEGIN:VCALENDAR");
+        line(&mut out, "VERSION:2.0");
+        line(&mut out, "PRODID:-//online-mis-wrapper//Online MIS//ID");
+        line(&mut out, "CALSCALE:GREGORIAN");
+        line(&mut out, "METHOD:PUBLISH");
+        line(&mut out, &format!("X-WR-CALNAME:{}", escape(&self.name)));
+        line(&mut out, &format!("X-WR-TIMEZONE:{TIMEZONE}"));
+        if let Some(description) = &self.description {
+            line(&mut out, &format!("X-WR-CALDESC:{}", escape(description)));
+        }
+
+        // WIB has had no daylight saving time since 1964
+        line(&mut out, "BEGIN:VTIMEZONE");
+        line(&mut out, &format!("TZID:{TIMEZONE}"));
+        line(&mut out, "BEGIN:STANDARD");
+        line(&mut out, "DTSTART:19700101T000000");
+        line(&mut out, "TZOFFSETFROM:+0700");
+        line(&mut out, "TZOFFSETTO:+0700");
+        line(&mut out, "TZNAME:WIB");
+        line(&mut out, "END:STANDARD");
+        line(&mut out, "END:VTIMEZONE");
+
+        for event in &self.events {
+            line(&mut out, "BEGIN:VEVENT");
+            line(&mut out, &format!("UID:{}", escape(&event.uid)));
+            line(&mut out, &format!("DTSTAMP:{stamp}"));
+            if event.all_day {
+                // The end of an all day event is the day after it
+                let end = event.end.date().next_day().unwrap_or(Date::MAX);
+                line(
+                    &mut out,
+                    &format!("DTSTART;VALUE=DATE:{}", date(event.start.date())),
+                );
+                line(&mut out, &format!("DTEND;VALUE=DATE:{}", date(end)));
+            } else {
+                line(
+                    &mut out,
+                    &format!("DTSTART;TZID={TIMEZONE}:{}", local(event.start)),
+                );
+                line(
+                    &mut out,
+                    &format!("DTEND;TZID={TIMEZONE}:{}", local(event.end)),
+                );
+            }
+            if let Some(until) = event.weekly_until {
+                // UNTIL has to be in UTC when DTSTART has a timezone
+                let until = PrimitiveDateTime::new(until, Time::MAX).assume_offset(offset!(+7));
+                line(&mut out, &format!("RRULE:FREQ=WEEKLY;UNTIL={}", utc(until)));
+            }
+            line(&mut out, &format!("SUMMARY:{}", escape(&event.summary)));
+            if let Some(location) = &event.location {
+                line(&mut out, &format!("LOCATION:{}", escape(location)));
+            }
+            if let Some(description) = &event.description {
+                line(&mut out, &format!("DESCRIPTION:{}", escape(description)));
+            }
+            if let Some(before) = event.remind_before_end {
+                line(&mut out, "BEGIN:VALARM");
+                line(&mut out, "ACTION:DISPLAY");
+                line(&mut out, &format!("DESCRIPTION:{}", escape(&event.summary)));
+                line(
+                    &mut out,
+                    &format!("TRIGGER;RELATED=END:-PT{}M", before.whole_minutes()),
+                );
+                line(&mut out, "END:VALARM");
+            }
+            line(&mut out, "END:VEVENT");
+        }
+
+        line(&mut out, "END:VCALENDAR");
+        out
+    }
+}
+
+fn local(at: PrimitiveDateTime) -> String {
+    at.format(format_description!(
+        "[year][month][day]T[hour][minute][second]"
+    ))
+    .unwrap_or_default()
+}
+
+fn date(at: Date) -> String {
+    at.format(format_description!("[year][month][day]"))
+        .unwrap_or_default()
+}
+
+fn utc(at: OffsetDateTime) -> String {
+    let at = at.to_offset(time::UtcOffset::UTC);
+    format!("{}Z", local(PrimitiveDateTime::new(at.date(), at.time())))
+}
+
+/// Escapes the characters with a meaning in TEXT values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends a content line, folded into lines of at most `MAX_LINE` octets without
+/// splitting a character
+fn line(out: &mut String, content: &str) {
+    let mut len = 0;
+    for c in content.chars() {
+        if len + c.len_utf8() > MAX_LINE {
+            out.push_str("\r\n ");
+            // The leading space counts towards the length of the continuation
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// An iCalendar file answered as `text/calendar`
+pub struct Ics {
+    body: String,
+    headers: Option<AppendHeaders<Vec<(HeaderName, String)>>>,
+}
+
+impl Ics {
+    pub fn new(body: String) -> Self {
+        Self {
+            body,
+            headers: None,
+        }
+    }
+
+    pub fn with_headers(self, headers: AppendHeaders<Vec<(HeaderName, String)>>) -> Self {
+        Self {
+            headers: Some(headers),
+            ..self
+        }
+    }
+}
+
+impl IntoResponse for Ics {
+    fn into_response(self) -> Response {
+        let content_type = [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")];
+        match self.headers {
+            Some(headers) => (content_type, headers, self.body).into_response(),
+            None => (content_type, self.body).into_response(),
+        }
+    }
+}
+
+impl OperationOutput for Ics {
+    type Inner = String;
+
+    fn operation_response(
+        _ctx: &mut GenContext,
+        _operation: &mut Operation,
+    ) -> Option<OpenApiResponse> {
+        let mut res = OpenApiResponse {
+            description: "An iCalendar file".to_owned(),
+            ..Default::default()
+        };
+        res.content
+            .insert("text/calendar".to_owned(), MediaType::default());
+
+        Some(res)
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<u16>, OpenApiResponse)> {
+        Self::operation_response(ctx, operation)
+            .map(|res| vec![(Some(200), res)])
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+
+    use super::{escape, line, Calendar, Event};
+
+    #[test]
+    fn escapes_and_folds_lines() {
+        assert_eq!(
+            escape("C-203, Gedung D4; lt. 2\\3\nruang"),
+            "C-203\\, Gedung D4\\; lt. 2\\\\3\\nruang"
+        );
+
+        let mut out = String::new();
+        line(&mut out, &format!("SUMMARY:{}", "é".repeat(40)));
+        let lines: Vec<_> = out.split("\r\n").collect();
+        assert_eq!(lines.len(), 3, "{out:?}");
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1].starts_with(' '));
+        assert_eq!(lines[2], "");
+    }
+
+    #[test]
+    fn renders_weekly_events_in_jakarta_time() {
+        let mut calendar = Calendar::new("Jadwal Kuliah");
+        calendar.push(Event {
+            uid: "abc@online-mis".to_owned(),
+            summary: "Pemrograman Web".to_owned(),
+            location: Some("C-203".to_owned()),
+            description: None,
+            start: datetime!(2024-08-05 08:00),
+            end: datetime!(2024-08-05 10:30),
+            all_day: false,
+            weekly_until: Some(datetime!(2025-01-31 00:00).date()),
+            remind_before_end: None,
+        });
+        calendar.push(Event {
+            uid: "def@online-mis".to_owned(),
+            summary: "UTS".to_owned(),
+            location: None,
+            description: None,
+            start: datetime!(2024-10-14 00:00),
+            end: datetime!(2024-10-18 00:00),
+            all_day: true,
+            weekly_until: None,
+            remind_before_end: Some(Duration::hours(36)),
+        });
+
+        let out = calendar.render(datetime!(2024-08-01 12:00 +7));
+        assert!(out.ends_with("END:VCALENDAR\r\n"));
+        assert!(!out.replace("\r\n", "").contains('\n'));
+        for expected in [
+            "DTSTAMP:20240801T050000Z",
+            "DTSTART;TZID=Asia/Jakarta:20240805T080000",
+            "DTEND;TZID=Asia/Jakarta:20240805T103000",
+            "RRULE:FREQ=WEEKLY;UNTIL=20250131T165959Z",
+            "LOCATION:C-203",
+            "DTSTART;VALUE=DATE:20241014",
+            "DTEND;VALUE=DATE:20241019",
+            "TRIGGER;RELATED=END:-PT2160M",
+        ] {
+            assert!(out.contains(&format!("\r\n{expected}\r\n")), "{expected}");
+        }
+        // Only the reminder has one
+        assert_eq!(out.matches("DESCRIPTION:").count(), 1);
+    }
+}
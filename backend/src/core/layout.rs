@@ -0,0 +1,123 @@
+use scraper::{selectable::Selectable, ElementRef};
+
+use super::{
+    api_response::LayoutChangeCause, error::Error, result::Result, selector::SelectorRegistry,
+};
+
+/// Name of the counter incremented for every mandatory element an extractor could not find
+pub const LAYOUT_CHANGED_COUNTER: &str = "online_mis_layout_changed_total";
+
+/// Tracks the mandatory elements of an Online MIS page while it is being scraped.
+///
+/// Missing elements are collected instead of defaulted so a changed layout is
+/// reported as [`Error::LayoutChanged`] rather than as an empty response. The
+/// endpoint of a selector is the part of its name before the first `.`.
+pub struct LayoutCheck<'a> {
+    selectors: &'a SelectorRegistry,
+    missing: Vec<&'static str>,
+}
+
+impl<'a> LayoutCheck<'a> {
+    pub fn new(selectors: &'a SelectorRegistry) -> Self {
+        Self {
+            selectors,
+            missing: Vec::new(),
+        }
+    }
+
+    /// Selects the first element matching `name`, marking it missing when there is none
+    pub fn first<'b, S>(&mut self, root: S, name: &'static str) -> Result<Option<ElementRef<'b>>>
+    where
+        S: Selectable<'b>,
+    {
+        let element = root.select(self.selectors.get(name)?).next();
+        if element.is_none() {
+            self.mark(name);
+        }
+        Ok(element)
+    }
+
+    /// Selects every element matching `name`, marking it missing when there is none
+    pub fn all<'b, S>(&mut self, root: S, name: &'static str) -> Result<Vec<ElementRef<'b>>>
+    where
+        S: Selectable<'b>,
+    {
+        let elements: Vec<_> = root.select(self.selectors.get(name)?).collect();
+        if elements.is_empty() {
+            self.mark(name);
+        }
+        Ok(elements)
+    }
+
+    /// Marks `name` as missing, e.g. when its element exists but its content can't be parsed
+    pub fn mark(&mut self, name: &'static str) {
+        if !self.missing.contains(&name) {
+            self.missing.push(name);
+        }
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let Some(first) = self.missing.first() else {
+            return Ok(());
+        };
+        let endpoint = first.split('.').next().unwrap_or_default().to_owned();
+
+        for name in &self.missing {
+            tracing::warn!(endpoint, selector = name, "Online MIS layout changed");
+            metrics::counter!(
+                LAYOUT_CHANGED_COUNTER,
+                "endpoint" => endpoint.clone(),
+                "selector" => *name,
+            )
+            .increment(1);
+        }
+
+        Err(Error::LayoutChanged(LayoutChangeCause {
+            endpoint,
+            missing: self.missing.iter().map(|name| name.to_string()).collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::LayoutCheck;
+    use crate::core::{error::Error, selector::SelectorRegistry};
+
+    #[test]
+    fn reports_each_missing_element_once() {
+        let selectors = SelectorRegistry::new(
+            &[
+                ("page.title", "h1"),
+                ("page.rows", "tr"),
+                ("page.cell", "td"),
+            ],
+            HashMap::new(),
+        )
+        .unwrap();
+        let doc = scraper::Html::parse_document("<h1>Title</h1><p>no table</p>");
+        let mut layout = LayoutCheck::new(&selectors);
+
+        assert!(layout.first(&doc, "page.title").unwrap().is_some());
+        assert!(layout.all(&doc, "page.rows").unwrap().is_empty());
+        layout.first(&doc, "page.rows").unwrap();
+
+        let Err(Error::LayoutChanged(cause)) = layout.finish() else {
+            panic!("expected a layout change");
+        };
+        assert_eq!(cause.endpoint, "page");
+        assert_eq!(cause.missing, vec!["page.rows"]);
+    }
+
+    #[test]
+    fn passes_when_nothing_is_missing() {
+        let selectors = SelectorRegistry::new(&[("page.title", "h1")], HashMap::new()).unwrap();
+        let doc = scraper::Html::parse_document("<h1>Title</h1>");
+        let mut layout = LayoutCheck::new(&selectors);
+
+        layout.first(&doc, "page.title").unwrap();
+        assert!(layout.finish().is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use subtle::ConstantTimeEq;
+
+/// Requests answered by the API, by `method`, `route` and `status`
+pub const HTTP_REQUESTS_COUNTER: &str = "http_requests_total";
+/// How long the API took to answer, by `method` and `route`
+pub const HTTP_DURATION_HISTOGRAM: &str = "http_request_duration_seconds";
+/// Requests sent to Online MIS and CAS, by `page` and `status`
+pub const UPSTREAM_REQUESTS_COUNTER: &str = "online_mis_upstream_requests_total";
+/// How long Online MIS and CAS took to answer, by `page`
+pub const UPSTREAM_DURATION_HISTOGRAM: &str = "online_mis_upstream_duration_seconds";
+/// Scraped pages requested from the cache, by `endpoint` and `result`
+pub const CACHE_COUNTER: &str = "online_mis_cache_requests_total";
+/// Extractors that failed to scrape a page, by `endpoint`
+pub const EXTRACTOR_FAILED_COUNTER: &str = "online_mis_extractor_failures_total";
+/// Logins, by `result`
+pub const LOGIN_COUNTER: &str = "online_mis_logins_total";
+/// Connections of the redis pool, by `state`
+pub const REDIS_POOL_GAUGE: &str = "redis_pool_connections";
+/// Values held by the memory cache, pages and everything else
+pub const MEMORY_CACHE_GAUGE: &str = "memory_cache_entries";
+
+/// The buckets of the duration histograms, in seconds
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// The page a request to Online MIS or CAS is for, e.g. `absen.php`
+pub fn page(url: &reqwest::Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|page| !page.is_empty())
+        .unwrap_or("/")
+        .to_owned()
+}
+
+/// Whether the request may read the metrics, always when no token is required
+pub fn authorized(headers: &HeaderMap, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|sent| bool::from(sent.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// Records the count and latency of every request by the route it matched, requests that
+/// matched none are grouped together so unknown paths don't create new series
+pub async fn track(req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_owned();
+
+    let started_at = Instant::now();
+    let res = next.run(req).await;
+
+    metrics::histogram!(
+        HTTP_DURATION_HISTOGRAM,
+        "method" => method.clone(),
+        "route" => route.clone(),
+    )
+    .record(started_at.elapsed());
+    metrics::counter!(
+        HTTP_REQUESTS_COUNTER,
+        "method" => method,
+        "route" => route,
+        "status" => res.status().as_u16().to_string(),
+    )
+    .increment(1);
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authorized, page};
+    use axum::http::{header, HeaderMap};
+
+    #[test]
+    fn labels_pages_by_their_last_segment() {
+        let page_of = |url: &str| page(&url.parse().unwrap());
+
+        assert_eq!(
+            page_of("https://online.mis.pens.ac.id/absen.php?valTahun=2024"),
+            "absen.php"
+        );
+        assert_eq!(
+            page_of("https://login.pens.ac.id/cas/login?service=x"),
+            "login"
+        );
+        assert_eq!(page_of("https://online.mis.pens.ac.id/"), "/");
+    }
+
+    #[test]
+    fn requires_the_token_when_set() {
+        let with = |authorization: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+            headers
+        };
+
+        assert!(authorized(&HeaderMap::new(), None));
+        assert!(authorized(&with("Bearer s3cr3t"), Some("s3cr3t")));
+        assert!(!authorized(&HeaderMap::new(), Some("s3cr3t")));
+        assert!(!authorized(&with("Bearer s3cr3"), Some("s3cr3t")));
+        assert!(!authorized(&with("Basic s3cr3t"), Some("s3cr3t")));
+    }
+}
@@ -1,7 +1,20 @@
 pub mod api_response;
 pub mod axum_extractor;
+pub mod cache;
+pub mod crypto;
+pub mod datetime;
 pub mod error;
 pub mod generate_openapi_response;
 pub mod handler;
 pub mod helper;
+pub mod ical;
+pub mod layout;
+pub mod metrics;
+pub mod rate_limit;
+pub mod request_id;
 pub mod result;
+pub mod selector;
+pub mod session;
+pub mod single_flight;
+pub mod ttl;
+pub mod upstream;
@@ -0,0 +1,309 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use super::{
+    cache::{Cache, Expiry},
+    error::Error,
+    result::Result,
+    ttl::parse_secs,
+};
+
+/// How many requests a client may send.
+///
+/// Parsed from `<requests>/<window>`, e.g. `5/1m`, or `off`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimit {
+    Off,
+    /// At most `requests` requests every `window` seconds
+    Every {
+        requests: u64,
+        window: u64,
+    },
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "off" {
+            return Ok(Self::Off);
+        }
+
+        let (requests, window) = s
+            .split_once('/')
+            .ok_or_else(|| format!("Expected '<requests>/<window>' or 'off', got '{s}'"))?;
+        let requests = requests
+            .parse()
+            .map_err(|_| format!("Invalid number of requests in '{s}'"))?;
+        let window = parse_secs(window)?;
+        if window == 0 {
+            return Err(format!("The window of '{s}' can't be empty"));
+        }
+
+        Ok(Self::Every { requests, window })
+    }
+}
+
+/// The limits of a route group, per client ip and per nrp
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub ip: RateLimit,
+    pub nrp: RateLimit,
+}
+
+/// Routes sharing the same limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteGroup {
+    /// `/login` and `/refresh`
+    Login,
+    /// `/academic/*`
+    Academic,
+    Default,
+}
+
+impl RouteGroup {
+    /// The group of the route at `path`, or `None` for the probes and the metrics, which
+    /// are never limited
+    pub fn of(path: &str) -> Option<Self> {
+        match path {
+            "/healthz" | "/readyz" | "/metrics" => return None,
+            _ => {}
+        }
+
+        Some(match path.strip_prefix("/api/v1") {
+            Some("/login" | "/refresh") => Self::Login,
+            Some(path) if path.starts_with("/academic/") => Self::Academic,
+            _ => Self::Default,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Academic => "academic",
+            Self::Default => "default",
+        }
+    }
+}
+
+/// The limits of every route group
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub login: Limits,
+    pub academic: Limits,
+    pub default: Limits,
+}
+
+impl RateLimits {
+    fn of(&self, group: RouteGroup) -> Limits {
+        match group {
+            RouteGroup::Login => self.login,
+            RouteGroup::Academic => self.academic,
+            RouteGroup::Default => self.default,
+        }
+    }
+}
+
+/// Counts the requests of each client in fixed windows stored in the cache, so replicas
+/// sharing a redis server share their counts
+pub struct RateLimiter {
+    cache: Arc<dyn Cache>,
+    limits: RateLimits,
+    /// Take the client ip from the last `X-Forwarded-For` entry rather than the socket
+    forwarded_for: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl RateLimiter {
+    pub fn new(cache: Arc<dyn Cache>, limits: RateLimits, forwarded_for: bool) -> Self {
+        Self {
+            cache,
+            limits,
+            forwarded_for,
+        }
+    }
+
+    /// Counts a request of `client`, failing with `Error::TooManyRequests` once it went
+    /// over `limit`
+    async fn check(
+        &self,
+        limit: RateLimit,
+        group: RouteGroup,
+        scope: &str,
+        client: &str,
+    ) -> Result<()> {
+        let RateLimit::Every { requests, window } = limit else {
+            return Ok(());
+        };
+
+        let now = now();
+        let window_end = (now / window + 1) * window;
+        let key = format!("ratelimit:{}:{scope}:{client}:{window_end}", group.name());
+
+        let count = self.cache.incr(&key, Expiry::At(window_end)).await?;
+        if count > requests {
+            tracing::debug!("Rate limited {scope} '{client}' on {}", group.name());
+            return Err(Error::TooManyRequests {
+                retry_after: window_end - now,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn client_ip(&self, req: &Request) -> Option<IpAddr> {
+        let forwarded = self
+            .forwarded_for
+            .then(|| req.headers().get_all("x-forwarded-for").iter().next_back())
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+
+        forwarded.or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+    }
+}
+
+/// The per nrp limit of the route group a request belongs to, enforced once the nrp is
+/// known
+#[derive(Clone)]
+pub struct NrpRateLimit {
+    limiter: Arc<RateLimiter>,
+    group: RouteGroup,
+}
+
+impl NrpRateLimit {
+    pub async fn check(&self, nrp: &str) -> Result<()> {
+        let limit = self.limiter.limits.of(self.group).nrp;
+        self.limiter.check(limit, self.group, "nrp", nrp).await
+    }
+
+    /// Counts a login to `email` against the per nrp limit, since the nrp is only known
+    /// once CAS accepted the credentials
+    pub async fn check_email(&self, email: &str) -> Result<()> {
+        let limit = self.limiter.limits.of(self.group).nrp;
+        self.limiter.check(limit, self.group, "email", email).await
+    }
+}
+
+/// Limits the requests of each client ip, and leaves the per nrp limit to the extractors
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response> {
+    let Some(group) = RouteGroup::of(req.uri().path()) else {
+        return Ok(next.run(req).await);
+    };
+
+    match limiter.client_ip(&req) {
+        Some(ip) => {
+            let limit = limiter.limits.of(group).ip;
+            limiter.check(limit, group, "ip", &ip.to_string()).await?;
+        }
+        None => tracing::warn!("Failed to get the client ip, not rate limiting it"),
+    }
+
+    req.extensions_mut().insert(NrpRateLimit {
+        limiter: limiter.clone(),
+        group,
+    });
+
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Limits, RateLimit, RateLimiter, RateLimits, RouteGroup};
+    use crate::core::{cache::MemoryCache, error::Error};
+
+    #[test]
+    fn parses_limits_and_groups() {
+        assert_eq!("off".parse(), Ok(RateLimit::Off));
+        assert_eq!(
+            "5/1m".parse(),
+            Ok(RateLimit::Every {
+                requests: 5,
+                window: 60
+            })
+        );
+        for invalid in ["", "5", "x/1m", "5/0", "5/1y"] {
+            assert!(invalid.parse::<RateLimit>().is_err(), "{invalid}");
+        }
+
+        assert_eq!(RouteGroup::of("/api/v1/login"), Some(RouteGroup::Login));
+        assert_eq!(
+            RouteGroup::of("/api/v1/academic/frs"),
+            Some(RouteGroup::Academic)
+        );
+        assert_eq!(
+            RouteGroup::of("/api/v1/sessions"),
+            Some(RouteGroup::Default)
+        );
+        assert_eq!(RouteGroup::of("/docs/login"), Some(RouteGroup::Default));
+        assert_eq!(RouteGroup::of("/healthz"), None);
+        assert_eq!(RouteGroup::of("/readyz"), None);
+        assert_eq!(RouteGroup::of("/metrics"), None);
+    }
+
+    #[tokio::test]
+    async fn limits_each_client_separately() {
+        let limits = Limits {
+            ip: RateLimit::Every {
+                requests: 2,
+                window: 60,
+            },
+            nrp: RateLimit::Off,
+        };
+        let limiter = RateLimiter::new(
+            Arc::new(MemoryCache::new(16)),
+            RateLimits {
+                login: limits,
+                academic: limits,
+                default: limits,
+            },
+            false,
+        );
+        let check = |client| limiter.check(limits.ip, RouteGroup::Login, "ip", client);
+
+        check("10.0.0.1").await.unwrap();
+        check("10.0.0.1").await.unwrap();
+        match check("10.0.0.1").await {
+            Err(Error::TooManyRequests { retry_after }) => assert!(retry_after <= 60),
+            res => panic!("expected too many requests, got {res:?}"),
+        }
+
+        check("10.0.0.2").await.unwrap();
+        limiter
+            .check(limits.ip, RouteGroup::Academic, "ip", "10.0.0.1")
+            .await
+            .unwrap();
+        limiter
+            .check(RateLimit::Off, RouteGroup::Login, "ip", "10.0.0.1")
+            .await
+            .unwrap();
+    }
+}
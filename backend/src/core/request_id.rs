@@ -0,0 +1,105 @@
+use std::future::Future;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+/// The header a request id is accepted from and answered in
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longer ids are replaced, so clients can't fill the logs with them
+const MAX_LEN: usize = 64;
+
+tokio::task_local! {
+    static CURRENT: Option<String>;
+}
+
+/// The id of the request being handled, if any
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|id| id.clone()).ok().flatten()
+}
+
+/// Keeps the id and span of the current request in a future spawned off it
+pub fn inherit<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    CURRENT.scope(current(), fut.instrument(tracing::Span::current()))
+}
+
+fn generate() -> String {
+    let mut id = [0; 8];
+    OsRng.fill_bytes(&mut id);
+    id.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The id the client sent, unless it's too long or has characters other than letters,
+/// digits, `-`, `_`, `.` and `:`
+fn accept(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+
+    valid.then(|| id.to_owned())
+}
+
+/// Keeps the `X-Request-Id` of the client or generates one, makes it the id of the request
+/// span and of the errors answered, and echoes it in the response
+pub async fn propagate(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(accept)
+        .unwrap_or_else(generate);
+    let value = HeaderValue::from_str(&id).expect("request ids are valid header values");
+    req.headers_mut()
+        .insert(REQUEST_ID_HEADER.clone(), value.clone());
+
+    let mut res = CURRENT.scope(Some(id), next.run(req)).await;
+    res.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::{accept, current, generate, inherit, CURRENT};
+
+    #[test]
+    fn accepts_only_short_plain_ids() {
+        let accept = |id: &str| accept(&HeaderValue::from_str(id).unwrap());
+
+        assert_eq!(
+            accept("5f0c7a2e-3c1d-4b9e-9a57-2d1c0e6f4a11").as_deref(),
+            Some("5f0c7a2e-3c1d-4b9e-9a57-2d1c0e6f4a11")
+        );
+        assert_eq!(accept("req_42.retry:1").as_deref(), Some("req_42.retry:1"));
+        assert_eq!(accept(""), None);
+        assert_eq!(accept("two words"), None);
+        assert_eq!(accept("line\"break"), None);
+        assert_eq!(accept(&"a".repeat(65)), None);
+
+        let id = generate();
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_id_in_spawned_tasks() {
+        assert_eq!(current(), None);
+
+        let id = CURRENT
+            .scope(Some("abc".to_owned()), async {
+                tokio::spawn(inherit(async { current() })).await.unwrap()
+            })
+            .await;
+        assert_eq!(id.as_deref(), Some("abc"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail, Context};
+use scraper::Selector;
+
+use super::result::Result;
+
+/// A named css selector and its default value
+pub type SelectorEntry = (&'static str, &'static str);
+
+/// Every css selector used to scrape Online MIS, compiled once at startup.
+///
+/// Entries are declared next to the extractor that uses them and can be
+/// overridden from a toml or json file mapping the selector name to a new
+/// css selector, e.g. `"frs.dosen" = "#dosen_wali"`.
+#[derive(Debug)]
+pub struct SelectorRegistry {
+    selectors: HashMap<&'static str, Selector>,
+}
+
+impl SelectorRegistry {
+    pub fn new<'a>(
+        defaults: impl IntoIterator<Item = &'a SelectorEntry>,
+        overrides: HashMap<String, String>,
+    ) -> anyhow::Result<Self> {
+        let mut sources: HashMap<&'static str, String> = defaults
+            .into_iter()
+            .map(|(name, selector)| (*name, selector.to_string()))
+            .collect();
+
+        for (name, selector) in overrides {
+            match sources.get_mut(name.as_str()) {
+                Some(source) => {
+                    tracing::info!("Overriding selector '{name}' with '{selector}'");
+                    *source = selector;
+                }
+                None => bail!("Unknown selector '{name}' in selector overrides"),
+            }
+        }
+
+        let selectors = sources
+            .into_iter()
+            .map(|(name, source)| {
+                Selector::parse(&source)
+                    .map(|selector| (name, selector))
+                    .map_err(|e| anyhow!("Invalid selector '{name}' = '{source}': {e}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { selectors })
+    }
+
+    /// Builds the registry from the defaults and the optional override file
+    pub fn load<'a>(
+        defaults: impl IntoIterator<Item = &'a SelectorEntry>,
+        path: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let overrides = match path {
+            Some(path) => {
+                tracing::info!("Loading selector overrides from '{}'", path.display());
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read '{}'", path.display()))?;
+
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some("toml") => toml::from_str(&content)
+                        .with_context(|| format!("Failed to parse '{}'", path.display()))?,
+                    Some("json") => serde_json::from_str(&content)
+                        .with_context(|| format!("Failed to parse '{}'", path.display()))?,
+                    _ => bail!("Selector file must be a .toml or .json file"),
+                }
+            }
+            None => HashMap::new(),
+        };
+
+        Self::new(defaults, overrides)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Selector> {
+        Ok(self
+            .selectors
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown selector '{name}'"))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{SelectorEntry, SelectorRegistry};
+    use crate::core::helper::test_helper::selectors;
+
+    const DEFAULTS: &[SelectorEntry] = &[("a.title", "h1"), ("a.rows", "table tr")];
+
+    #[test]
+    fn compiles_every_default_selector() {
+        let registry = selectors();
+        for (name, _) in crate::http::default_selectors() {
+            assert!(registry.get(name).is_ok(), "missing selector '{name}'");
+        }
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let overrides = HashMap::from([("a.title".to_owned(), "h2".to_owned())]);
+        let registry = SelectorRegistry::new(DEFAULTS, overrides).unwrap();
+        let doc = scraper::Html::parse_fragment("<h1>old</h1><h2>new</h2>");
+
+        let title = doc.select(registry.get("a.title").unwrap()).next().unwrap();
+        assert_eq!(title.inner_html(), "new");
+    }
+
+    #[test]
+    fn rejects_unknown_and_invalid_overrides() {
+        let unknown = HashMap::from([("a.missing".to_owned(), "h2".to_owned())]);
+        assert!(SelectorRegistry::new(DEFAULTS, unknown).is_err());
+
+        let invalid = HashMap::from([("a.rows".to_owned(), "table >".to_owned())]);
+        assert!(SelectorRegistry::new(DEFAULTS, invalid).is_err());
+    }
+}
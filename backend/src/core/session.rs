@@ -0,0 +1,324 @@
+use std::{sync::Arc, time::SystemTime};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use anyhow::anyhow;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cache::{Cache, Expiry},
+    crypto::Keyring,
+    result::Result,
+};
+
+/// A logged in user, stored in the cache under `session:{token}`.
+///
+/// The browser only ever gets the encrypted token, the Online MIS `PHPSESSID` stays here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Public identifier used to list and revoke the session, unlike the token
+    pub id: String,
+    pub nrp: String,
+    pub session_id: String,
+    pub year: u16,
+    pub semester: u8,
+    pub week: u8,
+    pub user: String,
+    pub created_at: u64,
+    pub last_seen_at: u64,
+}
+
+/// How a sealed session token was handed out, a token is only accepted where its kind is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    /// The `SESSION_ID` cookie, valid as long as the session
+    Cookie,
+    /// A short lived `Authorization: Bearer` token
+    Access,
+    /// Exchanged for a new access token, valid as long as the session
+    Refresh,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    token: String,
+    kind: TokenKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+}
+
+/// Sessions live in the cache for `ttl` seconds after they were last seen. The tokens of
+/// a user are indexed under `sessions:{nrp}:{id}`, keyed by the session id.
+#[derive(Clone)]
+pub struct SessionStore {
+    cache: Arc<dyn Cache>,
+    keyring: Keyring,
+    ttl: u64,
+    access_ttl: u64,
+}
+
+fn session_key(token: &str) -> String {
+    format!("session:{}", token)
+}
+
+fn index_key(nrp: &str, id: &str) -> String {
+    format!("sessions:{}:{}", nrp, id)
+}
+
+fn random_string(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl SessionStore {
+    pub fn new(cache: Arc<dyn Cache>, keyring: Keyring, ttl: u64, access_ttl: u64) -> Self {
+        Self {
+            cache,
+            keyring,
+            ttl,
+            access_ttl,
+        }
+    }
+
+    /// How long an access token is valid, in seconds
+    pub fn access_ttl(&self) -> u64 {
+        self.access_ttl
+    }
+
+    /// Stores a new session and returns its token, to be sealed before handing it out
+    pub async fn create(
+        &self,
+        nrp: String,
+        session_id: String,
+        (year, semester, week): (u16, u8, u8),
+        user: String,
+    ) -> Result<String> {
+        let token = random_string(32);
+        let now = now();
+        let record = SessionRecord {
+            id: random_string(9),
+            nrp,
+            session_id,
+            year,
+            semester,
+            week,
+            user,
+            created_at: now,
+            last_seen_at: now,
+        };
+
+        self.save(&token, &record).await?;
+
+        Ok(token)
+    }
+
+    /// Encrypts the session token into a token of the given kind
+    pub fn seal(&self, token: &str, kind: TokenKind) -> Result<String> {
+        let exp = match kind {
+            TokenKind::Cookie => None,
+            TokenKind::Access => Some(now() + self.access_ttl),
+            TokenKind::Refresh => Some(now() + self.ttl),
+        };
+        let claims = serde_json::to_vec(&Claims {
+            token: token.to_owned(),
+            kind,
+            exp,
+        })
+        .map_err(|e| anyhow!(e.to_string()))?;
+
+        self.keyring.encrypt(&claims)
+    }
+
+    /// Finds the session a sealed token of the given kind points to and marks it as seen
+    pub async fn resolve(
+        &self,
+        sealed: &str,
+        kind: TokenKind,
+    ) -> Result<Option<(String, SessionRecord)>> {
+        let claims: Claims = serde_json::from_slice(&self.keyring.decrypt(sealed)?)
+            .map_err(|e| anyhow!(e.to_string()))?;
+        if claims.kind != kind {
+            return Err(anyhow!("Expected a {kind:?} token, got a {:?} token", claims.kind).into());
+        }
+        if claims.exp.is_some_and(|exp| exp <= now()) {
+            return Err(anyhow!("The {kind:?} token expired").into());
+        }
+        let token = claims.token;
+
+        let Some(mut record) = self.load(&token).await? else {
+            return Ok(None);
+        };
+
+        record.last_seen_at = now();
+        self.save(&token, &record).await?;
+
+        Ok(Some((token, record)))
+    }
+
+    /// Points the session to a new Online MIS `PHPSESSID`, e.g. after logging in again
+    pub async fn update_session_id(&self, token: &str, session_id: String) -> Result<()> {
+        let Some(mut record) = self.load(token).await? else {
+            return Ok(());
+        };
+
+        record.session_id = session_id;
+        self.save(token, &record).await
+    }
+
+    /// Lists the active sessions of a user, forgetting the ones that expired
+    pub async fn list(&self, nrp: &str) -> Result<Vec<SessionRecord>> {
+        let mut sessions = Vec::new();
+        for key in self.cache.keys(&index_key(nrp, "*")).await? {
+            let Some(token) = self.cache.get(&key).await? else {
+                continue;
+            };
+
+            match self.load(&token).await? {
+                Some(record) => sessions.push(record),
+                None => self.cache.del(&key).await?,
+            }
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    /// The session of a user seen last, along with its token
+    pub async fn latest(&self, nrp: &str) -> Result<Option<(String, SessionRecord)>> {
+        let mut latest: Option<(String, SessionRecord)> = None;
+        for key in self.cache.keys(&index_key(nrp, "*")).await? {
+            let Some(token) = self.cache.get(&key).await? else {
+                continue;
+            };
+            let Some(record) = self.load(&token).await? else {
+                continue;
+            };
+
+            if latest
+                .as_ref()
+                .is_none_or(|(_, seen)| record.last_seen_at > seen.last_seen_at)
+            {
+                latest = Some((token, record));
+            }
+        }
+
+        Ok(latest)
+    }
+
+    /// Revokes a session of a user, returning whether it existed
+    pub async fn revoke(&self, nrp: &str, id: &str) -> Result<bool> {
+        let Some(token) = self.cache.get(&index_key(nrp, id)).await? else {
+            return Ok(false);
+        };
+
+        self.cache.del(&session_key(&token)).await?;
+        self.cache.del(&index_key(nrp, id)).await?;
+
+        Ok(true)
+    }
+
+    /// The session an opaque token points to, without marking it as seen
+    pub async fn load(&self, token: &str) -> Result<Option<SessionRecord>> {
+        let Some(json) = self.cache.get(&session_key(token)).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            serde_json::from_str(&json).map_err(|e| anyhow!(e.to_string()))?,
+        ))
+    }
+
+    async fn save(&self, token: &str, record: &SessionRecord) -> Result<()> {
+        let json = serde_json::to_string(record).map_err(|e| anyhow!(e.to_string()))?;
+        self.cache
+            .set(&session_key(token), json, Expiry::In(self.ttl))
+            .await?;
+        self.cache
+            .set(
+                &index_key(&record.nrp, &record.id),
+                token.to_owned(),
+                Expiry::In(self.ttl),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{SessionStore, TokenKind};
+    use crate::core::{cache::MemoryCache, crypto::Keyring};
+
+    #[tokio::test]
+    async fn lists_and_revokes_sessions() {
+        let store = SessionStore::new(Arc::new(MemoryCache::new(16)), Keyring::generate(), 60, 60);
+        let first = store
+            .create("3122600001".into(), "a".into(), (2024, 1, 1), "Budi".into())
+            .await
+            .unwrap();
+        store
+            .create("3122600001".into(), "b".into(), (2024, 1, 1), "Budi".into())
+            .await
+            .unwrap();
+        store
+            .create("3122600002".into(), "c".into(), (2024, 1, 1), "Ani".into())
+            .await
+            .unwrap();
+
+        let sealed = store.seal(&first, TokenKind::Cookie).unwrap();
+        let (_, record) = store
+            .resolve(&sealed, TokenKind::Cookie)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.session_id, "a");
+        assert_eq!(store.list("3122600001").await.unwrap().len(), 2);
+
+        assert!(store.revoke("3122600001", &record.id).await.unwrap());
+        assert!(!store.revoke("3122600001", &record.id).await.unwrap());
+        assert!(store
+            .resolve(&sealed, TokenKind::Cookie)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(store.list("3122600001").await.unwrap().len(), 1);
+        assert_eq!(store.list("3122600002").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn finds_the_latest_session() {
+        let store = SessionStore::new(Arc::new(MemoryCache::new(16)), Keyring::generate(), 60, 60);
+        assert!(store.latest("3122600001").await.unwrap().is_none());
+
+        for session_id in ["a", "b"] {
+            let token = store
+                .create(
+                    "3122600001".into(),
+                    session_id.into(),
+                    (2024, 1, 1),
+                    "Budi".into(),
+                )
+                .await
+                .unwrap();
+            let mut record = store.load(&token).await.unwrap().unwrap();
+            // Seen later than the first one
+            record.last_seen_at += u64::from(session_id == "b");
+            store.save(&token, &record).await.unwrap();
+        }
+
+        let (_, record) = store.latest("3122600001").await.unwrap().unwrap();
+        assert_eq!(record.session_id, "b");
+    }
+}
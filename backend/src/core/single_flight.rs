@@ -0,0 +1,231 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use tokio::sync::watch;
+
+use super::{
+    cache::{Cache, Expiry},
+    result::Result,
+};
+
+/// Lets a single request fetch a page at a time, the others wait for it and read what it
+/// cached. Requests of this process wait on each other directly, requests of other
+/// replicas through a `lock:{key}` value in the cache, kept for at most `lock_ttl`
+/// seconds in case its holder dies. The value is a random token of its holder, so that a
+/// holder that outlived its lock can't release the lock taken after it.
+pub struct SingleFlight {
+    cache: Arc<dyn Cache>,
+    lock_ttl: u64,
+    flights: Mutex<HashMap<String, watch::Receiver<()>>>,
+}
+
+/// Held while fetching a page, the waiting requests are woken up once it is dropped
+pub struct FlightGuard<'a> {
+    single_flight: &'a SingleFlight,
+    key: String,
+    /// The token the lock was taken with
+    owner: String,
+    locked: bool,
+    _done: watch::Sender<()>,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn lock_key(key: &str) -> String {
+    format!("lock:{}", key)
+}
+
+fn owner_token() -> String {
+    let mut bytes = [0; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl SingleFlight {
+    pub fn new(cache: Arc<dyn Cache>, lock_ttl: u64) -> Self {
+        Self {
+            cache,
+            lock_ttl,
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn flights(&self) -> std::sync::MutexGuard<'_, HashMap<String, watch::Receiver<()>>> {
+        self.flights.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts fetching `key`, or returns `None` once the request already fetching it is
+    /// done, here or on another replica
+    pub async fn lead_or_wait(&self, key: &str) -> Result<Option<FlightGuard<'_>>> {
+        let mut guard = match self.join(key) {
+            Ok(guard) => guard,
+            Err(mut done) => {
+                let _ = tokio::time::timeout(self.timeout(), done.changed()).await;
+                return Ok(None);
+            }
+        };
+
+        if self.lock(key, &guard.owner).await? {
+            guard.locked = true;
+            return Ok(Some(guard));
+        }
+
+        let unlocked = async {
+            while self.cache.get(&lock_key(key)).await?.is_some() {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            Result::<()>::Ok(())
+        };
+        if let Ok(res) = tokio::time::timeout(self.timeout(), unlocked).await {
+            res?;
+        }
+
+        Ok(None)
+    }
+
+    /// Starts fetching `key` unless another request already is
+    pub async fn try_lead(&self, key: &str) -> Result<Option<FlightGuard<'_>>> {
+        let Ok(mut guard) = self.join(key) else {
+            return Ok(None);
+        };
+
+        guard.locked = self.lock(key, &guard.owner).await?;
+        Ok(guard.locked.then_some(guard))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.lock_ttl)
+    }
+
+    /// Registers this process' flight for `key`, or returns the one already registered
+    fn join(&self, key: &str) -> Result<FlightGuard<'_>, watch::Receiver<()>> {
+        let mut flights = self.flights();
+        if let Some(done) = flights.get(key) {
+            return Err(done.clone());
+        }
+
+        let (done, waiting) = watch::channel(());
+        flights.insert(key.to_owned(), waiting);
+
+        Ok(FlightGuard {
+            single_flight: self,
+            key: key.to_owned(),
+            owner: owner_token(),
+            locked: false,
+            _done: done,
+        })
+    }
+
+    async fn lock(&self, key: &str, owner: &str) -> Result<bool> {
+        self.cache
+            .set_nx(&lock_key(key), owner.to_owned(), Expiry::In(self.lock_ttl))
+            .await
+    }
+}
+
+impl FlightGuard<'_> {
+    /// Lets the requests waiting on other replicas go
+    pub async fn release(mut self) -> Result<()> {
+        self.locked = false;
+        let released = self
+            .single_flight
+            .cache
+            .del_if(&lock_key(&self.key), &self.owner)
+            .await?;
+        if !released {
+            tracing::debug!(
+                "Lock on '{}' expired before it was released, leaving it to its new holder",
+                self.key
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.locked {
+            tracing::debug!(
+                "Lock on '{}' was not released, it expires by itself",
+                self.key
+            );
+        }
+        self.single_flight.flights().remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{lock_key, SingleFlight};
+    use crate::core::cache::{Cache, Expiry, MemoryCache};
+
+    #[tokio::test]
+    async fn waits_for_the_request_in_flight() {
+        let single_flight = SingleFlight::new(Arc::new(MemoryCache::new(8)), 5);
+
+        let leader = single_flight.lead_or_wait("frs").await.unwrap().unwrap();
+        assert!(single_flight.try_lead("frs").await.unwrap().is_none());
+        assert!(single_flight.try_lead("absen").await.unwrap().is_some());
+
+        let follower = single_flight.lead_or_wait("frs");
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            leader.release().await.unwrap();
+        };
+        let (follower, ()) = tokio::join!(follower, release);
+        assert!(follower.unwrap().is_none());
+
+        assert!(single_flight.try_lead("frs").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn waits_for_other_replicas() {
+        let cache = Arc::new(MemoryCache::new(8));
+        let single_flight = SingleFlight::new(cache.clone(), 5);
+        let other_replica = SingleFlight::new(cache.clone(), 5);
+
+        let leader = other_replica.lead_or_wait("frs").await.unwrap().unwrap();
+        assert!(single_flight.try_lead("frs").await.unwrap().is_none());
+
+        let follower = single_flight.lead_or_wait("frs");
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            leader.release().await.unwrap();
+        };
+        let (follower, ()) = tokio::join!(follower, release);
+        assert!(follower.unwrap().is_none());
+
+        // A lock left behind by a dead replica expires
+        cache
+            .set("lock:absen", String::new(), Expiry::In(0))
+            .await
+            .unwrap();
+        assert!(single_flight.try_lead("absen").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn only_releases_its_own_lock() {
+        let cache = Arc::new(MemoryCache::new(8));
+        let replica = SingleFlight::new(cache.clone(), 5);
+        let other_replica = SingleFlight::new(cache.clone(), 5);
+
+        let expired = replica.try_lead("frs").await.unwrap().unwrap();
+        // The lock expired while its holder was still fetching
+        cache.del(&lock_key("frs")).await.unwrap();
+        let leader = other_replica.try_lead("frs").await.unwrap().unwrap();
+
+        expired.release().await.unwrap();
+        assert!(cache.get(&lock_key("frs")).await.unwrap().is_some());
+        assert!(replica.try_lead("frs").await.unwrap().is_none());
+
+        leader.release().await.unwrap();
+        assert_eq!(cache.get(&lock_key("frs")).await.unwrap(), None);
+    }
+}
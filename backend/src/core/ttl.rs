@@ -0,0 +1,230 @@
+use std::str::FromStr;
+
+use time::{Date, Duration, OffsetDateTime, Time, UtcOffset};
+
+use super::cache::Expiry;
+
+/// How long a scraped page stays cached.
+///
+/// Parsed from `<n>[s|m|h|d]` for a fixed duration, `daily` or `daily@<hour>` for the
+/// next time the clock reads that hour, and `tanggal-penting:<during>/<otherwise>` for
+/// a short ttl inside the FRS windows and a longer one outside of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtlPolicy {
+    /// A fixed number of seconds
+    Fixed(u64),
+    /// Until the next time the clock reads `hour`:00 at the cache UTC offset
+    Daily { hour: u8 },
+    /// `during` seconds while a `TanggalPenting` window is open, `otherwise` seconds
+    /// when none is, never outliving the window it was cached in or before
+    TanggalPenting { during: u64, otherwise: u64 },
+}
+
+/// Cached values whose ttl depends on the dates they contain
+pub trait TtlWindows {
+    /// The date ranges, inclusive, the value changes often in
+    fn ttl_windows(&self) -> Vec<(Date, Date)> {
+        Vec::new()
+    }
+}
+
+/// A ttl policy along with how it is applied
+#[derive(Debug, Clone, Copy)]
+pub struct Ttl {
+    pub policy: TtlPolicy,
+    /// The UTC offset days are counted in
+    pub utc_offset: UtcOffset,
+    /// How long an expired value is still served while it is refreshed, in seconds
+    pub stale: u64,
+}
+
+impl Ttl {
+    pub fn expiry(self, now: OffsetDateTime, windows: &[(Date, Date)]) -> Expiry {
+        self.policy.expiry(now, self.utc_offset, windows)
+    }
+}
+
+/// The ttl of every cached endpoint
+#[derive(Debug, Clone, Copy)]
+pub struct CacheTtl {
+    pub absen: Ttl,
+    pub frs: Ttl,
+    pub jadwal: Ttl,
+    pub nilai: Ttl,
+    pub logbook: Ttl,
+}
+
+impl TtlPolicy {
+    pub fn expiry(
+        self,
+        now: OffsetDateTime,
+        utc_offset: UtcOffset,
+        windows: &[(Date, Date)],
+    ) -> Expiry {
+        let now = now.to_offset(utc_offset);
+
+        match self {
+            Self::Fixed(secs) => Expiry::In(secs),
+            Self::Daily { hour } => {
+                let hour = Time::from_hms(hour, 0, 0).unwrap_or(Time::MIDNIGHT);
+                let mut boundary = now.replace_time(hour);
+                if boundary <= now {
+                    boundary += Duration::DAY;
+                }
+                Expiry::At(boundary.unix_timestamp() as u64)
+            }
+            Self::TanggalPenting { during, otherwise } => {
+                let start_of = |date: Date| now.replace_date(date).replace_time(Time::MIDNIGHT);
+                let secs_until = |at: OffsetDateTime| (at - now).whole_seconds().max(1) as u64;
+
+                let open = windows.iter().find_map(|&(from, to)| {
+                    let end = start_of(to.next_day()?);
+                    (start_of(from) <= now && now < end).then_some(end)
+                });
+                if let Some(end) = open {
+                    return Expiry::In(during.min(secs_until(end)));
+                }
+
+                let next = windows
+                    .iter()
+                    .map(|&(from, _)| start_of(from))
+                    .filter(|start| *start > now)
+                    .min();
+                match next {
+                    Some(start) => Expiry::In(otherwise.min(secs_until(start))),
+                    None => Expiry::In(otherwise),
+                }
+            }
+        }
+    }
+}
+
+/// Parses a duration written as `<n>[s|m|h|d]`, in seconds
+pub(crate) fn parse_secs(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("Invalid duration '{s}'"))?;
+
+    match unit {
+        "s" => Ok(n),
+        "m" => Ok(n * 60),
+        "h" => Ok(n * 60 * 60),
+        "d" => Ok(n * 24 * 60 * 60),
+        _ => Err(format!(
+            "Invalid duration unit in '{s}', expected s, m, h or d"
+        )),
+    }
+}
+
+impl FromStr for TtlPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s == "daily" {
+            return Ok(Self::Daily { hour: 0 });
+        }
+        if let Some(hour) = s.strip_prefix("daily@") {
+            return match hour.parse() {
+                Ok(hour) if hour < 24 => Ok(Self::Daily { hour }),
+                _ => Err(format!("Invalid hour in '{s}', expected 0 to 23")),
+            };
+        }
+        if let Some(ttl) = s.strip_prefix("tanggal-penting:") {
+            let (during, otherwise) = ttl.split_once('/').ok_or_else(|| {
+                format!("Expected 'tanggal-penting:<during>/<otherwise>', got '{s}'")
+            })?;
+            return Ok(Self::TanggalPenting {
+                during: parse_secs(during)?,
+                otherwise: parse_secs(otherwise)?,
+            });
+        }
+
+        parse_secs(s).map(Self::Fixed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{
+        macros::{datetime, offset},
+        Date, Month, UtcOffset,
+    };
+
+    use super::TtlPolicy;
+    use crate::core::cache::Expiry;
+
+    const WIB: UtcOffset = offset!(+7);
+
+    fn date(day: u8) -> Date {
+        Date::from_calendar_date(2025, Month::February, day).unwrap()
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("90".parse(), Ok(TtlPolicy::Fixed(90)));
+        assert_eq!("15m".parse(), Ok(TtlPolicy::Fixed(15 * 60)));
+        assert_eq!("daily".parse(), Ok(TtlPolicy::Daily { hour: 0 }));
+        assert_eq!("daily@5".parse(), Ok(TtlPolicy::Daily { hour: 5 }));
+        assert_eq!(
+            "tanggal-penting:1m/6h".parse(),
+            Ok(TtlPolicy::TanggalPenting {
+                during: 60,
+                otherwise: 6 * 60 * 60
+            })
+        );
+
+        for invalid in ["", "5x", "daily@24", "tanggal-penting:1m"] {
+            assert!(invalid.parse::<TtlPolicy>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn daily_expires_at_the_next_boundary_at_the_offset() {
+        let policy = TtlPolicy::Daily { hour: 0 };
+
+        // 22:00 WIB, two hours before midnight WIB
+        let now = datetime!(2025-02-10 15:00 UTC);
+        assert_eq!(
+            policy.expiry(now, WIB, &[]),
+            Expiry::At(datetime!(2025-02-10 17:00 UTC).unix_timestamp() as u64)
+        );
+
+        // 00:30 WIB, the boundary just passed
+        let now = datetime!(2025-02-10 17:30 UTC);
+        assert_eq!(
+            policy.expiry(now, WIB, &[]),
+            Expiry::At(datetime!(2025-02-11 17:00 UTC).unix_timestamp() as u64)
+        );
+    }
+
+    #[test]
+    fn tanggal_penting_is_short_inside_windows() {
+        let policy = TtlPolicy::TanggalPenting {
+            during: 60,
+            otherwise: 6 * 60 * 60,
+        };
+        let windows = [(date(3), date(14)), (date(17), date(21))];
+
+        // Inside the first window
+        let now = datetime!(2025-02-10 03:00 UTC);
+        assert_eq!(policy.expiry(now, WIB, &windows), Expiry::In(60));
+
+        // 23:59:30 WIB on the last day of the first window
+        let now = datetime!(2025-02-14 16:59:30 UTC);
+        assert_eq!(policy.expiry(now, WIB, &windows), Expiry::In(30));
+
+        // Two hours before the second window opens
+        let now = datetime!(2025-02-16 15:00 UTC);
+        assert_eq!(policy.expiry(now, WIB, &windows), Expiry::In(2 * 60 * 60));
+
+        // After every window
+        let now = datetime!(2025-03-01 00:00 UTC);
+        assert_eq!(policy.expiry(now, WIB, &windows), Expiry::In(6 * 60 * 60));
+    }
+}
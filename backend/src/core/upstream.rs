@@ -0,0 +1,471 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use reqwest::{Method, Request, RequestBuilder, Response, StatusCode};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{
+    error::Error,
+    metrics::{page, UPSTREAM_DURATION_HISTOGRAM, UPSTREAM_REQUESTS_COUNTER},
+    request_id,
+    result::Result,
+};
+
+/// How requests to Online MIS and CAS are limited and retried
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamConfig {
+    /// How many requests may be in flight at once
+    pub concurrency: usize,
+    /// How many requests of a single user may be in flight at once
+    pub concurrency_per_user: usize,
+    /// How many times a failed `GET` is sent again
+    pub retries: u32,
+    /// How many failures in a row open the circuit breaker
+    pub breaker_threshold: u32,
+    /// How long the circuit breaker stays open before letting a request through again
+    pub breaker_cooldown: Duration,
+    /// How long connecting to Online MIS may take
+    pub connect_timeout: Duration,
+    /// How long Online MIS may go without sending anything
+    pub read_timeout: Duration,
+    /// How long a request may take in total, retries included
+    pub deadline: Duration,
+}
+
+impl UpstreamConfig {
+    /// A client builder with the configured timeouts
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+    }
+}
+
+enum Breaker {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single request is let through to find out whether Online MIS is back
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// The client every request to Online MIS and CAS goes through.
+///
+/// Requests wait for a free slot, globally and for their user, and `GET`s that failed
+/// to reach Online MIS are retried with a jittered backoff, until the deadline. After
+/// enough failures in a row to a host its circuit breaker opens, and requests to it fail
+/// right away with `Error::UpstreamUnavailable` until it lets one through again.
+pub struct Upstream {
+    client: reqwest::Client,
+    config: UpstreamConfig,
+    global: Arc<Semaphore>,
+    per_user: Mutex<HashMap<String, Arc<Semaphore>>>,
+    /// The circuit breaker of each host, so CAS being down doesn't fail Online MIS
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+impl Upstream {
+    pub fn new(client: reqwest::Client, config: UpstreamConfig) -> Self {
+        Self {
+            client,
+            config,
+            global: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            per_user: Mutex::new(HashMap::new()),
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The client to build requests with
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn config(&self) -> &UpstreamConfig {
+        &self.config
+    }
+
+    /// Sends a request on behalf of `user`, usually their nrp, and reads its body
+    pub async fn text(&self, user: &str, request: RequestBuilder) -> Result<String> {
+        let _permits = self.acquire(user).await?;
+        Ok(self.send_permitted(request).await?.text().await?)
+    }
+
+    /// Sends a request on behalf of `user`, usually their nrp
+    pub async fn send(&self, user: &str, request: RequestBuilder) -> Result<Response> {
+        let _permits = self.acquire(user).await?;
+        self.send_permitted(request).await
+    }
+
+    async fn send_permitted(&self, request: RequestBuilder) -> Result<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let page = page(request.url());
+
+        match tokio::time::timeout(self.config.deadline, self.send_retried(&client, request)).await
+        {
+            Ok(res) => res,
+            Err(_) => {
+                tracing::debug!(
+                    "Online MIS did not answer '{page}' within {:?}",
+                    self.config.deadline
+                );
+                Err(Error::UpstreamUnavailable)
+            }
+        }
+    }
+
+    async fn send_retried(&self, client: &reqwest::Client, request: Request) -> Result<Response> {
+        let retries = match request.method() {
+            &Method::GET => self.config.retries,
+            _ => 0,
+        };
+
+        let mut attempt = 0;
+        loop {
+            // Requests with a streaming body can't be cloned, and are never retried
+            let (res, failed) = match request.try_clone() {
+                Some(retry) if attempt < retries => self.execute(client, retry).await?,
+                _ => return Ok(self.execute(client, request).await?.0?),
+            };
+            if !failed {
+                return Ok(res?);
+            }
+
+            attempt += 1;
+            let backoff = RETRY_BACKOFF * 2u32.pow(attempt - 1);
+            let jitter = Duration::from_millis(OsRng.next_u64() % backoff.as_millis() as u64);
+            tracing::debug!(
+                "Online MIS request failed, retrying in {:?}",
+                backoff + jitter
+            );
+            tokio::time::sleep(backoff + jitter).await;
+        }
+    }
+
+    /// Sends the request unless the circuit breaker is open, returning its result and
+    /// whether Online MIS could not answer it
+    #[tracing::instrument(
+        name = "upstream_request",
+        skip_all,
+        fields(
+            method = %request.method(),
+            page = page(request.url()),
+            status,
+            request_id = request_id::current(),
+        )
+    )]
+    async fn execute(
+        &self,
+        client: &reqwest::Client,
+        request: Request,
+    ) -> Result<(reqwest::Result<Response>, bool)> {
+        let page = page(request.url());
+        let host = host(request.url());
+        if let Err(e) = self.check_breaker(&host) {
+            metrics::counter!(
+                UPSTREAM_REQUESTS_COUNTER,
+                "page" => page,
+                "status" => "breaker_open",
+            )
+            .increment(1);
+            return Err(e);
+        }
+
+        let started_at = Instant::now();
+        let res = client.execute(request).await;
+        metrics::histogram!(UPSTREAM_DURATION_HISTOGRAM, "page" => page.clone())
+            .record(started_at.elapsed());
+
+        let failed = match &res {
+            Ok(res) => is_unavailable(res.status()),
+            Err(e) => e.is_timeout() || e.is_connect(),
+        };
+        self.record(&host, !failed);
+
+        let status = match &res {
+            Ok(res) => res.status().as_u16().to_string(),
+            Err(e) if e.is_timeout() => "timeout".to_owned(),
+            Err(_) => "error".to_owned(),
+        };
+        tracing::Span::current().record("status", &status);
+        tracing::debug!(
+            "Online MIS answered '{page}' with {status} in {:?}",
+            started_at.elapsed()
+        );
+        metrics::counter!(UPSTREAM_REQUESTS_COUNTER, "page" => page, "status" => status)
+            .increment(1);
+
+        Ok((res, failed))
+    }
+
+    async fn acquire(&self, user: &str) -> Result<(OwnedSemaphorePermit, UserPermit<'_>)> {
+        let semaphore = self
+            .per_user()
+            .entry(user.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.config.concurrency_per_user.max(1))))
+            .clone();
+        let user_permit = UserPermit {
+            upstream: self,
+            user: user.to_owned(),
+            permit: Some(
+                semaphore
+                    .acquire_owned()
+                    .await
+                    .map_err(|e| anyhow::anyhow!(e))?,
+            ),
+        };
+
+        let global_permit = self
+            .global
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        Ok((global_permit, user_permit))
+    }
+
+    fn per_user(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<Semaphore>>> {
+        self.per_user.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn breakers(&self) -> std::sync::MutexGuard<'_, HashMap<String, Breaker>> {
+        self.breakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn check_breaker(&self, host: &str) -> Result<()> {
+        let mut breakers = self.breakers();
+        let Some(breaker) = breakers.get_mut(host) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+
+        match *breaker {
+            Breaker::Closed { .. } => Ok(()),
+            Breaker::Open { until } if now < until => Err(Error::UpstreamUnavailable),
+            // A probe that never finished, e.g. because its request was dropped, is given
+            // up on after a cooldown
+            Breaker::HalfOpen { since } if now < since + self.config.breaker_cooldown => {
+                Err(Error::UpstreamUnavailable)
+            }
+            _ => {
+                tracing::info!("Letting a request through to {host} to probe it");
+                *breaker = Breaker::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    fn record(&self, host: &str, success: bool) {
+        let mut breakers = self.breakers();
+        let breaker = breakers
+            .entry(host.to_owned())
+            .or_insert(Breaker::Closed { failures: 0 });
+
+        *breaker = match (&*breaker, success) {
+            (Breaker::Closed { .. } | Breaker::HalfOpen { .. }, true) => {
+                if matches!(*breaker, Breaker::HalfOpen { .. }) {
+                    tracing::info!("{host} is back, closing its circuit breaker");
+                }
+                Breaker::Closed { failures: 0 }
+            }
+            (Breaker::Closed { failures }, false)
+                if failures + 1 < self.config.breaker_threshold =>
+            {
+                Breaker::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (Breaker::Closed { .. } | Breaker::HalfOpen { .. }, false) => {
+                tracing::warn!(
+                    "{host} looks down, failing its requests for {:?}",
+                    self.config.breaker_cooldown
+                );
+                Breaker::Open {
+                    until: Instant::now() + self.config.breaker_cooldown,
+                }
+            }
+            // Requests sent before the breaker opened
+            (Breaker::Open { until }, _) => Breaker::Open { until: *until },
+        };
+    }
+}
+
+/// The host a circuit breaker is kept for, with its port
+fn host(url: &reqwest::Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+fn is_unavailable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Frees the user's slot, and forgets the user once they have no request in flight
+struct UserPermit<'a> {
+    upstream: &'a Upstream,
+    user: String,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for UserPermit<'_> {
+    fn drop(&mut self) {
+        drop(self.permit.take());
+
+        let mut per_user = self.upstream.per_user();
+        if per_user
+            .get(&self.user)
+            .is_some_and(|semaphore| Arc::strong_count(semaphore) == 1)
+        {
+            per_user.remove(&self.user);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::http::StatusCode;
+
+    use super::{Upstream, UpstreamConfig};
+    use crate::core::error::Error;
+
+    /// Answers with `status` after `delay` and counts the requests it got
+    async fn slow_server(status: StatusCode, delay: Duration) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().fallback(move || async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(delay).await;
+            status
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, hits)
+    }
+
+    async fn server(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+        slow_server(status, Duration::ZERO).await
+    }
+
+    fn upstream(retries: u32, breaker_threshold: u32) -> Upstream {
+        Upstream::new(
+            reqwest::Client::new(),
+            UpstreamConfig {
+                concurrency: 4,
+                concurrency_per_user: 2,
+                retries,
+                breaker_threshold,
+                breaker_cooldown: Duration::from_secs(60),
+                connect_timeout: Duration::from_secs(1),
+                read_timeout: Duration::from_secs(1),
+                deadline: Duration::from_secs(5),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn retries_only_gets() {
+        let (url, hits) = server(StatusCode::SERVICE_UNAVAILABLE).await;
+        let upstream = upstream(2, 100);
+
+        let res = upstream
+            .send("3122600001", upstream.client().get(&url))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        upstream
+            .send("3122600001", upstream.client().post(&url))
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+        assert!(upstream.per_user().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_fast_once_the_breaker_opens() {
+        let (url, hits) = server(StatusCode::BAD_GATEWAY).await;
+        let upstream = upstream(0, 2);
+
+        for _ in 0..2 {
+            upstream
+                .send("3122600001", upstream.client().get(&url))
+                .await
+                .unwrap();
+        }
+        let err = upstream
+            .send("3122600001", upstream.client().get(&url))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::UpstreamUnavailable));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_a_breaker_per_host() {
+        let (down, _) = server(StatusCode::BAD_GATEWAY).await;
+        let (up, hits) = server(StatusCode::OK).await;
+        let upstream = upstream(0, 1);
+
+        upstream
+            .send("3122600001", upstream.client().get(&down))
+            .await
+            .unwrap();
+        let err = upstream
+            .send("3122600001", upstream.client().get(&down))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::UpstreamUnavailable));
+
+        let res = upstream
+            .send("3122600001", upstream.client().get(&up))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_retries_at_the_deadline() {
+        let (url, hits) =
+            slow_server(StatusCode::SERVICE_UNAVAILABLE, Duration::from_millis(300)).await;
+        let mut upstream = upstream(5, 100);
+        upstream.config.deadline = Duration::from_millis(500);
+
+        let started_at = std::time::Instant::now();
+        let err = upstream
+            .send("3122600001", upstream.client().get(&url))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::UpstreamUnavailable));
+        assert!(started_at.elapsed() < Duration::from_millis(900));
+        assert!(hits.load(Ordering::SeqCst) < 6);
+    }
+}
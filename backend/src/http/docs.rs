@@ -28,6 +28,15 @@ pub fn api_docs(api: TransformOpenApi) -> TransformOpenApi {
                 extensions: Default::default(),
             },
         )
+        .security_scheme(
+            "BearerToken",
+            aide::openapi::SecurityScheme::Http {
+                scheme: "bearer".to_string(),
+                bearer_format: None,
+                description: Some("For clients that can't keep cookies. Log in with `tokens: true` to get an access token, send it as `Authorization: Bearer <accessToken>` and exchange the refresh token at `/refresh` once it expires.".to_string()),
+                extensions: Default::default(),
+            },
+        )
 }
 
 pub fn docs_routes() -> ApiRouter {
@@ -1,7 +1,5 @@
 use aide::axum::{routing::get_with, ApiRouter};
-use anyhow::anyhow;
 use schemars::JsonSchema;
-use scraper::Selector;
 
 use axum::extract::State;
 use serde::{Deserialize, Serialize};
@@ -9,16 +7,59 @@ use serde::{Deserialize, Serialize};
 use crate::{
     core::{
         api_response::{ApiResponseTrait, SuccessApiResponse},
-        axum_extractor::{validate_html, ValidatedCookieJar, ValidatedQuery},
+        axum_extractor::{validate_html, CacheControl, ValidatedCookieJar, ValidatedQuery},
         generate_openapi_response::generate_response,
         handler::{online_mis_handler, HttpHandler, RedisHandler},
-        helper::cache_helper,
+        layout::LayoutCheck,
+        selector::{SelectorEntry, SelectorRegistry},
+        ttl::TtlWindows,
+    },
+    http::{
+        features::shared::{
+            relogin::{background_relogin, with_relogin},
+            year_semester_request::YearSemesterRequest,
+        },
+        AppContext, Result,
     },
-    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    (
+        "absen.semester_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "absen.year_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "absen.table",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(4) > td:nth-child(2) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1)",
+    ),
+    (
+        "absen.rows",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(4) > td:nth-child(2) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:not(:first-child):not(:nth-child(2))",
+    ),
+    (
+        "absen.row.kode",
+        "td:nth-child(1)",
+    ),
+    (
+        "absen.row.mata_kuliah",
+        "td:nth-child(2)",
+    ),
+    (
+        "absen.row.minggu",
+        "td:not(:nth-child(1)):not(:nth-child(2)):not(:last-child)",
+    ),
+    (
+        "absen.row.kehadiran",
+        "td:last-child",
+    ),
+];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/absen",
@@ -28,109 +69,118 @@ pub fn endpoint() -> ApiRouter<AppContext> {
 
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
+    cache_control: CacheControl,
     ValidatedQuery(req): ValidatedQuery<YearSemesterRequest>,
 ) -> Result<SuccessApiResponse<AbsenResponse>> {
-    let conn = cache_helper::get_conn(&state.redis_pool).await?;
-
-    let http_handler = HttpHandler {
-        url: format!(
-            "https://online.mis.pens.ac.id/absen.php?valTahun={}&valSemester={}",
-            req.year, req.semester
-        ),
-        session_id: &session_id,
-        client: &state.client,
-    };
-
-    let redis_handler = RedisHandler {
-        key: format!("absen:{}:{}:{}", nrp, req.year, req.semester),
-        redis_pool: conn,
-    };
-
-    let data = online_mis_handler(redis_handler, http_handler, html_extractor).await?;
+    let data = with_relogin(&state, &session, |session_id| {
+        let state = &state;
+        let url = format!(
+            "{}/absen.php?valTahun={}&valSemester={}",
+            state.mis_base_url, req.year, req.semester
+        );
+        let key = format!("absen:{}:{}:{}", session.nrp, req.year, req.semester);
+        let nrp = session.nrp.clone();
+        let relogin = background_relogin(state, &session);
+
+        async move {
+            let http_handler = HttpHandler {
+                url,
+                session_id,
+                nrp,
+                upstream: state.upstream.clone(),
+                relogin: Some(relogin),
+            };
+
+            let redis_handler = RedisHandler {
+                key,
+                cache: state.cache.clone(),
+                flights: state.flights.clone(),
+                ttl: state.cache_ttl.absen,
+                fresh: cache_control.fresh,
+            };
+
+            let selectors = state.selectors.clone();
+            online_mis_handler(redis_handler, http_handler, move |body| {
+                html_extractor(body, &selectors)
+            })
+            .await
+        }
+    })
+    .await?;
 
-    Ok(SuccessApiResponse::new(data))
+    Ok(SuccessApiResponse::new(data.data).with_headers(data.status.headers()))
 }
 
-fn html_extractor(body: String) -> Result<AbsenResponse> {
+fn html_extractor(body: String, selectors: &SelectorRegistry) -> Result<AbsenResponse> {
     let doc = scraper::Html::parse_document(&body);
-    validate_html(&doc)?;
-
-    let semester: Vec<u8> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
-            })
-            .collect()
-    };
-
-    let year: Vec<u16> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
-            })
-            .collect()
-    };
+    validate_html(&doc, selectors)?;
+    let mut layout = LayoutCheck::new(selectors);
+
+    let semester: Vec<u8> = layout
+        .all(&doc, "absen.semester_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let year: Vec<u16> = layout
+        .all(&doc, "absen.year_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
 
     let table: Vec<Table> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(4) > td:nth-child(2) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:not(:first-child):not(:nth-child(2))").map_err(|_| anyhow!("Error parsing selector"))?;
-
-        let kode_selector =
-            Selector::parse("td:nth-child(1)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let matakuliah_selector =
-            Selector::parse("td:nth-child(2)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let minggu_selector =
-            Selector::parse("td:not(:nth-child(1)):not(:nth-child(2)):not(:last-child)")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-        let kehadiran_selector =
-            Selector::parse("td:last-child").map_err(|_| anyhow!("Error parsing selector"))?;
-
-        doc.select(&selector)
-            .map(|e| {
-                let kode: String = e
-                    .select(&kode_selector)
-                    .next()
-                    .map(|e| e.text().collect())
-                    .unwrap_or_default();
-
-                let mata_kuliah: String = e
-                    .select(&matakuliah_selector)
-                    .next()
-                    .map(|e| e.text().collect::<String>().trim().to_owned())
-                    .unwrap_or_default();
-
-                let kehadiran: String = e
-                    .select(&kehadiran_selector)
-                    .next()
-                    .map(|e| e.text().collect::<String>().trim().to_owned())
-                    .unwrap_or_default();
-
-                let minggu: Vec<String> = e
-                    .select(&minggu_selector)
-                    .map(|e| e.text().collect())
-                    .collect();
-
-                Table {
-                    kode,
-                    mata_kuliah,
-                    minggu,
-                    kehadiran,
-                }
-            })
-            .collect()
+        layout.first(&doc, "absen.table")?;
+        let selector = selectors.get("absen.rows")?;
+        let minggu_selector = selectors.get("absen.row.minggu")?;
+
+        let mut table = Vec::new();
+        for e in doc.select(selector) {
+            let kode: String = layout
+                .first(e, "absen.row.kode")?
+                .map(|e| e.text().collect())
+                .unwrap_or_default();
+
+            let mata_kuliah: String = layout
+                .first(e, "absen.row.mata_kuliah")?
+                .map(|e| e.text().collect::<String>().trim().to_owned())
+                .unwrap_or_default();
+
+            let kehadiran: String = layout
+                .first(e, "absen.row.kehadiran")?
+                .map(|e| e.text().collect::<String>().trim().to_owned())
+                .unwrap_or_default();
+
+            let minggu: Vec<String> = e
+                .select(minggu_selector)
+                .map(|e| e.text().collect())
+                .collect();
+
+            table.push(Table {
+                kode,
+                mata_kuliah,
+                minggu,
+                kehadiran,
+            });
+        }
+        table
     };
 
+    layout.finish()?;
+
     Ok(AbsenResponse {
         semester,
         year,
@@ -138,7 +188,7 @@ fn html_extractor(body: String) -> Result<AbsenResponse> {
     })
 }
 
-#[derive(Serialize, Deserialize, Default, JsonSchema)]
+#[derive(Debug, Serialize, Deserialize, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct AbsenResponse {
     pub semester: Vec<u8>,
@@ -146,7 +196,9 @@ struct AbsenResponse {
     pub table: Vec<Table>,
 }
 
-#[derive(Serialize, Deserialize, Default, JsonSchema)]
+impl TtlWindows for AbsenResponse {}
+
+#[derive(Debug, Serialize, Deserialize, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct Table {
     pub kode: String,
@@ -154,3 +206,15 @@ struct Table {
     pub minggu: Vec<String>,
     pub kehadiran: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::html_extractor;
+    use crate::core::helper::test_helper::{fixture, selectors};
+
+    #[test]
+    fn extracts_absen_page() {
+        let res = html_extractor(fixture("absen.html"), &selectors()).unwrap();
+        insta::assert_json_snapshot!(res);
+    }
+}
@@ -0,0 +1,225 @@
+use aide::axum::{routing::get_with, ApiRouter};
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::{macros::offset, Date, Duration, OffsetDateTime, PrimitiveDateTime, Time};
+
+use crate::{
+    core::{
+        api_response::{ApiResponseTrait, SuccessApiResponse},
+        axum_extractor::{CacheControl, ValidatedCookieJar, ValidatedQuery},
+        generate_openapi_response::{generate_ics_response, generate_response},
+        ical::{Calendar, Event, Ics},
+    },
+    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
+};
+
+use super::{
+    frs::{self, FrsResponse},
+    OPENAPI_TAG,
+};
+
+/// How long before a window closes calendars remind of it
+const REMIND_BEFORE_END: Duration = Duration::hours(36);
+
+pub fn endpoint() -> ApiRouter<AppContext> {
+    ApiRouter::new()
+        .api_route(
+            "/deadlines",
+            get_with(handler, generate_response(handler, OPENAPI_TAG, true)),
+        )
+        .api_route(
+            "/deadlines.ics",
+            get_with(ics_handler, generate_ics_response(OPENAPI_TAG, true)),
+        )
+}
+
+/// The FRS windows of the semester that haven't closed yet, the one closing first first
+#[axum::debug_handler]
+async fn handler(
+    session: ValidatedCookieJar,
+    State(state): State<AppContext>,
+    cache_control: CacheControl,
+    ValidatedQuery(req): ValidatedQuery<YearSemesterRequest>,
+) -> Result<SuccessApiResponse<Vec<Deadline>>> {
+    let data = frs::fetch(&state, &session, cache_control.fresh, &req).await?;
+    let today = OffsetDateTime::now_utc().to_offset(offset!(+7)).date();
+
+    Ok(SuccessApiResponse::new(upcoming(&data.data, today)).with_headers(data.status.headers()))
+}
+
+/// The FRS windows of the semester as all day events, reminding of each before it closes
+#[axum::debug_handler]
+async fn ics_handler(
+    session: ValidatedCookieJar,
+    State(state): State<AppContext>,
+    cache_control: CacheControl,
+    ValidatedQuery(req): ValidatedQuery<YearSemesterRequest>,
+) -> Result<Ics> {
+    let data = frs::fetch(&state, &session, cache_control.fresh, &req).await?;
+    let calendar = calendar(&data.data, &session.nrp, &req);
+
+    Ok(Ics::new(calendar.render(OffsetDateTime::now_utc())).with_headers(data.status.headers()))
+}
+
+#[derive(Debug, Serialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum DeadlineKind {
+    /// Filling in the FRS
+    Pengisian,
+    /// Changing the courses picked
+    Perubahan,
+    /// Dropping courses
+    Drop,
+}
+
+impl DeadlineKind {
+    fn summary(self) -> &'static str {
+        match self {
+            Self::Pengisian => "Pengisian FRS",
+            Self::Perubahan => "Perubahan FRS",
+            Self::Drop => "Drop mata kuliah",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Deadline {
+    pub kind: DeadlineKind,
+    /// The first day of the window, as `YYYY-MM-DD`
+    pub from: String,
+    /// The last day of the window, as `YYYY-MM-DD`
+    pub to: String,
+    /// Whether the window is open today
+    pub open: bool,
+    /// Days until the last day of the window, 0 on the last day
+    pub days_left: i64,
+}
+
+/// The windows with dates Online MIS shows, skipping the ones that can't be parsed
+fn windows(frs: &FrsResponse) -> Vec<(DeadlineKind, Date, Date)> {
+    let tanggal = &frs.tanggal_penting;
+
+    [
+        (DeadlineKind::Pengisian, &tanggal.pengisian),
+        (DeadlineKind::Perubahan, &tanggal.perubahan),
+        (DeadlineKind::Drop, &tanggal.drop),
+    ]
+    .into_iter()
+    .filter_map(|(kind, range)| match range.dates() {
+        Some((from, to)) => Some((kind, from, to)),
+        None => {
+            tracing::debug!(
+                "Skipping {kind:?}, '{}' to '{}' can't be parsed",
+                range.from,
+                range.to
+            );
+            None
+        }
+    })
+    .collect()
+}
+
+fn upcoming(frs: &FrsResponse, today: Date) -> Vec<Deadline> {
+    let mut deadlines: Vec<_> = windows(frs)
+        .into_iter()
+        .filter(|(_, _, to)| *to >= today)
+        .map(|(kind, from, to)| Deadline {
+            kind,
+            from: from.to_string(),
+            to: to.to_string(),
+            open: from <= today,
+            days_left: (to - today).whole_days(),
+        })
+        .collect();
+    deadlines.sort_by_key(|deadline| deadline.days_left);
+
+    deadlines
+}
+
+pub(crate) fn calendar(frs: &FrsResponse, nrp: &str, semester: &YearSemesterRequest) -> Calendar {
+    let mut calendar = Calendar::new("Tanggal Penting FRS");
+
+    for (kind, from, to) in windows(frs) {
+        // Keeps the same uid when the dates move, so calendars update the event
+        let digest = Sha256::new()
+            .chain_update(nrp)
+            .chain_update(format!("{}:{}:{kind:?}", semester.year, semester.semester))
+            .finalize();
+        let uid: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+
+        calendar.push(Event {
+            uid: format!("deadline-{uid}@online-mis-wrapper"),
+            summary: kind.summary().to_owned(),
+            location: None,
+            description: Some(format!("Sampai {to}")),
+            start: PrimitiveDateTime::new(from, Time::MIDNIGHT),
+            end: PrimitiveDateTime::new(to, Time::MIDNIGHT),
+            all_day: true,
+            weekly_until: None,
+            remind_before_end: Some(REMIND_BEFORE_END),
+        });
+    }
+
+    calendar
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::{calendar, upcoming, DeadlineKind};
+    use crate::http::features::{
+        academic::frs::{DateRange, FrsResponse, TanggalPenting},
+        shared::year_semester_request::YearSemesterRequest,
+    };
+
+    fn frs() -> FrsResponse {
+        let range = |from: &str, to: &str| DateRange {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            iso: None,
+        };
+
+        FrsResponse {
+            tanggal_penting: TanggalPenting {
+                pengisian: range("3 Februari 2025", "14 Februari 2025"),
+                perubahan: range("17 Februari 2025", "21 Februari 2025"),
+                drop: range("", ""),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lists_windows_not_closed_yet() {
+        let deadlines = upcoming(&frs(), date!(2025 - 02 - 14));
+        assert_eq!(deadlines.len(), 2);
+        assert_eq!(deadlines[0].kind, DeadlineKind::Pengisian);
+        assert_eq!(deadlines[0].to, "2025-02-14");
+        assert!(deadlines[0].open);
+        assert_eq!(deadlines[0].days_left, 0);
+        assert!(!deadlines[1].open);
+        assert_eq!(deadlines[1].days_left, 7);
+
+        assert!(upcoming(&frs(), date!(2025 - 02 - 22)).is_empty());
+    }
+
+    #[test]
+    fn turns_windows_into_all_day_events() {
+        let semester = YearSemesterRequest {
+            year: 2024,
+            semester: 2,
+        };
+        let cal = calendar(&frs(), "3122600001", &semester);
+
+        let events = cal.events();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.all_day));
+        assert_eq!(events[0].start.date(), date!(2025 - 02 - 03));
+        assert_eq!(events[0].end.date(), date!(2025 - 02 - 14));
+        assert_ne!(events[0].uid, events[1].uid);
+    }
+}
@@ -1,23 +1,106 @@
 use aide::axum::{routing::get_with, ApiRouter};
-use anyhow::anyhow;
 use axum::extract::State;
 use schemars::JsonSchema;
-use scraper::Selector;
 use serde::{Deserialize, Serialize};
+use time::Date;
 
 use crate::{
     core::{
         api_response::{ApiResponseTrait, SuccessApiResponse},
-        axum_extractor::{validate_html, ValidatedCookieJar, ValidatedQuery},
+        axum_extractor::{validate_html, CacheControl, ValidatedCookieJar, ValidatedQuery},
+        datetime::{self, IsoDateRange, IsoTimeRange},
         generate_openapi_response::generate_response,
-        handler::{online_mis_handler, HttpHandler, RedisHandler},
-        helper::cache_helper,
+        handler::{online_mis_handler, Cached, HttpHandler, RedisHandler},
+        layout::LayoutCheck,
+        selector::{SelectorEntry, SelectorRegistry},
+        ttl::TtlWindows,
+    },
+    http::{
+        features::shared::{
+            relogin::{background_relogin, with_relogin},
+            year_semester_request::YearSemesterRequest,
+        },
+        AppContext, Result,
     },
-    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    (
+        "frs.semester_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "frs.year_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "frs.dosen",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(5) > td:nth-child(2) > font:nth-child(1)",
+    ),
+    (
+        "frs.sks",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(6) > td:nth-child(2) > font:nth-child(1)",
+    ),
+    (
+        "frs.ip",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(7) > td:nth-child(2) > font:nth-child(1)",
+    ),
+    (
+        "frs.tanggal_pengisian",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(8) > td:nth-child(2) > font:nth-child(1) > i:nth-child(2)",
+    ),
+    (
+        "frs.tanggal_perubahan",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(8) > td:nth-child(2) > font:nth-child(1) > i:nth-child(4)",
+    ),
+    (
+        "frs.tanggal_drop",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(8) > td:nth-child(2) > font:nth-child(1) > i:nth-child(6)",
+    ),
+    (
+        "frs.table",
+        "table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(10) > td:nth-child(2) > table:nth-child(1)",
+    ),
+    (
+        "frs.rows",
+        "table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(10) > td:nth-child(2) > table:nth-child(1) > tbody > tr:not(:first-child):not(:last-child)",
+    ),
+    (
+        "frs.row.id",
+        "td:nth-child(1) a",
+    ),
+    (
+        "frs.row.kode",
+        "td:nth-child(3) font",
+    ),
+    (
+        "frs.row.group",
+        "td:nth-child(4) font",
+    ),
+    (
+        "frs.row.mata_kuliah",
+        "td:nth-child(5) font",
+    ),
+    (
+        "frs.row.dosen",
+        "td:nth-child(6) font",
+    ),
+    (
+        "frs.row.sks",
+        "td:nth-child(7) font",
+    ),
+    (
+        "frs.row.kelas",
+        "td:nth-child(8) font",
+    ),
+    (
+        "frs.row.disetujui",
+        "td:nth-child(9) font strong",
+    ),
+];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/frs",
@@ -27,76 +110,96 @@ pub fn endpoint() -> ApiRouter<AppContext> {
 
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
+    cache_control: CacheControl,
     ValidatedQuery(req): ValidatedQuery<YearSemesterRequest>,
 ) -> Result<SuccessApiResponse<FrsResponse>> {
-    let conn = cache_helper::get_conn(&state.redis_pool).await?;
-
-    let http_handler = HttpHandler {
-        url: format!(
-            "https://online.mis.pens.ac.id/FRS_mbkm.php?valTahun={}&valSemester={}",
-            req.year, req.semester
-        ),
-        session_id: &session_id,
-        client: &state.client,
-    };
+    let data = fetch(&state, &session, cache_control.fresh, &req).await?;
 
-    let redis_handler = RedisHandler {
-        key: format!("frs:{}:{}:{}", nrp, req.year, req.semester),
-        redis_pool: conn,
-    };
-
-    Ok(SuccessApiResponse::new(
-        online_mis_handler(redis_handler, http_handler, html_extractor).await?,
-    ))
+    Ok(SuccessApiResponse::new(data.data).with_headers(data.status.headers()))
 }
 
-fn html_extractor(body: String) -> Result<FrsResponse> {
-    let doc = scraper::Html::parse_document(&body);
-    validate_html(&doc)?;
-
-    let semester: Vec<u8> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
+/// The FRS of a semester, from the cache or Online MIS
+pub(crate) async fn fetch(
+    state: &AppContext,
+    session: &ValidatedCookieJar,
+    fresh: bool,
+    req: &YearSemesterRequest,
+) -> Result<Cached<FrsResponse>> {
+    with_relogin(state, session, |session_id| {
+        let url = format!(
+            "{}/FRS_mbkm.php?valTahun={}&valSemester={}",
+            state.mis_base_url, req.year, req.semester
+        );
+        let key = format!("frs:{}:{}:{}", session.nrp, req.year, req.semester);
+        let nrp = session.nrp.clone();
+        let relogin = background_relogin(state, session);
+
+        async move {
+            let http_handler = HttpHandler {
+                url,
+                session_id,
+                nrp,
+                upstream: state.upstream.clone(),
+                relogin: Some(relogin),
+            };
+
+            let redis_handler = RedisHandler {
+                key,
+                cache: state.cache.clone(),
+                flights: state.flights.clone(),
+                ttl: state.cache_ttl.frs,
+                fresh,
+            };
+
+            let selectors = state.selectors.clone();
+            online_mis_handler(redis_handler, http_handler, move |body| {
+                html_extractor(body, &selectors)
             })
-            .collect()
-    };
+            .await
+        }
+    })
+    .await
+}
 
-    let year: Vec<u16> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
-            })
-            .collect()
-    };
+fn html_extractor(body: String, selectors: &SelectorRegistry) -> Result<FrsResponse> {
+    let doc = scraper::Html::parse_document(&body);
+    validate_html(&doc, selectors)?;
+    let mut layout = LayoutCheck::new(selectors);
+
+    let semester: Vec<u8> = layout
+        .all(&doc, "frs.semester_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let year: Vec<u16> = layout
+        .all(&doc, "frs.year_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
 
-    let dosen = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(5) > td:nth-child(2) > font:nth-child(1)").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
-            .map(|e| e.inner_html())
-            .unwrap_or_default()
-            .trim()
-            .to_owned()
-    };
+    let dosen = layout
+        .first(&doc, "frs.dosen")?
+        .map(|e| e.inner_html().trim().to_owned())
+        .unwrap_or_default();
 
     let sks = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(6) > td:nth-child(2) > font:nth-child(1)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let data: Vec<String> = doc
-            .select(&selector)
-            .next()
+        let data: Vec<String> = layout
+            .first(&doc, "frs.sks")?
             .map(|e| {
                 e.inner_html()
                     .split(" ")
@@ -105,27 +208,21 @@ fn html_extractor(body: String) -> Result<FrsResponse> {
             })
             .unwrap_or_default();
 
-        let empty = &"".to_owned();
+        let batas = data.get(1).and_then(|s| s.parse().ok());
+        let sisa = data.get(3).and_then(|s| s.parse().ok());
+        if !data.is_empty() && (batas.is_none() || sisa.is_none()) {
+            layout.mark("frs.sks");
+        }
 
         SKS {
-            batas: data
-                .get(1)
-                .unwrap_or_else(|| empty)
-                .parse()
-                .unwrap_or_default(),
-            sisa: data
-                .get(3)
-                .unwrap_or_else(|| empty)
-                .parse()
-                .unwrap_or_default(),
+            batas: batas.unwrap_or_default(),
+            sisa: sisa.unwrap_or_default(),
         }
     };
 
     let ip = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(7) > td:nth-child(2) > font:nth-child(1)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let data: Vec<String> = doc
-            .select(&selector)
-            .next()
+        let data: Vec<String> = layout
+            .first(&doc, "frs.ip")?
             .map(|e| {
                 e.inner_html()
                     .split(" ")
@@ -134,187 +231,141 @@ fn html_extractor(body: String) -> Result<FrsResponse> {
             })
             .unwrap_or_default();
 
-        let empty = &"".to_owned();
+        let ipk = data.get(1).and_then(|s| s.parse().ok());
+        let ips = data.get(3).and_then(|s| s.parse().ok());
+        if !data.is_empty() && (ipk.is_none() || ips.is_none()) {
+            layout.mark("frs.ip");
+        }
 
         IP {
-            ipk: data
-                .get(1)
-                .unwrap_or_else(|| empty)
-                .parse()
-                .unwrap_or_default(),
-            ips: data
-                .get(3)
-                .unwrap_or_else(|| empty)
-                .parse()
-                .unwrap_or_default(),
+            ipk: ipk.unwrap_or_default(),
+            ips: ips.unwrap_or_default(),
         }
     };
 
     let tanggal_penting = {
-        let tanggal_pengisian: Vec<String> = {
-            let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(8) > td:nth-child(2) > font:nth-child(1) > i:nth-child(2)").map_err(|_| anyhow!("Error parsing selector"))?;
-            doc.select(&selector)
-                .next()
+        let mut date_range = |name: &'static str| -> Result<DateRange> {
+            let data: Vec<String> = layout
+                .first(&doc, name)?
                 .map(|e| e.inner_html())
                 .unwrap_or_default()
                 .split("sd")
                 .map(|e| e.trim().to_owned())
-                .collect()
-        };
+                .collect();
 
-        let tanggal_perubahan: Vec<String> = {
-            let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(8) > td:nth-child(2) > font:nth-child(1) > i:nth-child(4)").map_err(|_| anyhow!("Error parsing selector"))?;
-            doc.select(&selector)
-                .next()
-                .map(|e| e.inner_html())
-                .unwrap_or_default()
-                .split("sd")
-                .map(|e| e.trim().to_owned())
-                .collect()
-        };
+            let from = data.first().cloned().unwrap_or_default();
+            let to = data.get(1).cloned().unwrap_or_default();
+            let iso = datetime::parse_tanggal(&from)
+                .zip(datetime::parse_tanggal(&to))
+                .map(IsoDateRange::new);
 
-        let tanggal_drop: Vec<String> = {
-            let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(8) > td:nth-child(2) > font:nth-child(1) > i:nth-child(6)").map_err(|_| anyhow!("Error parsing selector"))?;
-            doc.select(&selector)
-                .next()
-                .map(|e| e.inner_html())
-                .unwrap_or_default()
-                .split("sd")
-                .map(|e| e.trim().to_owned())
-                .collect()
+            Ok(DateRange { from, to, iso })
         };
 
-        let empty = &"".to_owned();
-
         TanggalPenting {
-            pengisian: DateRange {
-                from: tanggal_pengisian
-                    .get(0)
-                    .unwrap_or_else(|| empty)
-                    .to_string(),
-                to: tanggal_pengisian
-                    .get(1)
-                    .unwrap_or_else(|| empty)
-                    .to_string(),
-            },
-            perubahan: DateRange {
-                from: tanggal_perubahan
-                    .get(0)
-                    .unwrap_or_else(|| empty)
-                    .to_string(),
-                to: tanggal_perubahan
-                    .get(1)
-                    .unwrap_or_else(|| empty)
-                    .to_string(),
-            },
-            drop: DateRange {
-                from: tanggal_drop.get(0).unwrap_or_else(|| empty).to_string(),
-                to: tanggal_drop.get(1).unwrap_or_else(|| empty).to_string(),
-            },
+            pengisian: date_range("frs.tanggal_pengisian")?,
+            perubahan: date_range("frs.tanggal_perubahan")?,
+            drop: date_range("frs.tanggal_drop")?,
         }
     };
 
     let table: Vec<Table> = {
-        let selector = Selector::parse("table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(10) > td:nth-child(2) > table:nth-child(1) > tbody > tr:not(:first-child):not(:last-child)").map_err(|_| anyhow!("Error parsing selector"))?;
-
-        let id_selector =
-            Selector::parse("td:nth-child(1) a").map_err(|_| anyhow!("Error parsing selector"))?;
-        let kode_selector = Selector::parse("td:nth-child(3) font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let group_selector = Selector::parse("td:nth-child(4) font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let matkul_selector = Selector::parse("td:nth-child(5) font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let dosen_selector = Selector::parse("td:nth-child(6) font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let sks_selector = Selector::parse("td:nth-child(7) font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let kelas_selector = Selector::parse("td:nth-child(8) font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let disetujui_selector = Selector::parse("td:nth-child(9) font strong")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-
-        doc.select(&selector)
-            .map(|e| {
-                let id = e
-                    .select(&id_selector)
-                    .next()
-                    .map(|e| e.attr("href").unwrap_or_default().to_owned())
-                    .unwrap_or_default()
-                    .split("=")
+        // The course table is mandatory, its rows are not since a student may not have
+        // picked any course yet
+        layout.first(&doc, "frs.table")?;
+        let selector = selectors.get("frs.rows")?;
+
+        let id_selector = selectors.get("frs.row.id")?;
+        let group_selector = selectors.get("frs.row.group")?;
+        let dosen_selector = selectors.get("frs.row.dosen")?;
+        let sks_selector = selectors.get("frs.row.sks")?;
+        let kelas_selector = selectors.get("frs.row.kelas")?;
+        let disetujui_selector = selectors.get("frs.row.disetujui")?;
+
+        let mut table = Vec::new();
+        for e in doc.select(selector) {
+            let id = e
+                .select(id_selector)
+                .next()
+                .map(|e| e.attr("href").unwrap_or_default().to_owned())
+                .unwrap_or_default()
+                .split("=")
+                .last()
+                .unwrap_or_default()
+                .to_owned();
+            let kode = layout
+                .first(e, "frs.row.kode")?
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+            let group = e
+                .select(group_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+            let dosen = e
+                .select(dosen_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+            let sks = e
+                .select(sks_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+            let kelas = e
+                .select(kelas_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+            let disetujui = e
+                .select(disetujui_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+            let mata_kuliah = {
+                let data: Vec<&str> = layout
+                    .first(e, "frs.row.mata_kuliah")?
+                    .map(|e| e.text().collect())
+                    .unwrap_or_default();
+
+                let jam = data
+                    .get(2)
+                    .unwrap_or(&"")
+                    .split(" : ")
                     .last()
                     .unwrap_or_default()
-                    .to_owned();
-                let kode = e
-                    .select(&kode_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-                let group = e
-                    .select(&group_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-                let dosen = e
-                    .select(&dosen_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-                let sks = e
-                    .select(&sks_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-                let kelas = e
-                    .select(&kelas_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-                let disetujui = e
-                    .select(&disetujui_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-                let mata_kuliah = {
-                    let data: Vec<&str> = e
-                        .select(&matkul_selector)
-                        .next()
-                        .map(|e| e.text().collect())
-                        .unwrap_or_default();
-
-                    MataKuliah {
-                        nama: data.get(0).unwrap_or_else(|| &"").to_string(),
-                        hari: data
-                            .get(1)
-                            .unwrap_or_else(|| &"")
-                            .split(" : ")
-                            .last()
-                            .unwrap_or_default()
-                            .to_string(),
-                        jam: data
-                            .get(2)
-                            .unwrap_or_else(|| &"")
-                            .split(" : ")
-                            .last()
-                            .unwrap_or_default()
-                            .to_string(),
-                    }
-                };
-
-                Table {
-                    id,
-                    kode,
-                    group,
-                    mata_kuliah,
-                    dosen,
-                    sks,
-                    kelas,
-                    disetujui,
+                    .to_string();
+
+                MataKuliah {
+                    nama: data.first().unwrap_or(&"").to_string(),
+                    hari: data
+                        .get(1)
+                        .unwrap_or(&"")
+                        .split(" : ")
+                        .last()
+                        .unwrap_or_default()
+                        .to_string(),
+                    jam_iso: datetime::parse_jam_range(&jam).map(IsoTimeRange::new),
+                    jam,
                 }
-            })
-            .collect()
+            };
+
+            table.push(Table {
+                id,
+                kode,
+                group,
+                mata_kuliah,
+                dosen,
+                sks,
+                kelas,
+                disetujui,
+            });
+        }
+        table
     };
 
+    layout.finish()?;
+
     Ok(FrsResponse {
         semester,
         year,
@@ -326,9 +377,9 @@ fn html_extractor(body: String) -> Result<FrsResponse> {
     })
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
-struct FrsResponse {
+pub(crate) struct FrsResponse {
     pub semester: Vec<u8>,
     pub year: Vec<u16>,
     pub dosen: String,
@@ -338,9 +389,24 @@ struct FrsResponse {
     pub table: Vec<Table>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+impl TtlWindows for FrsResponse {
+    fn ttl_windows(&self) -> Vec<(Date, Date)> {
+        let TanggalPenting {
+            pengisian,
+            perubahan,
+            drop,
+        } = &self.tanggal_penting;
+
+        [pengisian, perubahan, drop]
+            .into_iter()
+            .filter_map(DateRange::dates)
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
-struct Table {
+pub(crate) struct Table {
     pub id: String,
     pub kode: String,
     pub group: String,
@@ -351,34 +417,92 @@ struct Table {
     pub disetujui: String,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
-struct MataKuliah {
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MataKuliah {
     pub nama: String,
     pub hari: String,
+    /// As Online MIS shows it, e.g. `08:00-10:30`
     pub jam: String,
+    /// `jam` as ISO 8601 times, unless it can't be parsed
+    pub jam_iso: Option<IsoTimeRange>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
-struct DateRange {
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+pub(crate) struct DateRange {
+    /// As Online MIS shows it, e.g. `3 Februari 2025`
     pub from: String,
     pub to: String,
+    /// `from` and `to` as ISO 8601 dates, unless either can't be parsed
+    pub iso: Option<IsoDateRange>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
-struct TanggalPenting {
+impl DateRange {
+    /// The first and last day of the range, unless either can't be parsed
+    pub fn dates(&self) -> Option<(Date, Date)> {
+        datetime::parse_tanggal(&self.from).zip(datetime::parse_tanggal(&self.to))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+pub(crate) struct TanggalPenting {
     pub pengisian: DateRange,
     pub perubahan: DateRange,
     pub drop: DateRange,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
-struct SKS {
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) struct SKS {
     pub batas: i32,
     pub sisa: i32,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
-struct IP {
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) struct IP {
     pub ipk: f32,
     pub ips: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::html_extractor;
+    use crate::core::{
+        error::Error,
+        helper::test_helper::{fixture, selectors},
+        ttl::TtlWindows,
+    };
+
+    #[test]
+    fn extracts_frs_page() {
+        let res = html_extractor(fixture("FRS_mbkm.html"), &selectors()).unwrap();
+        insta::assert_json_snapshot!(res);
+    }
+
+    #[test]
+    fn derives_ttl_windows_from_tanggal_penting() {
+        let res = html_extractor(fixture("FRS_mbkm.html"), &selectors()).unwrap();
+        assert_eq!(
+            res.ttl_windows(),
+            [
+                (date!(2025 - 02 - 03), date!(2025 - 02 - 14)),
+                (date!(2025 - 02 - 17), date!(2025 - 02 - 21)),
+                (date!(2025 - 03 - 03), date!(2025 - 03 - 07)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_layout_change_on_shell_page() {
+        let err = html_extractor(fixture("mFRS_mbkm.html"), &selectors()).unwrap_err();
+        let Error::LayoutChanged(cause) = err else {
+            panic!("expected a layout change, got {err:?}");
+        };
+        assert_eq!(cause.endpoint, "frs");
+        assert!(cause.missing.contains(&"frs.dosen".to_owned()));
+        assert!(cause.missing.contains(&"frs.table".to_owned()));
+    }
+}
@@ -0,0 +1,285 @@
+use aide::axum::{routing::get_with, ApiRouter};
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::{Date, Duration, Month, OffsetDateTime, PrimitiveDateTime};
+
+use crate::{
+    core::{
+        api_response::ValidationErrorCause,
+        axum_extractor::{CacheControl, ValidatedCookieJar, ValidatedQuery},
+        datetime,
+        error::Error,
+        generate_openapi_response::generate_ics_response,
+        ical::{Calendar, Event, Ics},
+    },
+    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
+};
+
+use super::{
+    jadwal_kuliah::{self, JadwalKuliahResponse},
+    OPENAPI_TAG,
+};
+
+pub fn endpoint() -> ApiRouter<AppContext> {
+    ApiRouter::new().api_route(
+        "/jadwal.ics",
+        get_with(handler, generate_ics_response(OPENAPI_TAG, true)),
+    )
+}
+
+/// The weekly class schedule as an iCalendar file, each class repeating every week of
+/// the semester
+#[axum::debug_handler]
+async fn handler(
+    session: ValidatedCookieJar,
+    State(state): State<AppContext>,
+    cache_control: CacheControl,
+    ValidatedQuery(req): ValidatedQuery<JadwalIcsRequest>,
+) -> Result<Ics> {
+    let (start, end) = req.bounds()?;
+    let semester = YearSemesterRequest {
+        year: req.year,
+        semester: req.semester,
+    };
+    let data = jadwal_kuliah::fetch(&state, &session, cache_control.fresh, &semester).await?;
+
+    let calendar = calendar(&data.data, &session.nrp, &semester, start, end);
+    Ok(Ics::new(calendar.render(OffsetDateTime::now_utc())).with_headers(data.status.headers()))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+pub(super) struct JadwalIcsRequest {
+    #[schemars(range(min = 1988))]
+    pub year: u16,
+    /// 1 runs from August to January, 2 from February to July of the next year
+    #[schemars(range(min = 1, max = 2))]
+    pub semester: u8,
+    /// The first day of classes as `YYYY-MM-DD`, when it isn't the 1st of the month the
+    /// semester starts in
+    #[schemars(regex(pattern = r"^\d{4}-\d{2}-\d{2}$"))]
+    pub start: Option<String>,
+    /// The last day of classes as `YYYY-MM-DD`, when it isn't the last day of the month
+    /// the semester ends in
+    #[schemars(regex(pattern = r"^\d{4}-\d{2}-\d{2}$"))]
+    pub end: Option<String>,
+}
+
+impl JadwalIcsRequest {
+    /// The first and last day of the semester, unless overridden
+    pub fn bounds(&self) -> Result<(Date, Date)> {
+        let (default_start, default_end) = semester_bounds(self.year, self.semester)?;
+        let start = match &self.start {
+            Some(start) => parse_date("/start", start)?,
+            None => default_start,
+        };
+        let end = match &self.end {
+            Some(end) => parse_date("/end", end)?,
+            None => default_end,
+        };
+
+        if end < start {
+            return Err(Error::Validation(vec![ValidationErrorCause {
+                field: "/end".to_owned(),
+                message: "Must not be before the start".to_owned(),
+                received_value: end.to_string(),
+            }]));
+        }
+
+        Ok((start, end))
+    }
+}
+
+/// The odd semester of `year` runs from August to January, the even one from February to
+/// July of the next year
+pub(crate) fn semester_bounds(year: u16, semester: u8) -> Result<(Date, Date)> {
+    let year = i32::from(year);
+    let (start, end) = match semester {
+        1 => (
+            Date::from_calendar_date(year, Month::August, 1),
+            Date::from_calendar_date(year + 1, Month::January, 31),
+        ),
+        _ => (
+            Date::from_calendar_date(year + 1, Month::February, 1),
+            Date::from_calendar_date(year + 1, Month::July, 31),
+        ),
+    };
+
+    Ok((
+        start.map_err(|e| anyhow::anyhow!(e))?,
+        end.map_err(|e| anyhow::anyhow!(e))?,
+    ))
+}
+
+fn parse_date(field: &str, value: &str) -> Result<Date> {
+    datetime::parse_iso_date(value).ok_or_else(|| {
+        Error::Validation(vec![ValidationErrorCause {
+            field: field.to_owned(),
+            message: "Invalid date, expected YYYY-MM-DD".to_owned(),
+            received_value: value.to_owned(),
+        }])
+    })
+}
+
+/// Turns the schedule into events repeating every week from `start` until `end`
+pub(crate) fn calendar(
+    jadwal: &JadwalKuliahResponse,
+    nrp: &str,
+    semester: &YearSemesterRequest,
+    start: Date,
+    end: Date,
+) -> Calendar {
+    let name = match jadwal.kelas.trim() {
+        "" => "Jadwal Kuliah".to_owned(),
+        kelas => format!("Jadwal Kuliah {kelas}"),
+    };
+    let mut calendar = Calendar::new(name);
+
+    for (weekday, classes) in jadwal.table.days() {
+        let days_until =
+            (weekday.number_days_from_monday() + 7 - start.weekday().number_days_from_monday()) % 7;
+        let first = start + Duration::days(days_until.into());
+        if first > end {
+            continue;
+        }
+
+        for class in classes {
+            let Some((from, to)) = datetime::parse_jam_range(&class.jam) else {
+                tracing::debug!(
+                    "Skipping '{}', its time '{}' can't be parsed",
+                    class.nama,
+                    class.jam
+                );
+                continue;
+            };
+
+            // The same class keeps the same uid, so calendars update it in place
+            let digest = Sha256::new()
+                .chain_update(nrp)
+                .chain_update(format!("{}:{}", semester.year, semester.semester))
+                .chain_update(weekday.to_string())
+                .chain_update(&class.nama)
+                .chain_update(&class.jam)
+                .finalize();
+            let uid: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+
+            calendar.push(Event {
+                uid: format!("jadwal-{uid}@online-mis-wrapper"),
+                summary: class.nama.clone(),
+                location: Some(class.ruangan.clone()).filter(|ruangan| !ruangan.is_empty()),
+                description: Some(class.dosen.clone())
+                    .filter(|dosen| !dosen.is_empty())
+                    .map(|dosen| format!("Dosen: {dosen}")),
+                start: PrimitiveDateTime::new(first, from),
+                end: PrimitiveDateTime::new(first, to),
+                all_day: false,
+                weekly_until: Some(end),
+                remind_before_end: None,
+            });
+        }
+    }
+
+    calendar
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, datetime};
+
+    use super::{calendar, semester_bounds, JadwalIcsRequest};
+    use crate::{
+        core::error::Error,
+        http::features::{
+            academic::jadwal_kuliah::{JadwalKuliahResponse, Matakuliah, Table},
+            shared::year_semester_request::YearSemesterRequest,
+        },
+    };
+
+    #[test]
+    fn bounds_semesters() {
+        assert_eq!(
+            semester_bounds(2024, 1).unwrap(),
+            (date!(2024 - 08 - 01), date!(2025 - 01 - 31))
+        );
+        assert_eq!(
+            semester_bounds(2024, 2).unwrap(),
+            (date!(2025 - 02 - 01), date!(2025 - 07 - 31))
+        );
+
+        let req = |start: Option<&str>, end: Option<&str>| JadwalIcsRequest {
+            year: 2024,
+            semester: 1,
+            start: start.map(str::to_owned),
+            end: end.map(str::to_owned),
+        };
+        assert_eq!(
+            req(Some("2024-09-02"), None).bounds().unwrap(),
+            (date!(2024 - 09 - 02), date!(2025 - 01 - 31))
+        );
+        for (start, end) in [
+            (Some("2024-02-30"), None),
+            (None, Some("2024-13-01")),
+            (Some("2024-09-02"), Some("2024-09-01")),
+        ] {
+            assert!(matches!(
+                req(start, end).bounds(),
+                Err(Error::Validation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn repeats_classes_weekly_from_their_first_day() {
+        let class = |nama: &str, jam: &str| Matakuliah {
+            nama: nama.to_owned(),
+            dosen: "Dosen Satu, S.Kom.".to_owned(),
+            jam: jam.to_owned(),
+            jam_iso: None,
+            ruangan: "C-203".to_owned(),
+        };
+        let jadwal = JadwalKuliahResponse {
+            kelas: "3 D4 IT A".to_owned(),
+            table: Table {
+                senin: vec![class("Pemrograman Web", "08:00-10:30")],
+                kamis: vec![class("Kecerdasan Buatan", "10:30-13:00")],
+                jumat: vec![class("Tanpa Jam", "")],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let semester = YearSemesterRequest {
+            year: 2024,
+            semester: 1,
+        };
+
+        // 2024-08-01 is a thursday
+        let cal = calendar(
+            &jadwal,
+            "3122600001",
+            &semester,
+            date!(2024 - 08 - 01),
+            date!(2025 - 01 - 31),
+        );
+        let events = cal.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].start, datetime!(2024-08-05 08:00));
+        assert_eq!(events[0].end, datetime!(2024-08-05 10:30));
+        assert_eq!(events[1].start, datetime!(2024-08-01 10:30));
+        assert_eq!(events[0].location.as_deref(), Some("C-203"));
+        assert_eq!(
+            events[0].description.as_deref(),
+            Some("Dosen: Dosen Satu, S.Kom.")
+        );
+
+        let again = calendar(
+            &jadwal,
+            "3122600001",
+            &semester,
+            date!(2024 - 08 - 01),
+            date!(2025 - 01 - 31),
+        );
+        assert_eq!(events[0].uid, again.events()[0].uid);
+        assert_ne!(events[0].uid, events[1].uid);
+    }
+}
@@ -1,23 +1,59 @@
 use aide::axum::{routing::get_with, ApiRouter};
-use anyhow::anyhow;
 use axum::extract::State;
 use schemars::JsonSchema;
-use scraper::{selectable::Selectable, Selector};
+use scraper::selectable::Selectable;
 use serde::{Deserialize, Serialize};
+use time::Weekday;
 
 use crate::{
     core::{
         api_response::{ApiResponseTrait, SuccessApiResponse},
-        axum_extractor::{validate_html, ValidatedCookieJar, ValidatedQuery},
+        axum_extractor::{validate_html, CacheControl, ValidatedCookieJar, ValidatedQuery},
+        datetime::{self, IsoTimeRange},
         generate_openapi_response::generate_response,
-        handler::{online_mis_handler, HttpHandler, RedisHandler},
-        helper::cache_helper,
+        handler::{online_mis_handler, Cached, HttpHandler, RedisHandler},
+        layout::LayoutCheck,
+        selector::{SelectorEntry, SelectorRegistry},
+        ttl::TtlWindows,
+    },
+    http::{
+        features::shared::{
+            relogin::{background_relogin, with_relogin},
+            year_semester_request::YearSemesterRequest,
+        },
+        AppContext, Result,
     },
-    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    (
+        "jadwal.semester_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "jadwal.year_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "jadwal.jam_istirahat",
+        "table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(4) > td:nth-child(2) > table > tbody > tr > td > table:nth-child(2) > tbody > tr:nth-child(9) > td > strong",
+    ),
+    (
+        "jadwal.kelas",
+        "table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(4) > td:nth-child(2) > table > tbody > tr > td > table:nth-child(1) > tbody > tr > td > div > b",
+    ),
+    (
+        "jadwal.days",
+        "body > table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(4) > td:nth-child(2) > table > tbody > tr > td > table:nth-child(2) > tbody > tr:not(:first-child):not(:last-child)",
+    ),
+    (
+        "jadwal.day.mata_kuliah",
+        "tr:nth-child(odd) > td:nth-child(2) > div",
+    ),
+];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/jadwal",
@@ -27,104 +63,125 @@ pub fn endpoint() -> ApiRouter<AppContext> {
 
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
+    cache_control: CacheControl,
     ValidatedQuery(req): ValidatedQuery<YearSemesterRequest>,
 ) -> Result<SuccessApiResponse<JadwalKuliahResponse>> {
-    let conn = cache_helper::get_conn(&state.redis_pool).await?;
-
-    let http_handler = HttpHandler {
-        url: format!(
-            "https://online.mis.pens.ac.id/jadwal_kul.php?valTahun={}&valSemester={}",
-            req.year, req.semester
-        ),
-        session_id: &session_id,
-        client: &state.client,
-    };
+    let data = fetch(&state, &session, cache_control.fresh, &req).await?;
 
-    let redis_handler = RedisHandler {
-        key: format!("jadwal:{}:{}:{}", nrp, req.year, req.semester),
-        redis_pool: conn,
-    };
+    Ok(SuccessApiResponse::new(data.data).with_headers(data.status.headers()))
+}
+
+/// The schedule of a semester, from the cache or Online MIS
+pub(crate) async fn fetch(
+    state: &AppContext,
+    session: &ValidatedCookieJar,
+    fresh: bool,
+    req: &YearSemesterRequest,
+) -> Result<Cached<JadwalKuliahResponse>> {
+    with_relogin(state, session, |session_id| {
+        let url = format!(
+            "{}/jadwal_kul.php?valTahun={}&valSemester={}",
+            state.mis_base_url, req.year, req.semester
+        );
+        let key = format!("jadwal:{}:{}:{}", session.nrp, req.year, req.semester);
+        let nrp = session.nrp.clone();
+        let relogin = background_relogin(state, session);
+
+        async move {
+            let http_handler = HttpHandler {
+                url,
+                session_id,
+                nrp,
+                upstream: state.upstream.clone(),
+                relogin: Some(relogin),
+            };
 
-    Ok(SuccessApiResponse::new(
-        online_mis_handler(redis_handler, http_handler, html_extractor).await?,
-    ))
+            let redis_handler = RedisHandler {
+                key,
+                cache: state.cache.clone(),
+                flights: state.flights.clone(),
+                ttl: state.cache_ttl.jadwal,
+                fresh,
+            };
+
+            let selectors = state.selectors.clone();
+            online_mis_handler(redis_handler, http_handler, move |body| {
+                html_extractor(body, &selectors)
+            })
+            .await
+        }
+    })
+    .await
 }
 
-fn html_extractor(body: String) -> Result<JadwalKuliahResponse> {
+fn html_extractor(body: String, selectors: &SelectorRegistry) -> Result<JadwalKuliahResponse> {
     let doc = scraper::Html::parse_document(&body);
-    validate_html(&doc)?;
+    validate_html(&doc, selectors)?;
+    let mut layout = LayoutCheck::new(selectors);
 
-    let semester: Vec<u8> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
-            })
-            .collect()
-    };
+    let semester: Vec<u8> = layout
+        .all(&doc, "jadwal.semester_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
 
-    let year: Vec<u16> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
-            })
-            .collect()
-    };
+    let year: Vec<u16> = layout
+        .all(&doc, "jadwal.year_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
 
-    let jam_istirahat = {
-        let selector = Selector::parse("table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(4) > td:nth-child(2) > table > tbody > tr > td > table:nth-child(2) > tbody > tr:nth-child(9) > td > strong").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
-            .map(|e| e.inner_html())
-            .unwrap_or_default()
-    };
+    let jam_istirahat = layout
+        .first(&doc, "jadwal.jam_istirahat")?
+        .map(|e| e.inner_html())
+        .unwrap_or_default();
 
-    let kelas = {
-        let selector = Selector::parse("table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(4) > td:nth-child(2) > table > tbody > tr > td > table:nth-child(1) > tbody > tr > td > div > b").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
-            .map(|e| e.inner_html())
-            .unwrap_or_default()
-    };
+    let kelas = layout
+        .first(&doc, "jadwal.kelas")?
+        .map(|e| e.inner_html())
+        .unwrap_or_default();
 
     let table: Table = {
-        let selector = Selector::parse("body > table > tbody > tr:nth-child(3) > td > div > table > tbody > tr > td > table > tbody > tr:nth-child(4) > td:nth-child(2) > table > tbody > tr > td > table:nth-child(2) > tbody > tr:not(:first-child):not(:last-child)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let matkul_selector = Selector::parse("tr:nth-child(odd) > td:nth-child(2) > div")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
+        let matkul_selector = selectors.get("jadwal.day.mata_kuliah")?;
 
-        let data: Vec<Vec<Matakuliah>> = doc
-            .select(&selector)
+        let data: Vec<Vec<Matakuliah>> = layout
+            .all(&doc, "jadwal.days")?
+            .into_iter()
             .map(|e| {
-                e.select(&matkul_selector)
+                e.select(matkul_selector)
                     .map(|e| {
                         let matkul = e.text().map(|e| e.trim()).collect::<Vec<_>>();
 
-                        let nama = matkul.get(0).unwrap_or_else(|| &"").to_string();
+                        let nama = matkul.first().unwrap_or(&"").to_string();
 
                         let dosen_jam = matkul
                             .get(1)
-                            .unwrap_or_else(|| &"")
+                            .unwrap_or(&"")
                             .split(" - ")
                             .collect::<Vec<_>>();
-                        let dosen = dosen_jam.get(0).unwrap_or_else(|| &"").trim().to_string();
-                        let jam = dosen_jam.get(1).unwrap_or_else(|| &"").to_string();
+                        let dosen = dosen_jam.first().unwrap_or(&"").trim().to_string();
+                        let jam = dosen_jam.get(1).unwrap_or(&"").to_string();
 
-                        let ruangan = matkul.get(2).unwrap_or_else(|| &"").to_string();
+                        let ruangan = matkul.get(2).unwrap_or(&"").to_string();
 
                         Matakuliah {
                             nama,
                             dosen,
+                            jam_iso: datetime::parse_jam_range(&jam).map(IsoTimeRange::new),
                             jam,
                             ruangan,
                         }
@@ -135,16 +192,18 @@ fn html_extractor(body: String) -> Result<JadwalKuliahResponse> {
 
         let empty: Vec<Matakuliah> = vec![];
         Table {
-            minggu: data.get(0).unwrap_or_else(|| &empty).to_vec(),
-            senin: data.get(1).unwrap_or_else(|| &empty).to_vec(),
-            selasa: data.get(2).unwrap_or_else(|| &empty).to_vec(),
-            rabu: data.get(3).unwrap_or_else(|| &empty).to_vec(),
-            kamis: data.get(4).unwrap_or_else(|| &empty).to_vec(),
-            jumat: data.get(5).unwrap_or_else(|| &empty).to_vec(),
-            sabtu: data.get(6).unwrap_or_else(|| &empty).to_vec(),
+            minggu: data.first().unwrap_or(&empty).to_vec(),
+            senin: data.get(1).unwrap_or(&empty).to_vec(),
+            selasa: data.get(2).unwrap_or(&empty).to_vec(),
+            rabu: data.get(3).unwrap_or(&empty).to_vec(),
+            kamis: data.get(4).unwrap_or(&empty).to_vec(),
+            jumat: data.get(5).unwrap_or(&empty).to_vec(),
+            sabtu: data.get(6).unwrap_or(&empty).to_vec(),
         }
     };
 
+    layout.finish()?;
+
     Ok(JadwalKuliahResponse {
         semester,
         year,
@@ -154,9 +213,9 @@ fn html_extractor(body: String) -> Result<JadwalKuliahResponse> {
     })
 }
 
-#[derive(Serialize, Deserialize, Default, JsonSchema)]
+#[derive(Debug, Serialize, Deserialize, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
-struct JadwalKuliahResponse {
+pub(crate) struct JadwalKuliahResponse {
     pub semester: Vec<u8>,
     pub year: Vec<u16>,
     pub kelas: String,
@@ -164,8 +223,10 @@ struct JadwalKuliahResponse {
     pub table: Table,
 }
 
-#[derive(Serialize, Deserialize, Default, JsonSchema)]
-struct Table {
+impl TtlWindows for JadwalKuliahResponse {}
+
+#[derive(Debug, Serialize, Deserialize, Default, JsonSchema)]
+pub(crate) struct Table {
     pub minggu: Vec<Matakuliah>,
     pub senin: Vec<Matakuliah>,
     pub selasa: Vec<Matakuliah>,
@@ -175,10 +236,41 @@ struct Table {
     pub sabtu: Vec<Matakuliah>,
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, JsonSchema)]
-struct Matakuliah {
+#[derive(Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Matakuliah {
     pub nama: String,
     pub dosen: String,
+    /// As Online MIS shows it, e.g. `08:00-10:30`
     pub jam: String,
+    /// `jam` as ISO 8601 times, unless it can't be parsed
+    pub jam_iso: Option<IsoTimeRange>,
     pub ruangan: String,
 }
+
+impl Table {
+    /// The classes of each day, from monday
+    pub fn days(&self) -> [(Weekday, &[Matakuliah]); 7] {
+        [
+            (Weekday::Monday, &self.senin),
+            (Weekday::Tuesday, &self.selasa),
+            (Weekday::Wednesday, &self.rabu),
+            (Weekday::Thursday, &self.kamis),
+            (Weekday::Friday, &self.jumat),
+            (Weekday::Saturday, &self.sabtu),
+            (Weekday::Sunday, &self.minggu),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html_extractor;
+    use crate::core::helper::test_helper::{fixture, selectors};
+
+    #[test]
+    fn extracts_jadwal_page() {
+        let res = html_extractor(fixture("jadwal_kul.html"), &selectors()).unwrap();
+        insta::assert_json_snapshot!(res);
+    }
+}
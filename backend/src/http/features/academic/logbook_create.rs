@@ -3,24 +3,34 @@ use std::collections::HashMap;
 use aide::axum::{routing::post_with, ApiRouter};
 use anyhow::anyhow;
 use axum::extract::State;
-use redis::AsyncCommands;
 use schemars::JsonSchema;
-use scraper::Selector;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     core::{
-        api_response::{ApiResponseTrait, SuccessApiResponse},
+        api_response::{ApiResponseTrait, SuccessApiResponse, ValidationErrorCause},
         axum_extractor::{ValidatedCookieJar, ValidatedJson},
+        datetime,
         error::Error,
         generate_openapi_response::generate_response,
-        helper::cache_helper,
+        selector::SelectorEntry,
     },
-    http::{AppContext, Result},
+    http::{features::shared::relogin::with_relogin, AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    (
+        "logbook_create.page",
+        "table",
+    ),
+    (
+        "logbook_create.message",
+        "table:nth-child(2) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(1) > div:nth-child(1) > font",
+    ),
+];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/logbook",
@@ -37,9 +47,12 @@ pub struct LobookCreateRequest {
     pub semester: u8,
     #[schemars(range(min = 1, max = 24))]
     pub minggu: u8,
+    /// As `YYYY-MM-DD`
+    #[schemars(regex(pattern = r"^\d{4}-\d{2}-\d{2}$"))]
     pub tanggal: String,
     #[schemars(regex(pattern = r"^(0[0-9]|1[0-9]|2[0-3]):[0-5][0-9]$"))]
     pub jam_mulai: String,
+    /// After `jam_mulai`
     #[schemars(regex(pattern = r"^(0[0-9]|1[0-9]|2[0-3]):[0-5][0-9]$"))]
     pub jam_selesai: String,
     #[schemars(length(max = 4000))]
@@ -50,17 +63,51 @@ pub struct LobookCreateRequest {
     pub mahasiswa: String,
 }
 
+impl LobookCreateRequest {
+    /// Checks what the schema can't, that `tanggal` is a day of the calendar and the
+    /// activity ends after it starts
+    pub fn validate(&self) -> Result<()> {
+        let mut causes = vec![];
+
+        if datetime::parse_iso_date(&self.tanggal).is_none() {
+            causes.push(ValidationErrorCause {
+                field: "/tanggal".to_owned(),
+                message: "Invalid date, expected YYYY-MM-DD".to_owned(),
+                received_value: self.tanggal.clone(),
+            });
+        }
+
+        if let (Some(mulai), Some(selesai)) = (
+            datetime::parse_jam(&self.jam_mulai),
+            datetime::parse_jam(&self.jam_selesai),
+        ) {
+            if selesai <= mulai {
+                causes.push(ValidationErrorCause {
+                    field: "/jamSelesai".to_owned(),
+                    message: "Must be after jamMulai".to_owned(),
+                    received_value: self.jam_selesai.clone(),
+                });
+            }
+        }
+
+        match causes.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Validation(causes)),
+        }
+    }
+}
+
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
     ValidatedJson(req): ValidatedJson<LobookCreateRequest>,
 ) -> Result<SuccessApiResponse<String>> {
-    let mut conn = cache_helper::get_conn(&state.redis_pool).await?;
+    req.validate()?;
 
     let params = {
         let initial_params = [
-            ("valnrpMahasiswa", nrp.clone()),
+            ("valnrpMahasiswa", session.nrp.clone()),
             ("valTahun", req.tahun.to_string()),
             ("valSemester", req.semester.to_string()),
             ("Simpan", "1".to_string()),
@@ -92,45 +139,87 @@ async fn handler(
         }
     };
 
-    let response = state
-        .client
-        .post("https://online.mis.pens.ac.id/entry_logbook_kp1.php")
-        .form(&params)
-        .header("Cookie", format!("PHPSESSID={};", session_id))
-        .send()
-        .await?
-        .text()
-        .await?;
-
-    {
-        let doc = scraper::Html::parse_document(&response);
-        let validate_selector =
-            Selector::parse("table").map_err(|_| anyhow!("Error parsing selector"))?;
-
-        doc.select(&validate_selector)
-            .next()
-            .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?;
-
-        let message_selector = Selector::parse("table:nth-child(2) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(1) > div:nth-child(1) > font")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-
-        let msg = doc
-            .select(&message_selector)
-            .next()
-            .map(|e| e.inner_html())
-            .ok_or_else(|| anyhow!("Failed to create logbook, message not found"))?;
+    with_relogin(&state, &session, |session_id| {
+        let state = &state;
+        let params = &params;
+        let nrp = &session.nrp;
+
+        async move {
+            let request = state
+                .upstream
+                .client()
+                .post(format!("{}/entry_logbook_kp1.php", state.mis_base_url))
+                .form(params)
+                .header("Cookie", format!("PHPSESSID={};", session_id));
+            let response = state.upstream.text(nrp, request).await?;
+
+            let doc = scraper::Html::parse_document(&response);
+            let selectors = &state.selectors;
+            let validate_selector = selectors.get("logbook_create.page")?;
+
+            doc.select(validate_selector)
+                .next()
+                .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?;
+
+            let message_selector = selectors.get("logbook_create.message")?;
+
+            let msg = doc
+                .select(message_selector)
+                .next()
+                .map(|e| e.inner_html())
+                .ok_or_else(|| anyhow!("Failed to create logbook, message not found"))?;
+
+            if msg != "Simpan Data Berhasil" {
+                return Err(anyhow!("Failed to create logbook: {}", msg).into());
+            }
 
-        if msg != "Simpan Data Berhasil" {
-            return Err(anyhow!("Failed to create logbook: {}", msg).into());
+            Ok(())
         }
-    }
+    })
+    .await?;
 
-    let () = conn
-        .del(format!(
+    state
+        .cache
+        .del(&format!(
             "logbook:{}:{}:{}:{}",
-            nrp, req.tahun, req.semester, req.minggu
+            session.nrp, req.tahun, req.semester, req.minggu
         ))
         .await?;
 
     Ok(SuccessApiResponse::new("Logbook Created".to_string()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LobookCreateRequest;
+    use crate::core::error::Error;
+
+    #[test]
+    fn validates_tanggal_and_jam() {
+        let req = |tanggal: &str, jam_mulai: &str, jam_selesai: &str| LobookCreateRequest {
+            tanggal: tanggal.to_owned(),
+            jam_mulai: jam_mulai.to_owned(),
+            jam_selesai: jam_selesai.to_owned(),
+            ..Default::default()
+        };
+
+        assert!(req("2025-03-04", "08:00", "16:00").validate().is_ok());
+        for (tanggal, jam_mulai, jam_selesai, fields) in [
+            ("2025-02-30", "08:00", "16:00", vec!["/tanggal"]),
+            ("2025-03-04", "16:00", "16:00", vec!["/jamSelesai"]),
+            (
+                "2025-13-01",
+                "16:00",
+                "08:00",
+                vec!["/tanggal", "/jamSelesai"],
+            ),
+        ] {
+            let Err(Error::Validation(causes)) = req(tanggal, jam_mulai, jam_selesai).validate()
+            else {
+                panic!("{tanggal} {jam_mulai} {jam_selesai} should be rejected");
+            };
+            let received: Vec<_> = causes.iter().map(|cause| cause.field.as_str()).collect();
+            assert_eq!(received, fields);
+        }
+    }
+}
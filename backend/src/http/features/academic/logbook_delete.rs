@@ -1,9 +1,6 @@
 use aide::axum::{routing::delete_with, ApiRouter};
-use anyhow::anyhow;
 use axum::extract::State;
-use redis::AsyncCommands;
 use schemars::JsonSchema;
-use scraper::Selector;
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -12,13 +9,15 @@ use crate::{
         axum_extractor::{ValidatedCookieJar, ValidatedJson, ValidatedPath},
         error::Error,
         generate_openapi_response::generate_response,
-        helper::cache_helper,
+        selector::SelectorEntry,
     },
-    http::{AppContext, Result},
+    http::{features::shared::relogin::with_relogin, AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[("logbook_delete.page", "table")];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/logbook/{id}",
@@ -45,15 +44,13 @@ pub struct LogbookDeleteParamRequest {
 
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
     ValidatedPath(path): ValidatedPath<LogbookDeleteParamRequest>,
     ValidatedJson(json): ValidatedJson<LogbookDeleteBodyRequest>,
 ) -> Result<SuccessApiResponse<String>> {
-    let mut conn = cache_helper::get_conn(&state.redis_pool).await?;
-
     let params = [
-        ("valnrpMahasiswa", nrp.clone()),
+        ("valnrpMahasiswa", session.nrp.clone()),
         ("valTahun", json.tahun.to_string()),
         ("valSemester", json.semester.to_string()),
         ("valMinggu", json.minggu.to_string()),
@@ -61,30 +58,38 @@ async fn handler(
         ("nokplogbook", path.id),
     ];
 
-    let response = state
-        .client
-        .get("https://online.mis.pens.ac.id/entry_logbook_kp1.php")
-        .query(&params)
-        .header("Cookie", format!("PHPSESSID={};", session_id))
-        .send()
-        .await?
-        .text()
-        .await?;
+    with_relogin(&state, &session, |session_id| {
+        let state = &state;
+        let params = &params;
+        let nrp = &session.nrp;
+
+        async move {
+            let request = state
+                .upstream
+                .client()
+                .get(format!("{}/entry_logbook_kp1.php", state.mis_base_url))
+                .query(params)
+                .header("Cookie", format!("PHPSESSID={};", session_id));
+            let response = state.upstream.text(nrp, request).await?;
+
+            let doc = scraper::Html::parse_document(&response);
+            let selectors = &state.selectors;
+            let validate_selector = selectors.get("logbook_delete.page")?;
 
-    {
-        let doc = scraper::Html::parse_document(&response);
-        let validate_selector =
-            Selector::parse("table").map_err(|_| anyhow!("Error parsing selector"))?;
+            doc.select(validate_selector)
+                .next()
+                .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?;
 
-        doc.select(&validate_selector)
-            .next()
-            .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?;
-    }
+            Ok(())
+        }
+    })
+    .await?;
 
-    let () = conn
-        .del(format!(
+    state
+        .cache
+        .del(&format!(
             "logbook:{}:{}:{}:{}",
-            nrp, json.tahun, json.semester, json.minggu
+            session.nrp, json.tahun, json.semester, json.minggu
         ))
         .await?;
 
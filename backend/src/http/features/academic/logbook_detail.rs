@@ -1,7 +1,5 @@
 use aide::axum::{routing::get_with, ApiRouter};
-use anyhow::anyhow;
 use schemars::JsonSchema;
-use scraper::Selector;
 
 use axum::extract::State;
 use serde::{Deserialize, Serialize};
@@ -9,17 +7,78 @@ use serde::{Deserialize, Serialize};
 use crate::{
     core::{
         api_response::{ApiResponseTrait, SuccessApiResponse},
-        axum_extractor::{validate_html, ValidatedCookieJar, ValidatedQuery},
+        axum_extractor::{validate_html, CacheControl, ValidatedCookieJar, ValidatedQuery},
+        datetime::{self, IsoDateRange, IsoTimeRange},
         error::Error,
         generate_openapi_response::generate_response,
         handler::{online_mis_handler, HttpHandler, RedisHandler},
-        helper::cache_helper,
+        layout::LayoutCheck,
+        selector::{SelectorEntry, SelectorRegistry},
+        ttl::TtlWindows,
+    },
+    http::{
+        features::shared::relogin::{background_relogin, with_relogin},
+        AppContext, Result,
     },
-    http::{AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    ("logbook.page", "table"),
+    ("logbook.semester_options", "#cbSemester > option"),
+    ("logbook.year_options", "#tahun > option"),
+    ("logbook.week_options", "#minggu > option"),
+    (
+        "logbook.catatan_dosen",
+        "table:nth-child(10) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(1)",
+    ),
+    (
+        "logbook.catatan_perusahaan",
+        "table:nth-child(12) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(1)",
+    ),
+    ("logbook.kp_daftar", "#kp_daftar"),
+    ("logbook.mahasiswa", "#mahasiswa"),
+    (
+        "logbook.nama",
+        "table:nth-child(2) > tbody > tr:nth-child(6) > td:nth-child(2)",
+    ),
+    (
+        "logbook.nrp",
+        "table:nth-child(2) > tbody > tr:nth-child(7) > td:nth-child(2)",
+    ),
+    (
+        "logbook.pembimbing",
+        "table:nth-child(2) > tbody > tr:nth-child(8) > td:nth-child(2)",
+    ),
+    (
+        "logbook.tempat_kp",
+        "table:nth-child(2) > tbody > tr:nth-child(9) > td:nth-child(2)",
+    ),
+    (
+        "logbook.tanggal_kp",
+        "table:nth-child(2) > tbody > tr:nth-child(10) > td:nth-child(2)",
+    ),
+    (
+        "logbook.matakuliah_options",
+        "#matakuliah > option:not(:first-child)",
+    ),
+    ("logbook.table", "table:nth-child(8)"),
+    (
+        "logbook.rows",
+        "table:nth-child(8) > tbody > tr:not(:first-child):not(:nth-child(2))",
+    ),
+    ("logbook.row.tanggal", "td:nth-child(2)"),
+    ("logbook.row.jam_mulai", "td:nth-child(3)"),
+    ("logbook.row.jam_selesai", "td:nth-child(4)"),
+    ("logbook.row.kegiatan", "td:nth-child(5)"),
+    ("logbook.row.matkul_kegiatan", "td:nth-child(6)"),
+    ("logbook.row.file_progres", "td:nth-child(7) > a"),
+    ("logbook.row.file_foto", "td:nth-child(8) > a"),
+    ("logbook.row.link_cetak", "td:nth-child(9) > a"),
+    ("logbook.row.can_delete", "td:nth-child(10) > img"),
+];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/logbook",
@@ -39,51 +98,68 @@ pub struct LobookDetailRequest {
 
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
+    cache_control: CacheControl,
     ValidatedQuery(req): ValidatedQuery<LobookDetailRequest>,
 ) -> Result<SuccessApiResponse<LogbookDetailResponse>> {
-    let conn = cache_helper::get_conn(&state.redis_pool).await?;
-
-    let http_handler = HttpHandler {
-        session_id: &session_id,
-        client: &state.client,
-        url: format!(
-            "https://online.mis.pens.ac.id/entry_logbook_kp1.php?valTahun={}&valSemester={}&valMinggu={}",
-            req.year,
-            req.semester,
-            req.minggu
-        ),
-    };
-
-    let redis_handler = RedisHandler {
-        redis_pool: conn,
-        key: format!(
+    let data = with_relogin(&state, &session, |session_id| {
+        let state = &state;
+        let url = format!(
+            "{}/entry_logbook_kp1.php?valTahun={}&valSemester={}&valMinggu={}",
+            state.mis_base_url, req.year, req.semester, req.minggu
+        );
+        let key = format!(
             "logbook:{}:{}:{}:{}",
-            nrp, req.year, req.semester, req.minggu
-        ),
-    };
+            session.nrp, req.year, req.semester, req.minggu
+        );
+        let nrp = session.nrp.clone();
+        let relogin = background_relogin(state, &session);
+
+        async move {
+            let http_handler = HttpHandler {
+                url,
+                session_id,
+                nrp,
+                upstream: state.upstream.clone(),
+                relogin: Some(relogin),
+            };
+
+            let redis_handler = RedisHandler {
+                key,
+                cache: state.cache.clone(),
+                flights: state.flights.clone(),
+                ttl: state.cache_ttl.logbook,
+                fresh: cache_control.fresh,
+            };
+
+            let selectors = state.selectors.clone();
+            online_mis_handler(redis_handler, http_handler, move |body| {
+                html_extractor(body, &selectors)
+            })
+            .await
+        }
+    })
+    .await?;
 
-    Ok(SuccessApiResponse::new(
-        online_mis_handler(redis_handler, http_handler, html_extractor).await?,
-    ))
+    Ok(SuccessApiResponse::new(data.data).with_headers(data.status.headers()))
 }
 
-fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
+fn html_extractor(body: String, selectors: &SelectorRegistry) -> Result<LogbookDetailResponse> {
     let doc = scraper::Html::parse_document(&body);
-    validate_html(&doc)?;
+    validate_html(&doc, selectors)?;
 
-    let validate_selector =
-        Selector::parse("table").map_err(|_| anyhow!("Error parsing selector"))?;
+    let validate_selector = selectors.get("logbook.page")?;
 
-    doc.select(&validate_selector)
+    doc.select(validate_selector)
         .next()
         .ok_or_else(|| Error::Unauthorized("Unauthorized".to_string()))?;
+    let mut layout = LayoutCheck::new(selectors);
 
     let semester: Vec<u8> = {
-        let selector = Selector::parse("#cbSemester > option")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
+        layout
+            .all(&doc, "logbook.semester_options")?
+            .into_iter()
             .map(|e| {
                 e.value()
                     .attr("value")
@@ -95,9 +171,9 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let year: Vec<u16> = {
-        let selector =
-            Selector::parse("#tahun > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
+        layout
+            .all(&doc, "logbook.year_options")?
+            .into_iter()
             .map(|e| {
                 e.value()
                     .attr("value")
@@ -109,9 +185,9 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let minggu: Vec<u8> = {
-        let selector =
-            Selector::parse("#minggu > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
+        layout
+            .all(&doc, "logbook.week_options")?
+            .into_iter()
             .map(|e| {
                 e.value()
                     .attr("value")
@@ -123,12 +199,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let catatan_dosen = {
-        let selector = Selector::parse(
-            "table:nth-child(10) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(1)",
-        )
-        .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.catatan_dosen")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .trim()
@@ -136,12 +208,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let catatan_perusahaan = {
-        let selector = Selector::parse(
-            "table:nth-child(12) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(1)",
-        )
-        .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.catatan_perusahaan")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .trim()
@@ -149,31 +217,24 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let kp_daftar = {
-        let selector =
-            Selector::parse("#kp_daftar").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.kp_daftar")?
             .map(|e| e.attr("value").unwrap_or_default())
             .unwrap_or_default()
             .to_owned()
     };
 
     let mahasiswa = {
-        let selector =
-            Selector::parse("#mahasiswa").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.mahasiswa")?
             .map(|e| e.attr("value").unwrap_or_default())
             .unwrap_or_default()
             .to_owned()
     };
 
     let nama = {
-        let selector =
-            Selector::parse("table:nth-child(2) > tbody > tr:nth-child(6) > td:nth-child(2)")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.nama")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .trim()
@@ -184,11 +245,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let nrp = {
-        let selector =
-            Selector::parse("table:nth-child(2) > tbody > tr:nth-child(7) > td:nth-child(2)")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.nrp")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .trim()
@@ -199,11 +257,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let pembimbing = {
-        let selector =
-            Selector::parse("table:nth-child(2) > tbody > tr:nth-child(8) > td:nth-child(2)")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.pembimbing")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .trim()
@@ -214,11 +269,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let tempat_kp = {
-        let selector =
-            Selector::parse("table:nth-child(2) > tbody > tr:nth-child(9) > td:nth-child(2)")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.tempat_kp")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .trim()
@@ -229,11 +281,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let tanggal_kp = {
-        let selector =
-            Selector::parse("table:nth-child(2) > tbody > tr:nth-child(10) > td:nth-child(2)")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .next()
+        layout
+            .first(&doc, "logbook.tanggal_kp")?
             .map(|e| e.inner_html())
             .unwrap_or_default()
             .split(":")
@@ -244,9 +293,8 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let list_matkul: Vec<LogbookMatkulResponse> = {
-        let selector = Selector::parse("#matakuliah > option:not(:first-child)")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
+        let selector = selectors.get("logbook.matakuliah_options")?;
+        doc.select(selector)
             .map(|e| {
                 let text = e.inner_html().trim().to_owned();
                 let value: u32 = e
@@ -262,119 +310,111 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
     };
 
     let table: Vec<LogbookTableResponse> = {
-        let selector =
-            Selector::parse("table:nth-child(8) > tbody > tr:not(:first-child):not(:nth-child(2))")
-                .map_err(|_| anyhow!("Error parsing selector"))?;
-
-        let tanggal_selector =
-            Selector::parse("td:nth-child(2)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let jam_mulai_selector =
-            Selector::parse("td:nth-child(3)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let jam_selesai_selector =
-            Selector::parse("td:nth-child(4)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let kegiatan_selector =
-            Selector::parse("td:nth-child(5)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let matkul_kegiatan_selector =
-            Selector::parse("td:nth-child(6)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let file_progres_selector = Selector::parse("td:nth-child(7) > a")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let file_foto_selector = Selector::parse("td:nth-child(8) > a")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let link_cetak_selector = Selector::parse("td:nth-child(9) > a")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-        let can_delete_selector = Selector::parse("td:nth-child(10) > img")
-            .map_err(|_| anyhow!("Error parsing selector"))?;
-
-        doc.select(&selector)
-            .map(|e| {
-                let tanggal: String = e
-                    .select(&tanggal_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-
-                let jam_mulai: String = e
-                    .select(&jam_mulai_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-
-                let jam_selesai: String = e
-                    .select(&jam_selesai_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-
-                let kegiatan: String = e
-                    .select(&kegiatan_selector)
-                    .next()
-                    .map(|e| e.inner_html().trim().to_owned())
-                    .unwrap_or_default();
-
-                let matkul_kegiatan: String = e
-                    .select(&matkul_kegiatan_selector)
+        layout.first(&doc, "logbook.table")?;
+        let selector = selectors.get("logbook.rows")?;
+
+        let jam_mulai_selector = selectors.get("logbook.row.jam_mulai")?;
+        let jam_selesai_selector = selectors.get("logbook.row.jam_selesai")?;
+        let kegiatan_selector = selectors.get("logbook.row.kegiatan")?;
+        let matkul_kegiatan_selector = selectors.get("logbook.row.matkul_kegiatan")?;
+        let file_progres_selector = selectors.get("logbook.row.file_progres")?;
+        let file_foto_selector = selectors.get("logbook.row.file_foto")?;
+        let can_delete_selector = selectors.get("logbook.row.can_delete")?;
+
+        let mut table = Vec::new();
+        for e in doc.select(selector) {
+            let tanggal: String = layout
+                .first(e, "logbook.row.tanggal")?
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+
+            let jam_mulai: String = e
+                .select(jam_mulai_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+
+            let jam_selesai: String = e
+                .select(jam_selesai_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+
+            let kegiatan: String = e
+                .select(kegiatan_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+
+            let matkul_kegiatan: String = e
+                .select(matkul_kegiatan_selector)
+                .next()
+                .map(|e| e.inner_html().trim().to_owned())
+                .unwrap_or_default();
+
+            let file_progres: Option<String> = {
+                let (inner, href) = e
+                    .select(file_progres_selector)
                     .next()
-                    .map(|e| e.inner_html().trim().to_owned())
+                    .map(|e| {
+                        (
+                            e.inner_html().trim().to_owned(),
+                            e.attr("href").unwrap_or_default().trim().to_owned(),
+                        )
+                    })
                     .unwrap_or_default();
 
-                let file_progres: Option<String> = {
-                    let (inner, href) = e
-                        .select(&file_progres_selector)
-                        .next()
-                        .map(|e| {
-                            (
-                                e.inner_html().trim().to_owned(),
-                                e.attr("href").unwrap_or_default().trim().to_owned(),
-                            )
-                        })
-                        .unwrap_or_default();
-
-                    match inner.is_empty() {
-                        true => None,
-                        false => Some(href),
-                    }
-                };
-
-                let file_foto: String = e
-                    .select(&file_foto_selector)
-                    .next()
-                    .map(|e| e.attr("href").unwrap_or_default().trim().to_owned())
-                    .unwrap_or_default();
-
-                let link_cetak: String = e
-                    .select(&link_cetak_selector)
-                    .next()
-                    .map(|e| e.attr("href").unwrap_or_default().trim().to_owned())
-                    .unwrap_or_default();
-
-                let id = link_cetak
-                    .clone()
-                    .split("=")
-                    .last()
-                    .unwrap_or_default()
-                    .to_owned();
-
-                let deletable = match e.select(&can_delete_selector).next() {
-                    Some(_) => true,
-                    None => false,
-                };
-
-                LogbookTableResponse {
-                    id,
-                    tanggal,
-                    jam_mulai,
-                    jam_selesai,
-                    file_foto,
-                    file_progres,
-                    kegiatan,
-                    link_cetak,
-                    deletable,
-                    matkul_kegiatan,
+                match inner.is_empty() {
+                    true => None,
+                    false => Some(href),
                 }
-            })
-            .collect()
+            };
+
+            let file_foto: String = e
+                .select(file_foto_selector)
+                .next()
+                .map(|e| e.attr("href").unwrap_or_default().trim().to_owned())
+                .unwrap_or_default();
+
+            let link_cetak: String = layout
+                .first(e, "logbook.row.link_cetak")?
+                .map(|e| e.attr("href").unwrap_or_default().trim().to_owned())
+                .unwrap_or_default();
+
+            let id = link_cetak
+                .clone()
+                .split("=")
+                .last()
+                .unwrap_or_default()
+                .to_owned();
+
+            let deletable = e.select(can_delete_selector).next().is_some();
+
+            let tanggal_iso = datetime::parse_tanggal(&tanggal).map(|tanggal| tanggal.to_string());
+            let jam_iso = datetime::parse_jam(&jam_mulai)
+                .zip(datetime::parse_jam(&jam_selesai))
+                .map(IsoTimeRange::new);
+
+            table.push(LogbookTableResponse {
+                id,
+                tanggal,
+                tanggal_iso,
+                jam_iso,
+                jam_mulai,
+                jam_selesai,
+                file_foto,
+                file_progres,
+                kegiatan,
+                link_cetak,
+                deletable,
+                matkul_kegiatan,
+            });
+        }
+        table
     };
 
+    layout.finish()?;
+
     Ok(LogbookDetailResponse {
         semester,
         year,
@@ -389,13 +429,14 @@ fn html_extractor(body: String) -> Result<LogbookDetailResponse> {
             nrp,
             pembimbing,
             tempat_kp,
+            tanggal_kp_iso: datetime::parse_tanggal_range(&tanggal_kp).map(IsoDateRange::new),
             tanggal_kp,
             list_matkul,
         },
     })
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 struct LogbookDetailResponse {
     pub semester: Vec<u8>,
@@ -409,31 +450,41 @@ struct LogbookDetailResponse {
     pub mahasiswa: String,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+impl TtlWindows for LogbookDetailResponse {}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 struct LogbookMatkulResponse {
     pub text: String,
     pub value: u32,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 struct LogbookFormDetailResponse {
     pub nama: String,
     pub nrp: String,
     pub pembimbing: String,
     pub tempat_kp: String,
+    /// As Online MIS shows it, e.g. `3 Februari 2025 sd 30 Juni 2025`
     pub tanggal_kp: String,
+    /// `tanggal_kp` as ISO 8601 dates, unless it can't be parsed
+    pub tanggal_kp_iso: Option<IsoDateRange>,
     pub list_matkul: Vec<LogbookMatkulResponse>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 struct LogbookTableResponse {
     pub id: String,
+    /// As Online MIS shows it, e.g. `3 Maret 2025`
     pub tanggal: String,
+    /// `tanggal` as an ISO 8601 date, unless it can't be parsed
+    pub tanggal_iso: Option<String>,
     pub jam_mulai: String,
     pub jam_selesai: String,
+    /// `jam_mulai` and `jam_selesai` as ISO 8601 times, unless either can't be parsed
+    pub jam_iso: Option<IsoTimeRange>,
     pub kegiatan: String,
     pub matkul_kegiatan: String,
     pub file_progres: Option<String>,
@@ -441,3 +492,24 @@ struct LogbookTableResponse {
     pub link_cetak: String,
     pub deletable: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::html_extractor;
+    use crate::core::{
+        error::Error,
+        helper::test_helper::{fixture, selectors},
+    };
+
+    #[test]
+    fn extracts_logbook_page() {
+        let res = html_extractor(fixture("entry_logbook_kp1.html"), &selectors()).unwrap();
+        insta::assert_json_snapshot!(res);
+    }
+
+    #[test]
+    fn rejects_empty_page() {
+        let err = html_extractor(String::new(), &selectors()).unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+    }
+}
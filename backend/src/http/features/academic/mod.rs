@@ -1,14 +1,19 @@
 use aide::axum::ApiRouter;
 
+use crate::core::selector::SelectorEntry;
+
 use super::AppContext;
 
 mod absen;
-mod frs;
-mod jadwal_kuliah;
+pub(super) mod deadlines;
+pub(super) mod frs;
+pub(super) mod jadwal_ics;
+pub(super) mod jadwal_kuliah;
 mod logbook_create;
 mod logbook_delete;
 mod logbook_detail;
-mod nilai_semester;
+pub(super) mod nilai_semester;
+mod transkrip;
 
 const OPENAPI_TAG: &str = "Academic";
 
@@ -18,10 +23,27 @@ pub fn router() -> ApiRouter<AppContext> {
         ApiRouter::new()
             .merge(absen::endpoint())
             .merge(frs::endpoint())
+            .merge(deadlines::endpoint())
             .merge(jadwal_kuliah::endpoint())
+            .merge(jadwal_ics::endpoint())
             .merge(nilai_semester::endpoint())
+            .merge(transkrip::endpoint())
             .merge(logbook_create::endpoint())
             .merge(logbook_delete::endpoint())
             .merge(logbook_detail::endpoint()),
     )
 }
+
+pub fn selectors() -> impl Iterator<Item = &'static SelectorEntry> {
+    [
+        absen::SELECTORS,
+        frs::SELECTORS,
+        jadwal_kuliah::SELECTORS,
+        nilai_semester::SELECTORS,
+        logbook_create::SELECTORS,
+        logbook_delete::SELECTORS,
+        logbook_detail::SELECTORS,
+    ]
+    .into_iter()
+    .flatten()
+}
@@ -1,7 +1,5 @@
 use aide::axum::{routing::get_with, ApiRouter};
-use anyhow::anyhow;
 use schemars::JsonSchema;
-use scraper::Selector;
 
 use axum::extract::State;
 use serde::{Deserialize, Serialize};
@@ -9,16 +7,55 @@ use serde::{Deserialize, Serialize};
 use crate::{
     core::{
         api_response::{ApiResponseTrait, SuccessApiResponse},
-        axum_extractor::{validate_html, ValidatedCookieJar, ValidatedQuery},
+        axum_extractor::{validate_html, CacheControl, ValidatedCookieJar, ValidatedQuery},
         generate_openapi_response::generate_response,
-        handler::{online_mis_handler, HttpHandler, RedisHandler},
-        helper::cache_helper,
+        handler::{online_mis_handler, Cached, HttpHandler, RedisHandler},
+        layout::LayoutCheck,
+        selector::{SelectorEntry, SelectorRegistry},
+        ttl::TtlWindows,
+    },
+    http::{
+        features::shared::{
+            relogin::{background_relogin, with_relogin},
+            year_semester_request::YearSemesterRequest,
+        },
+        AppContext, Result,
     },
-    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
 };
 
 use super::OPENAPI_TAG;
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    (
+        "nilai.semester_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "nilai.year_options",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option",
+    ),
+    (
+        "nilai.table",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(4) > td:nth-child(2) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1)",
+    ),
+    (
+        "nilai.rows",
+        "table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(4) > td:nth-child(2) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:not(:first-child):not(:nth-child(2))",
+    ),
+    (
+        "nilai.row.kode",
+        "td:nth-child(1)",
+    ),
+    (
+        "nilai.row.mata_kuliah",
+        "td:nth-child(2)",
+    ),
+    (
+        "nilai.row.value",
+        "td:nth-child(3)",
+    ),
+];
+
 pub fn endpoint() -> ApiRouter<AppContext> {
     ApiRouter::new().api_route(
         "/nilai",
@@ -28,100 +65,120 @@ pub fn endpoint() -> ApiRouter<AppContext> {
 
 #[axum::debug_handler]
 async fn handler(
-    ValidatedCookieJar { session_id, nrp }: ValidatedCookieJar,
+    session: ValidatedCookieJar,
     State(state): State<AppContext>,
+    cache_control: CacheControl,
     ValidatedQuery(req): ValidatedQuery<YearSemesterRequest>,
 ) -> Result<SuccessApiResponse<NilaiSemesterResponse>> {
-    let conn = cache_helper::get_conn(&state.redis_pool).await?;
-
-    let http_handler = HttpHandler {
-        url: format!(
-            "https://online.mis.pens.ac.id/nilai_sem.php?valTahun={}&valSemester={}",
-            req.year, req.semester
-        ),
-        session_id: &session_id,
-        client: &state.client,
-    };
-
-    let redis_handler = RedisHandler {
-        key: format!("nilai:{}:{}:{}", nrp, req.year, req.semester),
-        redis_pool: conn,
-    };
+    let data = fetch(&state, &session, cache_control.fresh, &req).await?;
 
-    Ok(SuccessApiResponse::new(
-        online_mis_handler(redis_handler, http_handler, html_extractor).await?,
-    ))
+    Ok(SuccessApiResponse::new(data.data).with_headers(data.status.headers()))
 }
 
-fn html_extractor(body: String) -> Result<NilaiSemesterResponse> {
-    let doc = scraper::Html::parse_document(&body);
-    validate_html(&doc)?;
-
-    let semester: Vec<u8> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
+/// The grades of a semester, from the cache or Online MIS
+pub(crate) async fn fetch(
+    state: &AppContext,
+    session: &ValidatedCookieJar,
+    fresh: bool,
+    req: &YearSemesterRequest,
+) -> Result<Cached<NilaiSemesterResponse>> {
+    with_relogin(state, session, |session_id| {
+        let url = format!(
+            "{}/nilai_sem.php?valTahun={}&valSemester={}",
+            state.mis_base_url, req.year, req.semester
+        );
+        let key = format!("nilai:{}:{}:{}", session.nrp, req.year, req.semester);
+        let nrp = session.nrp.clone();
+        let relogin = background_relogin(state, session);
+
+        async move {
+            let http_handler = HttpHandler {
+                url,
+                session_id,
+                nrp,
+                upstream: state.upstream.clone(),
+                relogin: Some(relogin),
+            };
+
+            let redis_handler = RedisHandler {
+                key,
+                cache: state.cache.clone(),
+                flights: state.flights.clone(),
+                ttl: state.cache_ttl.nilai,
+                fresh,
+            };
+
+            let selectors = state.selectors.clone();
+            online_mis_handler(redis_handler, http_handler, move |body| {
+                html_extractor(body, &selectors)
             })
-            .collect()
-    };
+            .await
+        }
+    })
+    .await
+}
 
-    let year: Vec<u16> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(2) > td:nth-child(2) > font:nth-child(1) > font:nth-child(1) > select:nth-child(1) > option").map_err(|_| anyhow!("Error parsing selector"))?;
-        doc.select(&selector)
-            .map(|e| {
-                e.value()
-                    .attr("value")
-                    .unwrap_or_default()
-                    .parse()
-                    .unwrap_or_default()
-            })
-            .collect()
-    };
+fn html_extractor(body: String, selectors: &SelectorRegistry) -> Result<NilaiSemesterResponse> {
+    let doc = scraper::Html::parse_document(&body);
+    validate_html(&doc, selectors)?;
+    let mut layout = LayoutCheck::new(selectors);
+
+    let semester: Vec<u8> = layout
+        .all(&doc, "nilai.semester_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let year: Vec<u16> = layout
+        .all(&doc, "nilai.year_options")?
+        .into_iter()
+        .map(|e| {
+            e.value()
+                .attr("value")
+                .unwrap_or_default()
+                .parse()
+                .unwrap_or_default()
+        })
+        .collect();
 
     let table: Vec<Table> = {
-        let selector = Selector::parse("table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(3) > td:nth-child(1) > div:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(4) > td:nth-child(2) > table:nth-child(1) > tbody:nth-child(1) > tr:nth-child(1) > td:nth-child(1) > table:nth-child(1) > tbody:nth-child(1) > tr:not(:first-child):not(:nth-child(2))").map_err(|_| anyhow!("Error parsing selector"))?;
-
-        let kode_selector =
-            Selector::parse("td:nth-child(1)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let matakuliah_selector =
-            Selector::parse("td:nth-child(2)").map_err(|_| anyhow!("Error parsing selector"))?;
-        let value_selector =
-            Selector::parse("td:nth-child(3)").map_err(|_| anyhow!("Error parsing selector"))?;
-
-        doc.select(&selector)
-            .map(|e| {
-                let kode: String = e
-                    .select(&kode_selector)
-                    .next()
-                    .map(|e| e.text().collect::<String>().trim().to_owned())
-                    .unwrap_or_default();
-
-                let mata_kuliah: String = e
-                    .select(&matakuliah_selector)
-                    .next()
-                    .map(|e| e.text().collect::<String>().trim().to_owned())
-                    .unwrap_or_default();
-
-                let value: String = e
-                    .select(&value_selector)
-                    .next()
-                    .map(|e| e.text().collect::<String>().trim().to_owned())
-                    .unwrap_or_default();
-
-                Table {
-                    kode,
-                    mata_kuliah,
-                    value,
-                }
-            })
-            .collect()
+        layout.first(&doc, "nilai.table")?;
+        let selector = selectors.get("nilai.rows")?;
+
+        let mut table = Vec::new();
+        for e in doc.select(selector) {
+            let kode: String = layout
+                .first(e, "nilai.row.kode")?
+                .map(|e| e.text().collect::<String>().trim().to_owned())
+                .unwrap_or_default();
+
+            let mata_kuliah: String = layout
+                .first(e, "nilai.row.mata_kuliah")?
+                .map(|e| e.text().collect::<String>().trim().to_owned())
+                .unwrap_or_default();
+
+            let value: String = layout
+                .first(e, "nilai.row.value")?
+                .map(|e| e.text().collect::<String>().trim().to_owned())
+                .unwrap_or_default();
+
+            table.push(Table {
+                kode,
+                mata_kuliah,
+                value,
+            });
+        }
+        table
     };
 
+    layout.finish()?;
+
     Ok(NilaiSemesterResponse {
         semester,
         year,
@@ -129,18 +186,32 @@ fn html_extractor(body: String) -> Result<NilaiSemesterResponse> {
     })
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
-struct NilaiSemesterResponse {
+pub(crate) struct NilaiSemesterResponse {
     pub semester: Vec<u8>,
     pub year: Vec<u16>,
     pub table: Vec<Table>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Default)]
+impl TtlWindows for NilaiSemesterResponse {}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
-struct Table {
+pub(crate) struct Table {
     pub kode: String,
     pub mata_kuliah: String,
     pub value: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::html_extractor;
+    use crate::core::helper::test_helper::{fixture, selectors};
+
+    #[test]
+    fn extracts_nilai_page() {
+        let res = html_extractor(fixture("nilai_sem.html"), &selectors()).unwrap();
+        insta::assert_json_snapshot!(res);
+    }
+}
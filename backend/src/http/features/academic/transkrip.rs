@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use aide::axum::{routing::get_with, ApiRouter};
+use anyhow::anyhow;
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::task::JoinSet;
+
+use crate::{
+    core::{
+        api_response::{ApiResponseTrait, SuccessApiResponse},
+        axum_extractor::{CacheControl, ValidatedCookieJar},
+        error::Error,
+        generate_openapi_response::generate_response,
+        request_id,
+    },
+    http::{features::shared::year_semester_request::YearSemesterRequest, AppContext, Result},
+};
+
+use super::{
+    nilai_semester::{self, Table},
+    OPENAPI_TAG,
+};
+
+pub fn endpoint() -> ApiRouter<AppContext> {
+    ApiRouter::new().api_route(
+        "/transkrip",
+        get_with(handler, generate_response(handler, OPENAPI_TAG, true)),
+    )
+}
+
+/// The grades of every semester since the student enrolled, up to the one logged in to,
+/// each course once.
+///
+/// The semesters are fetched in parallel through the cache, only the current one is
+/// fetched again when asked for fresh data. A course taken again is graded with its last
+/// attempt that has a grade. The semesters that couldn't be fetched are listed under
+/// `failed` rather than failing the whole transcript. The `Cache-Status` and `Age`
+/// headers are the ones of the semester fetched longest ago.
+#[axum::debug_handler]
+async fn handler(
+    session: ValidatedCookieJar,
+    State(state): State<AppContext>,
+    cache_control: CacheControl,
+) -> Result<SuccessApiResponse<TranskripResponse>> {
+    let Some(record) = state.sessions.load(&session.token).await? else {
+        return Err(Error::Unauthorized("Unauthorized".to_owned()));
+    };
+    let current = Period {
+        year: record.year,
+        semester: record.semester,
+    };
+
+    // The page of any semester lists the years and semesters there are
+    let first =
+        nilai_semester::fetch(&state, &session, cache_control.fresh, &current.request()).await?;
+    let periods = periods(
+        &first.data.year,
+        &first.data.semester,
+        current,
+        entry_year(&session.nrp),
+    );
+
+    // Fetching the first one may have logged in again, pointing the session elsewhere
+    let session_id = state
+        .sessions
+        .load(&session.token)
+        .await?
+        .map(|record| record.session_id)
+        .unwrap_or(session.session_id.clone());
+
+    let mut fetches = JoinSet::new();
+    let mut spawned = HashMap::new();
+    for period in periods {
+        let state = state.clone();
+        let session = ValidatedCookieJar {
+            token: session.token.clone(),
+            id: session.id.clone(),
+            session_id: session_id.clone(),
+            nrp: session.nrp.clone(),
+        };
+
+        // The grades of past semesters hardly change, they are fetched again once expired
+        let task = fetches.spawn(request_id::inherit(async move {
+            nilai_semester::fetch(&state, &session, false, &period.request()).await
+        }));
+        spawned.insert(task.id(), period);
+    }
+
+    let mut status = first.status;
+    let mut semesters = vec![(current, first.data.table)];
+    let mut failed = vec![];
+    while let Some(fetched) = fetches.join_next_with_id().await {
+        let (period, res) = match fetched {
+            Ok((id, res)) => (spawned[&id], res),
+            Err(e) => (spawned[&e.id()], Err(anyhow!(e.to_string()).into())),
+        };
+        match res {
+            Ok(data) => {
+                if data.status.age() > status.age() {
+                    status = data.status;
+                }
+                semesters.push((period, data.data.table));
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to fetch the grades of {}/{} for the transcript: {e}",
+                    period.year,
+                    period.semester
+                );
+                failed.push(period);
+            }
+        }
+    }
+    failed.sort();
+
+    let transkrip = TranskripResponse {
+        failed,
+        ..merge(semesters)
+    };
+    Ok(SuccessApiResponse::new(transkrip).with_headers(status.headers()))
+}
+
+/// Semesters the transcript covers when the student's nrp doesn't tell when they enrolled
+const MAX_SEMESTERS: usize = 14;
+
+/// The year a student enrolled in, from the third and fourth digits of their nrp, e.g.
+/// 2022 for `3122600001`
+fn entry_year(nrp: &str) -> Option<u16> {
+    if nrp.len() != 10 || !nrp.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(2000 + nrp[2..4].parse::<u16>().ok()?)
+}
+
+/// The semesters before `current` to fetch, out of the years and semesters Online MIS
+/// offers, since the student enrolled, or the last `MAX_SEMESTERS` with the current one
+/// when that isn't known
+fn periods(years: &[u16], semesters: &[u8], current: Period, entry: Option<u16>) -> Vec<Period> {
+    let mut periods: Vec<Period> = years
+        .iter()
+        .flat_map(|&year| {
+            semesters
+                .iter()
+                .map(move |&semester| Period { year, semester })
+        })
+        .filter(|period| *period < current && entry.is_none_or(|entry| period.year >= entry))
+        .collect();
+    periods.sort();
+    periods.dedup();
+
+    if entry.is_none() {
+        let skipped = periods.len().saturating_sub(MAX_SEMESTERS - 1);
+        periods.drain(..skipped);
+    }
+    periods
+}
+
+#[derive(Debug, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+struct Period {
+    pub year: u16,
+    pub semester: u8,
+}
+
+impl Period {
+    fn request(self) -> YearSemesterRequest {
+        YearSemesterRequest {
+            year: self.year,
+            semester: self.semester,
+        }
+    }
+}
+
+/// Merges the grades of the semesters into one row per course, ordered by when it was
+/// first taken
+fn merge(mut semesters: Vec<(Period, Vec<Table>)>) -> TranskripResponse {
+    semesters.sort_by_key(|(period, _)| *period);
+
+    let mut courses: Vec<Course> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (period, table) in &semesters {
+        for row in table {
+            // Rows without a code are told apart by their name
+            let key = match row.kode.is_empty() {
+                true => row.mata_kuliah.to_lowercase(),
+                false => row.kode.clone(),
+            };
+            let attempt = Attempt {
+                year: period.year,
+                semester: period.semester,
+                value: row.value.clone(),
+            };
+
+            match index.get(&key) {
+                Some(&i) => {
+                    let course = &mut courses[i];
+                    if !row.value.is_empty() {
+                        course.value = row.value.clone();
+                        course.mata_kuliah = row.mata_kuliah.clone();
+                    }
+                    course.attempts.push(attempt);
+                }
+                None => {
+                    index.insert(key, courses.len());
+                    courses.push(Course {
+                        kode: row.kode.clone(),
+                        mata_kuliah: row.mata_kuliah.clone(),
+                        value: row.value.clone(),
+                        attempts: vec![attempt],
+                    });
+                }
+            }
+        }
+    }
+
+    TranskripResponse {
+        periods: semesters.into_iter().map(|(period, _)| period).collect(),
+        failed: vec![],
+        table: courses,
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct TranskripResponse {
+    /// The semesters fetched, the oldest first
+    pub periods: Vec<Period>,
+    /// The semesters that couldn't be fetched, whose grades are missing
+    pub failed: Vec<Period>,
+    pub table: Vec<Course>,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct Course {
+    pub kode: String,
+    pub mata_kuliah: String,
+    /// The grade of the last attempt that has one, empty when none has
+    pub value: String,
+    /// Every semester the course was taken in, the oldest first
+    pub attempts: Vec<Attempt>,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct Attempt {
+    pub year: u16,
+    pub semester: u8,
+    pub value: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_year, merge, periods, Period};
+    use crate::http::features::academic::nilai_semester::Table;
+
+    fn row(kode: &str, mata_kuliah: &str, value: &str) -> Table {
+        Table {
+            kode: kode.to_owned(),
+            mata_kuliah: mata_kuliah.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn only_covers_the_semesters_since_enrollment() {
+        let period = |year, semester| Period { year, semester };
+        let years: Vec<u16> = (2015..=2025).rev().collect();
+        let current = period(2024, 1);
+
+        assert_eq!(entry_year("3122600001"), Some(2022));
+        assert_eq!(entry_year("31226"), None);
+        assert_eq!(
+            periods(&years, &[1, 2], current, entry_year("3122600001")),
+            [
+                period(2022, 1),
+                period(2022, 2),
+                period(2023, 1),
+                period(2023, 2)
+            ]
+        );
+
+        let unknown = periods(&years, &[1, 2], current, None);
+        assert_eq!(unknown.len(), 13);
+        assert_eq!(unknown[0], period(2017, 2));
+        assert_eq!(unknown.last(), Some(&period(2023, 2)));
+    }
+
+    #[test]
+    fn grades_retaken_courses_with_their_last_grade() {
+        let period = |year, semester| Period { year, semester };
+        let transkrip = merge(vec![
+            (
+                period(2023, 1),
+                vec![
+                    row("IT045201", "Basis Data", "A"),
+                    row("IT045202", "Statistika", "AB"),
+                ],
+            ),
+            (
+                period(2022, 2),
+                vec![row("IT045101", "Matematika 1", "D"), row("", "Agama", "A")],
+            ),
+            (
+                period(2023, 2),
+                vec![
+                    row("IT045101", "Matematika 1", "B"),
+                    row("IT045202", "Statistika", ""),
+                    row("", "AGAMA", ""),
+                ],
+            ),
+        ]);
+
+        assert_eq!(
+            transkrip.periods,
+            [period(2022, 2), period(2023, 1), period(2023, 2)]
+        );
+
+        let courses: Vec<_> = transkrip
+            .table
+            .iter()
+            .map(|course| (course.mata_kuliah.as_str(), course.value.as_str()))
+            .collect();
+        assert_eq!(
+            courses,
+            [
+                ("Matematika 1", "B"),
+                ("Agama", "A"),
+                ("Basis Data", "A"),
+                ("Statistika", "AB"),
+            ]
+        );
+        assert_eq!(transkrip.table[0].attempts.len(), 2);
+        assert_eq!(transkrip.table[0].attempts[0].value, "D");
+        assert_eq!(transkrip.table[1].attempts.len(), 2);
+    }
+}
@@ -1,24 +1,37 @@
 use aide::axum::{routing::post_with, ApiRouter};
 use anyhow::anyhow;
-use axum::{extract::State, http::header, response::AppendHeaders};
+use axum::{extract::State, http::header, response::AppendHeaders, Extension};
+use axum_extra::extract::cookie::Cookie;
 use base64::Engine;
-use redis::AsyncCommands;
 use reqwest::{cookie::CookieStore, Url};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::Arc, u8};
+use std::{str::FromStr, sync::Arc};
+use tracing::Instrument;
 
 use crate::{
     core::{
         api_response::{ApiResponseTrait, SuccessApiResponse},
         axum_extractor::{ValidatedCookieJar, ValidatedJson},
+        cache::invalidate_pages,
         error::Error,
         generate_openapi_response::generate_response,
-        helper::cache_helper::{self},
+        metrics::LOGIN_COUNTER,
+        rate_limit::NrpRateLimit,
+        selector::SelectorEntry,
+        session::TokenKind,
     },
-    http::{AppContext, Result},
+    http::{features::shared::relogin, AppContext, Result},
+    telemetry::hash_nrp,
 };
 
+pub const SELECTORS: &[SelectorEntry] = &[
+    ("cas.login_ticket", "[name=lt]"),
+    ("cas.errors", ".errors"),
+    ("home.body", "body"),
+    ("home.user", ".userout:last-child a"),
+];
+
 pub fn router() -> ApiRouter<AppContext> {
     ApiRouter::new()
         .api_route(
@@ -28,6 +41,13 @@ pub fn router() -> ApiRouter<AppContext> {
                 generate_response(login_handler, "Auth", false),
             ),
         )
+        .api_route(
+            "/refresh",
+            post_with(
+                refresh_handler,
+                generate_response(refresh_handler, "Auth", false),
+            ),
+        )
         .api_route(
             "/logout",
             post_with(
@@ -39,55 +59,71 @@ pub fn router() -> ApiRouter<AppContext> {
 
 #[axum::debug_handler]
 async fn logout_handler(
-    ValidatedCookieJar { nrp, .. }: ValidatedCookieJar,
+    ValidatedCookieJar { id, nrp, .. }: ValidatedCookieJar,
     State(state): State<AppContext>,
 ) -> Result<SuccessApiResponse<String>> {
-    let mut conn = cache_helper::get_conn(&state.redis_pool).await?;
+    state.sessions.revoke(&nrp, &id).await?;
     let headers = AppendHeaders(vec![
         (
             header::SET_COOKIE,
-            "SESSION_ID=; HttpOnly; SameSite=Lax; Erpires=Thu, Jan 01 1970 00:00:00 UTC; Path=/"
-                .to_owned(),
+            session_cookie(&state, "SESSION_ID", None, true),
         ),
         (
             header::SET_COOKIE,
-            "SESSION_DATA=; SameSite=Lax; Expires=Thu, Jan 01 1970 00:00:00 UTC; Path=/".to_owned(),
+            session_cookie(&state, "SESSION_DATA", None, false),
         ),
     ]);
 
-    let keys = {
-        let mut iter = conn
-            .scan_match::<String, String>(format!("*{}*", nrp))
-            .await?;
-
-        let mut keys = Vec::new();
-        while let Some(key) = iter.next_item().await {
-            keys.push(key);
-        }
-        keys
-    };
-
-    for key in keys {
-        let () = conn.del(key).await?;
+    // The cached pages and credentials are shared by every session of the user, only
+    // forget them once the last one is gone
+    if !state.sessions.list(&nrp).await?.is_empty() {
+        return Ok(SuccessApiResponse::new("Logout Success".to_owned()).with_headers(headers));
     }
 
+    invalidate_pages(state.cache.as_ref(), &nrp).await?;
+    relogin::forget_credentials(&state, &nrp).await?;
+
     Ok(SuccessApiResponse::new("Logout Success".to_owned()).with_headers(headers))
 }
 
 #[axum::debug_handler]
 async fn login_handler(
     State(state): State<AppContext>,
+    rate_limit: Option<Extension<NrpRateLimit>>,
     ValidatedJson(input): ValidatedJson<LoginRequest>,
 ) -> Result<SuccessApiResponse<LoginResponse>> {
-    let res = login_cas(input, state.proxy_url).await?;
+    // The nrp isn't known yet, guessing passwords is limited per email instead
+    if let Some(Extension(limit)) = rate_limit {
+        limit.check_email(&input.email.to_lowercase()).await?;
+    }
 
-    let session_id = base64::engine::general_purpose::STANDARD.encode(
-        serde_json::to_string(&ValidatedCookieJar {
-            nrp: res.nrp.clone(),
-            session_id: res.session_id.clone(),
-        })
-        .map_err(|e| anyhow!(e.to_string()))?,
-    );
+    let (email, password, remember) = (input.email.clone(), input.password.clone(), input.remember);
+    let with_tokens = input.tokens;
+    let res = login_cas(input, &state).await;
+    let result = match &res {
+        Ok(_) => "success",
+        // CAS rejected the credentials
+        Err(Error::BadRequest(_)) => "failure",
+        Err(_) => "error",
+    };
+    metrics::counter!(LOGIN_COUNTER, "result" => result).increment(1);
+    let mut res = res?;
+    res.relogin =
+        relogin::remember_credentials(&state, &res.nrp, email, password, remember).await?;
+
+    let token = state
+        .sessions
+        .create(
+            res.nrp.clone(),
+            res.session_id.clone(),
+            (res.year, res.semester, res.week),
+            res.user.clone(),
+        )
+        .await?;
+    if with_tokens {
+        res.tokens = Some(issue_tokens(&state, &token)?);
+    }
+    let session_id = state.sessions.seal(&token, TokenKind::Cookie)?;
 
     let session_data = base64::engine::general_purpose::STANDARD.encode(
         serde_json::to_string(&SesssionData {
@@ -102,20 +138,73 @@ async fn login_handler(
     let headers = AppendHeaders(vec![
         (
             header::SET_COOKIE,
-            format!("SESSION_ID={}; HttpOnly; SameSite=Lax; Path=/", session_id),
+            session_cookie(&state, "SESSION_ID", Some(session_id), true),
         ),
         (
             header::SET_COOKIE,
-            format!("SESSION_DATA={}; SameSite=Lax; Path=/", session_data),
+            session_cookie(&state, "SESSION_DATA", Some(session_data), false),
         ),
     ]);
 
     Ok(SuccessApiResponse::new(res).with_headers(headers))
 }
 
-async fn login_cas(
-    LoginRequest { email, password }: LoginRequest,
-    proxy_url: Option<String>,
+#[axum::debug_handler]
+async fn refresh_handler(
+    State(state): State<AppContext>,
+    ValidatedJson(input): ValidatedJson<RefreshRequest>,
+) -> Result<SuccessApiResponse<TokenResponse>> {
+    let session = state
+        .sessions
+        .resolve(&input.refresh_token, TokenKind::Refresh)
+        .await
+        .map_err(|e| match e {
+            Error::Redis(_) => e,
+            _ => Error::Unauthorized("Invalid refresh token".to_owned()),
+        })?;
+
+    match session {
+        Some((token, _)) => Ok(SuccessApiResponse::new(issue_tokens(&state, &token)?)),
+        None => Err(Error::Unauthorized("Invalid refresh token".to_owned())),
+    }
+}
+
+fn issue_tokens(state: &AppContext, token: &str) -> Result<TokenResponse> {
+    Ok(TokenResponse {
+        access_token: state.sessions.seal(token, TokenKind::Access)?,
+        refresh_token: state.sessions.seal(token, TokenKind::Refresh)?,
+        token_type: "Bearer".to_owned(),
+        expires_in: state.sessions.access_ttl(),
+    })
+}
+
+/// Builds a `Set-Cookie` value for a session cookie, removing it when there is no value
+fn session_cookie(
+    state: &AppContext,
+    name: &'static str,
+    value: Option<String>,
+    http_only: bool,
+) -> String {
+    let removal = value.is_none();
+    let mut cookie = Cookie::build((name, value.unwrap_or_default()))
+        .path("/")
+        .http_only(http_only)
+        .same_site(state.cookie_same_site)
+        .secure(state.cookie_secure)
+        .build();
+    if removal {
+        cookie.make_removal();
+    }
+
+    cookie.to_string()
+}
+
+#[tracing::instrument(skip_all, fields(nrp))]
+pub(super) async fn login_cas(
+    LoginRequest {
+        email, password, ..
+    }: LoginRequest,
+    state: &AppContext,
 ) -> Result<LoginResponse> {
     let jar = Arc::new(reqwest::cookie::Jar::default());
     let custom_redirect = reqwest::redirect::Policy::custom(|attempt| {
@@ -125,10 +214,13 @@ async fn login_cas(
         }
         attempt.follow()
     });
-    let reqwest_builder = reqwest::ClientBuilder::new()
+    let reqwest_builder = state
+        .upstream
+        .config()
+        .client_builder()
         .cookie_provider(Arc::clone(&jar))
         .redirect(custom_redirect);
-    let client = match proxy_url {
+    let client = match state.proxy_url.clone() {
         Some(url) => {
             tracing::debug!("Using proxy for login request: '{url}'");
             reqwest_builder.proxy(reqwest::Proxy::all(url)?).build()?
@@ -139,7 +231,20 @@ async fn login_cas(
         }
     };
 
-    let res = client.get("https://login.pens.ac.id/cas/login?service=https%3A%2F%2Fonline.mis.pens.ac.id%2Findex.php%3FLogin%3D1%26halAwal%3D1").send().await?;
+    let login_url = Url::parse_with_params(
+        &format!("{}/login", state.cas_base_url),
+        &[(
+            "service",
+            format!("{}/index.php?Login=1&halAwal=1", state.mis_base_url),
+        )],
+    )
+    .map_err(|_| anyhow!("Failed to parse cas url"))?;
+
+    let res = state
+        .upstream
+        .send(&email, client.get(login_url.clone()))
+        .instrument(tracing::info_span!("cas_login_form"))
+        .await?;
 
     let jsession_id = res
         .cookies()
@@ -152,9 +257,9 @@ async fn login_cas(
 
     let lt = {
         let doc = scraper::Html::parse_document(&body);
-        let selector = scraper::Selector::parse("[name=lt]").map_err(|e| anyhow!(e.to_string()))?;
+        let selector = state.selectors.get("cas.login_ticket")?;
 
-        doc.select(&selector)
+        doc.select(selector)
             .next()
             .ok_or_else(|| anyhow!("Element lt not found"))?
             .value()
@@ -163,6 +268,9 @@ async fn login_cas(
             .to_owned()
     };
 
+    // Requests are limited by email until the nrp is known
+    let user = email.clone();
+
     let login = {
         let params = [
             ("username", email),
@@ -172,47 +280,49 @@ async fn login_cas(
             ("lt", lt),
         ];
 
-        client.post("https://login.pens.ac.id/cas/login?service=https%3A%2F%2Fonline.mis.pens.ac.id%2Findex.php%3FLogin%3D1%26halAwal%3D1")
-        .form(&params)
-        .header("Cookie", format!("JSESSIONID={}", jsession_id))
-        .send().await?
+        let request = client
+            .post(login_url)
+            .form(&params)
+            .header("Cookie", format!("JSESSIONID={}", jsession_id));
+
+        state
+            .upstream
+            .send(&user, request)
+            .instrument(tracing::info_span!("cas_submit_credentials"))
+            .await?
     };
 
     {
         let login_body = login.text().await?;
         let login_doc = scraper::Html::parse_document(&login_body);
-        let selector = scraper::Selector::parse(".errors").map_err(|e| anyhow!(e.to_string()))?;
-
-        match login_doc.select(&selector).next() {
-            Some(e) => match e.inner_html().is_empty() {
-                false => {
-                    tracing::debug!(
-                        "Bad Request from online mis with message: '{}'",
-                        e.inner_html()
-                    );
-                    return Err(Error::BadRequest(e.inner_html().to_owned()));
-                }
-                _ => (),
-            },
-            _ => (),
+        let selector = state.selectors.get("cas.errors")?;
+
+        if let Some(e) = login_doc.select(selector).next() {
+            if !e.inner_html().is_empty() {
+                tracing::debug!(
+                    "Bad Request from online mis with message: '{}'",
+                    e.inner_html()
+                );
+                return Err(Error::BadRequest(e.inner_html().to_owned()));
+            }
         }
     };
 
     let session_id = {
         jar.cookies(
-            &Url::from_str("https://online.mis.pens.ac.id")
-                .map_err(|_| anyhow!("Failed to parse url"))?,
+            &Url::from_str(&state.mis_base_url).map_err(|_| anyhow!("Failed to parse url"))?,
         )
         .ok_or_else(|| anyhow!("Failed to get cookies for `session_id`"))?
     };
 
     let home_doc = {
-        let res = client
-            .get("https://online.mis.pens.ac.id/mEntry_Logbook_KP1.php")
-            .header("Cookie", session_id.clone())
-            .send()
-            .await?
-            .text()
+        let request = client
+            .get(format!("{}/mEntry_Logbook_KP1.php", state.mis_base_url))
+            .header("Cookie", session_id.clone());
+        let res = state
+            .upstream
+            .text(&user, request)
+            .instrument(tracing::info_span!("mis_home"))
             .await?;
 
         scraper::Html::parse_document(&res)
@@ -221,9 +331,9 @@ async fn login_cas(
     let (year, semester, week): (u16, u8, u8) = {
         let re = regex::Regex::new(r"showEntry_Logbook_KP1\((.*?), (.*?), (.*?)\)")
             .map_err(|e| anyhow!(e))?;
-        let selector = scraper::Selector::parse("body").map_err(|e| anyhow!(e.to_string()))?;
+        let selector = state.selectors.get("home.body")?;
         let data = home_doc
-            .select(&selector)
+            .select(selector)
             .next()
             .ok_or_else(|| anyhow!("Body not found"))?
             .attr("onload")
@@ -253,10 +363,9 @@ async fn login_cas(
     };
 
     let user_text = {
-        let selector = scraper::Selector::parse(".userout:last-child a")
-            .map_err(|e| anyhow!(e.to_string()))?;
+        let selector = state.selectors.get("home.user")?;
         home_doc
-            .select(&selector)
+            .select(selector)
             .next()
             .ok_or_else(|| anyhow!("User not found"))?
             .text()
@@ -284,17 +393,21 @@ async fn login_cas(
             .to_owned()
     };
 
+    tracing::Span::current().record("nrp", hash_nrp(&nrp));
+
     Ok(LoginResponse {
         nrp,
         year,
         week,
         semester,
         user,
+        relogin: false,
+        tokens: None,
         session_id: session_id
             .to_str()
             .unwrap_or_default()
             .split('=')
-            .last()
+            .next_back()
             .unwrap_or_default()
             .to_owned(),
     })
@@ -302,13 +415,39 @@ async fn login_cas(
 
 #[derive(Debug, Serialize, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
-struct LoginResponse {
+pub(super) struct LoginResponse {
     pub user: String,
     pub nrp: String,
+    /// The Online MIS `PHPSESSID`, it never leaves the server
+    #[serde(skip)]
     pub session_id: String,
     pub year: u16,
     pub semester: u8,
     pub week: u8,
+    /// Whether the backend logs in again on its own once the Online MIS session expires
+    pub relogin: bool,
+    /// Only returned when requested with `tokens`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<TokenResponse>,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct TokenResponse {
+    /// Sent as `Authorization: Bearer <accessToken>` instead of the `SESSION_ID` cookie
+    pub access_token: String,
+    /// Exchanged for new tokens at `/refresh` once the access token expired
+    pub refresh_token: String,
+    pub token_type: String,
+    /// How long the access token is valid, in seconds
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct RefreshRequest {
+    #[schemars(length(min = 1), required(message = "Refresh token is required"))]
+    pub refresh_token: String,
 }
 
 #[derive(Debug, Serialize, JsonSchema, Default)]
@@ -321,7 +460,7 @@ struct SesssionData {
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
-struct LoginRequest {
+pub(super) struct LoginRequest {
     #[schemars(
         email(message = "Invalid email"),
         required(message = "Email is required")
@@ -329,4 +468,13 @@ struct LoginRequest {
     pub email: String,
     #[schemars(length(min = 1), required(message = "Password is required"))]
     pub password: String,
+    /// Keep the credentials encrypted on the server to log in again once the Online MIS
+    /// session expires, only honored when the server has automatic re-login enabled.
+    /// `false` forgets them for every session of the user, leaving it out keeps the
+    /// choice made before
+    #[serde(default)]
+    pub remember: Option<bool>,
+    /// Also return an access and a refresh token, for clients that can't keep cookies
+    #[serde(default)]
+    pub tokens: bool,
 }
@@ -0,0 +1,474 @@
+use std::{future::Future, time::SystemTime};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use aide::axum::{
+    routing::{get_with, post_with},
+    ApiRouter,
+};
+use anyhow::anyhow;
+use axum::{extract::State, http::HeaderName, response::AppendHeaders};
+use base64::Engine;
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use time::{macros::offset, OffsetDateTime, PrimitiveDateTime, Time};
+
+use crate::{
+    core::{
+        api_response::{ApiResponseTrait, SuccessApiResponse},
+        axum_extractor::{ValidatedCookieJar, ValidatedPath},
+        cache::{Cache, Expiry},
+        error::Error,
+        generate_openapi_response::{generate_ics_response, generate_response},
+        ical::{Calendar, Event, Ics},
+    },
+    http::{
+        features::{
+            academic::{
+                deadlines,
+                frs::{self, FrsResponse},
+                jadwal_ics::{self, semester_bounds},
+                jadwal_kuliah::{self, JadwalKuliahResponse},
+            },
+            shared::year_semester_request::YearSemesterRequest,
+        },
+        AppContext, Result,
+    },
+};
+
+const OPENAPI_TAG: &str = "Feeds";
+
+pub fn router() -> ApiRouter<AppContext> {
+    ApiRouter::new()
+        .api_route(
+            "/feeds",
+            post_with(
+                issue_handler,
+                generate_response(issue_handler, OPENAPI_TAG, true),
+            )
+            .delete_with(
+                revoke_handler,
+                generate_response(revoke_handler, OPENAPI_TAG, true),
+            ),
+        )
+        .api_route(
+            "/feeds/{token}/jadwal.ics",
+            get_with(
+                feed_handler::<JadwalKuliahResponse>,
+                generate_ics_response(OPENAPI_TAG, false),
+            ),
+        )
+        .api_route(
+            "/feeds/{token}/deadlines.ics",
+            get_with(
+                feed_handler::<FrsResponse>,
+                generate_ics_response(OPENAPI_TAG, false),
+            ),
+        )
+}
+
+/// The nrp a feed token belongs to
+fn feed_key(token: &str) -> String {
+    format!("feed:{}", token)
+}
+
+/// The feed token of a user
+fn token_key(nrp: &str) -> String {
+    format!("feeds:{}", nrp)
+}
+
+/// What a feed of a user served last
+fn copy_key(nrp: &str, name: &str) -> String {
+    format!("feeds:{}:{}", nrp, name)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Forgets the feed token of a user, returning whether there was one
+async fn revoke(cache: &dyn Cache, nrp: &str) -> Result<bool> {
+    let Some(token) = cache.get(&token_key(nrp)).await? else {
+        return Ok(false);
+    };
+
+    cache.del(&feed_key(&token)).await?;
+    cache.del(&token_key(nrp)).await?;
+    for name in [JadwalKuliahResponse::NAME, FrsResponse::NAME] {
+        cache.del(&copy_key(nrp, name)).await?;
+    }
+
+    Ok(true)
+}
+
+/// Issues a new feed token for a user, revoking the previous one
+async fn issue(cache: &dyn Cache, nrp: &str) -> Result<String> {
+    revoke(cache, nrp).await?;
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+    cache
+        .set(&feed_key(&token), nrp.to_owned(), Expiry::Never)
+        .await?;
+    cache
+        .set(&token_key(nrp), token.clone(), Expiry::Never)
+        .await?;
+
+    Ok(token)
+}
+
+/// Issues a feed token for the user, replacing the previous one. Anyone with the token can
+/// read the schedule and the FRS deadlines without logging in, until it is revoked.
+#[axum::debug_handler]
+async fn issue_handler(
+    ValidatedCookieJar { nrp, .. }: ValidatedCookieJar,
+    State(state): State<AppContext>,
+) -> Result<SuccessApiResponse<FeedResponse>> {
+    let token = issue(state.cache.as_ref(), &nrp).await?;
+
+    Ok(SuccessApiResponse::new(FeedResponse {
+        jadwal_url: format!("/api/v1/feeds/{token}/jadwal.ics"),
+        deadlines_url: format!("/api/v1/feeds/{token}/deadlines.ics"),
+        token,
+    }))
+}
+
+#[axum::debug_handler]
+async fn revoke_handler(
+    ValidatedCookieJar { nrp, .. }: ValidatedCookieJar,
+    State(state): State<AppContext>,
+) -> Result<SuccessApiResponse<String>> {
+    if !revoke(state.cache.as_ref(), &nrp).await? {
+        return Err(Error::NotFound);
+    }
+
+    Ok(SuccessApiResponse::new("Feed Revoked".to_owned()))
+}
+
+/// Why a feed serves what it served last instead of fetching it again
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stale {
+    /// The user has no session left to fetch it with
+    NoSession,
+    /// Online MIS rejected the session, and the user can't be logged in again
+    SessionExpired,
+    /// Online MIS can't be reached and nothing is cached
+    Unavailable,
+}
+
+impl Stale {
+    /// Why a feed couldn't be fetched, or the error itself when the feed should fail
+    fn from_error(e: Error) -> Result<Self> {
+        match e {
+            Error::Unauthorized(_) => Ok(Self::SessionExpired),
+            e if e.is_upstream_down() => Ok(Self::Unavailable),
+            e => Err(e),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::NoSession => "no-session",
+            Self::SessionExpired => "session-expired",
+            Self::Unavailable => "unavailable",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::NoSession | Self::SessionExpired => {
+                "This calendar is no longer updated, log in again to refresh it"
+            }
+            Self::Unavailable => "Online MIS is unavailable, this calendar may be out of date",
+        }
+    }
+}
+
+/// A page of the current semester served as a calendar in a feed
+trait FeedCalendar: Serialize + DeserializeOwned + Send + Sync + Sized + 'static {
+    /// The name of the feed in its path, `/feeds/{token}/{NAME}.ics`
+    const NAME: &'static str;
+    /// The name of the calendar when nothing was ever fetched
+    const TITLE: &'static str;
+
+    fn fetch(
+        state: &AppContext,
+        session: &ValidatedCookieJar,
+        semester: &YearSemesterRequest,
+    ) -> impl Future<Output = Result<Self>> + Send;
+
+    fn calendar(&self, nrp: &str, semester: &YearSemesterRequest) -> Result<Calendar>;
+}
+
+impl FeedCalendar for JadwalKuliahResponse {
+    const NAME: &'static str = "jadwal";
+    const TITLE: &'static str = "Jadwal Kuliah";
+
+    async fn fetch(
+        state: &AppContext,
+        session: &ValidatedCookieJar,
+        semester: &YearSemesterRequest,
+    ) -> Result<Self> {
+        Ok(jadwal_kuliah::fetch(state, session, false, semester)
+            .await?
+            .data)
+    }
+
+    fn calendar(&self, nrp: &str, semester: &YearSemesterRequest) -> Result<Calendar> {
+        let (start, end) = semester_bounds(semester.year, semester.semester)?;
+        Ok(jadwal_ics::calendar(self, nrp, semester, start, end))
+    }
+}
+
+impl FeedCalendar for FrsResponse {
+    const NAME: &'static str = "deadlines";
+    const TITLE: &'static str = "Tanggal Penting FRS";
+
+    async fn fetch(
+        state: &AppContext,
+        session: &ValidatedCookieJar,
+        semester: &YearSemesterRequest,
+    ) -> Result<Self> {
+        Ok(frs::fetch(state, session, false, semester).await?.data)
+    }
+
+    fn calendar(&self, nrp: &str, semester: &YearSemesterRequest) -> Result<Calendar> {
+        Ok(deadlines::calendar(self, nrp, semester))
+    }
+}
+
+/// What a feed served last, kept under `feeds:{nrp}:{name}` to serve it again once it
+/// can't be fetched anymore
+#[derive(Debug, Serialize, Deserialize)]
+struct FeedCopy<T> {
+    year: u16,
+    semester: u8,
+    fetched_at: u64,
+    data: T,
+}
+
+impl<T: FeedCalendar> FeedCopy<T> {
+    fn semester(&self) -> YearSemesterRequest {
+        YearSemesterRequest {
+            year: self.year,
+            semester: self.semester,
+        }
+    }
+
+    async fn save(&self, cache: &dyn Cache, nrp: &str) -> Result<()> {
+        let value = serde_json::to_string(self).map_err(|e| anyhow!(e.to_string()))?;
+        cache
+            .set(&copy_key(nrp, T::NAME), value, Expiry::Never)
+            .await
+    }
+
+    /// The copy served last, if any could be read
+    async fn load(cache: &dyn Cache, nrp: &str) -> Result<Option<Self>> {
+        Ok(cache
+            .get(&copy_key(nrp, T::NAME))
+            .await?
+            .and_then(|value| serde_json::from_str(&value).ok()))
+    }
+}
+
+/// Fetches the page of the current semester with the session the user used last, through
+/// the cache
+async fn fetch_copy<T: FeedCalendar>(
+    state: &AppContext,
+    nrp: &str,
+) -> Result<Result<FeedCopy<T>, Stale>> {
+    let Some((token, record)) = state.sessions.latest(nrp).await? else {
+        return Ok(Err(Stale::NoSession));
+    };
+
+    let session = ValidatedCookieJar {
+        token,
+        id: record.id,
+        session_id: record.session_id,
+        nrp: nrp.to_owned(),
+    };
+    let semester = YearSemesterRequest {
+        year: record.year,
+        semester: record.semester,
+    };
+
+    match T::fetch(state, &session, &semester).await {
+        Ok(data) => Ok(Ok(FeedCopy {
+            year: semester.year,
+            semester: semester.semester,
+            fetched_at: now(),
+            data,
+        })),
+        Err(e) => Ok(Err(Stale::from_error(e)?)),
+    }
+}
+
+/// A calendar of the current semester as an iCalendar file, for calendar apps that can't
+/// log in.
+///
+/// When it can't be fetched, e.g. because the Online MIS session expired, the calendar
+/// served last is served again with an all day event telling so, and the reason in the
+/// `Feed-Stale` header.
+async fn feed_handler<T: FeedCalendar>(
+    State(state): State<AppContext>,
+    ValidatedPath(path): ValidatedPath<FeedParamRequest>,
+) -> Result<Ics> {
+    let Some(nrp) = state.cache.get(&feed_key(&path.token)).await? else {
+        return Err(Error::NotFound);
+    };
+
+    let (copy, stale) = match fetch_copy::<T>(&state, &nrp).await? {
+        Ok(copy) => {
+            copy.save(state.cache.as_ref(), &nrp).await?;
+            (Some(copy), None)
+        }
+        Err(stale) => {
+            tracing::debug!(
+                "Serving the last {} feed of '{nrp}', {}",
+                T::NAME,
+                stale.name()
+            );
+            let copy = FeedCopy::<T>::load(state.cache.as_ref(), &nrp).await?;
+            (copy, Some(stale))
+        }
+    };
+
+    render(&nrp, copy.as_ref(), stale, OffsetDateTime::now_utc())
+}
+
+/// Renders the calendar of `copy`, marked as stale with an all day event and the
+/// `Feed-Stale` header when it couldn't be fetched again
+fn render<T: FeedCalendar>(
+    nrp: &str,
+    copy: Option<&FeedCopy<T>>,
+    stale: Option<Stale>,
+    now: OffsetDateTime,
+) -> Result<Ics> {
+    let mut cal = match &copy {
+        Some(copy) => copy.data.calendar(nrp, &copy.semester())?,
+        None => Calendar::new(T::TITLE),
+    };
+
+    let mut headers = vec![];
+    if let Some(stale) = stale {
+        let today = now.to_offset(offset!(+7)).date();
+        cal.push(Event {
+            uid: format!("feed-stale-{}-{today}@online-mis-wrapper", T::NAME),
+            summary: stale.message().to_owned(),
+            location: None,
+            description: copy.map(|copy| {
+                let fetched_at = OffsetDateTime::from_unix_timestamp(copy.fetched_at as i64)
+                    .map(|at| at.to_offset(offset!(+7)).date().to_string())
+                    .unwrap_or_default();
+                format!("Last updated on {fetched_at}")
+            }),
+            start: PrimitiveDateTime::new(today, Time::MIDNIGHT),
+            end: PrimitiveDateTime::new(today, Time::MIDNIGHT),
+            all_day: true,
+            weekly_until: None,
+            remind_before_end: None,
+        });
+        cal = cal.with_description(stale.message());
+        headers.push((
+            HeaderName::from_static("feed-stale"),
+            stale.name().to_owned(),
+        ));
+    }
+
+    Ok(Ics::new(cal.render(now)).with_headers(AppendHeaders(headers)))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+struct FeedParamRequest {
+    #[schemars(length(min = 1))]
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct FeedResponse {
+    /// Revoked with `DELETE /api/v1/feeds` or by issuing another one
+    pub token: String,
+    /// The paths to subscribe to from a calendar app, relative to the server
+    pub jadwal_url: String,
+    pub deadlines_url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, response::IntoResponse};
+    use time::macros::datetime;
+
+    use super::{feed_key, issue, render, revoke, FeedCopy, Stale};
+    use crate::{
+        core::{
+            cache::{invalidate_pages, Cache, MemoryCache},
+            error::Error,
+        },
+        http::features::academic::jadwal_kuliah::JadwalKuliahResponse,
+    };
+
+    const NRP: &str = "3122600001";
+
+    #[tokio::test]
+    async fn invalidating_the_cache_keeps_the_feed_revocable() {
+        let cache = MemoryCache::new(16);
+        let token = issue(&cache, NRP).await.unwrap();
+
+        invalidate_pages(&cache, NRP).await.unwrap();
+        assert_eq!(
+            cache.get(&feed_key(&token)).await.unwrap().as_deref(),
+            Some(NRP)
+        );
+
+        assert!(revoke(&cache, NRP).await.unwrap());
+        assert_eq!(cache.get(&feed_key(&token)).await.unwrap(), None);
+        assert!(!revoke(&cache, NRP).await.unwrap());
+    }
+
+    #[test]
+    fn tells_why_a_feed_is_stale() {
+        let expired = Stale::from_error(Error::Unauthorized("Unauthorized".to_owned()));
+        assert_eq!(expired.unwrap(), Stale::SessionExpired);
+        let down = Stale::from_error(Error::UpstreamUnavailable);
+        assert_eq!(down.unwrap(), Stale::Unavailable);
+        assert!(matches!(
+            Stale::from_error(Error::NotFound),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn serves_the_last_copy_marked_as_stale() {
+        let cache = MemoryCache::new(16);
+        let copy = FeedCopy {
+            year: 2024,
+            semester: 2,
+            fetched_at: datetime!(2025-03-01 10:00 UTC).unix_timestamp() as u64,
+            data: JadwalKuliahResponse::default(),
+        };
+        copy.save(&cache, NRP).await.unwrap();
+        let copy = FeedCopy::<JadwalKuliahResponse>::load(&cache, NRP)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((copy.year, copy.semester), (2024, 2));
+
+        let now = datetime!(2025-03-10 10:00 UTC);
+        let fresh = render(NRP, Some(&copy), None, now).unwrap().into_response();
+        assert!(fresh.headers().get("feed-stale").is_none());
+
+        let stale = render(NRP, Some(&copy), Some(Stale::SessionExpired), now)
+            .unwrap()
+            .into_response();
+        assert_eq!(stale.headers()["feed-stale"], "session-expired");
+        let body = to_bytes(stale.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("SUMMARY:This calendar is no longer updated\\, log in again"));
+        assert!(body.contains("Last updated on 2025-03-01"));
+    }
+}
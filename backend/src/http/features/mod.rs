@@ -1,15 +1,25 @@
 use aide::axum::ApiRouter;
 
+use crate::core::selector::SelectorEntry;
+
 use super::AppContext;
 
 mod academic;
 mod auth;
+mod feeds;
 mod others;
+mod sessions;
 mod shared;
 
 pub fn router() -> ApiRouter<AppContext> {
     ApiRouter::new()
         .merge(auth::router())
+        .merge(sessions::router())
+        .merge(feeds::router())
         .merge(academic::router())
         .merge(others::router())
 }
+
+pub fn selectors() -> impl Iterator<Item = &'static SelectorEntry> {
+    auth::SELECTORS.iter().chain(academic::selectors())
+}
@@ -20,7 +20,8 @@ pub fn endpoint() -> ApiRouter<AppContext> {
 #[axum::debug_handler]
 async fn handler(State(state): State<AppContext>) -> Result<SuccessApiResponse<String>> {
     let res = state
-        .client
+        .upstream
+        .client()
         .get("https://icanhazip.com")
         .send()
         .await?
@@ -1,11 +1,10 @@
 use aide::axum::{routing::post_with, ApiRouter};
 use axum::extract::State;
-use redis::AsyncCommands;
 
 use crate::{
     core::{
         api_response::SuccessApiResponse, axum_extractor::ValidatedCookieJar,
-        generate_openapi_response::generate_response, helper::cache_helper,
+        cache::invalidate_pages, generate_openapi_response::generate_response,
     },
     http::AppContext,
 };
@@ -26,23 +25,7 @@ async fn handler(
     ValidatedCookieJar { nrp, .. }: ValidatedCookieJar,
     State(state): State<AppContext>,
 ) -> Result<SuccessApiResponse<String>> {
-    let mut conn = cache_helper::get_conn(&state.redis_pool).await?;
-
-    let keys = {
-        let mut iter = conn
-            .scan_match::<String, String>(format!("*{}*", nrp))
-            .await?;
-
-        let mut keys = Vec::new();
-        while let Some(key) = iter.next_item().await {
-            keys.push(key);
-        }
-        keys
-    };
-
-    for key in keys {
-        let () = conn.del(key).await?;
-    }
+    invalidate_pages(state.cache.as_ref(), &nrp).await?;
 
     Ok(SuccessApiResponse::new(
         "Cache invalidated successfully".to_owned(),
@@ -1,3 +1,5 @@
+// Not routed by default, wire it up when debugging the egress ip of the proxy
+#[allow(dead_code)]
 mod check_ip;
 mod invalidate_cache;
 
@@ -0,0 +1,92 @@
+use aide::axum::{
+    routing::{delete_with, get_with},
+    ApiRouter,
+};
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{
+        api_response::{ApiResponseTrait, SuccessApiResponse},
+        axum_extractor::{ValidatedCookieJar, ValidatedPath},
+        error::Error,
+        generate_openapi_response::generate_response,
+    },
+    http::{AppContext, Result},
+};
+
+pub fn router() -> ApiRouter<AppContext> {
+    ApiRouter::new()
+        .api_route(
+            "/sessions",
+            get_with(list_handler, generate_response(list_handler, "Auth", true)),
+        )
+        .api_route(
+            "/sessions/{id}",
+            delete_with(
+                revoke_handler,
+                generate_response(revoke_handler, "Auth", true),
+            ),
+        )
+}
+
+#[axum::debug_handler]
+async fn list_handler(
+    ValidatedCookieJar { id, nrp, .. }: ValidatedCookieJar,
+    State(state): State<AppContext>,
+) -> Result<SuccessApiResponse<Vec<SessionResponse>>> {
+    let sessions = state
+        .sessions
+        .list(&nrp)
+        .await?
+        .into_iter()
+        .map(|s| SessionResponse {
+            current: s.id == id,
+            id: s.id,
+            user: s.user,
+            year: s.year,
+            semester: s.semester,
+            week: s.week,
+            created_at: s.created_at,
+            last_seen_at: s.last_seen_at,
+        })
+        .collect();
+
+    Ok(SuccessApiResponse::new(sessions))
+}
+
+#[axum::debug_handler]
+async fn revoke_handler(
+    ValidatedCookieJar { nrp, .. }: ValidatedCookieJar,
+    State(state): State<AppContext>,
+    ValidatedPath(path): ValidatedPath<SessionParamRequest>,
+) -> Result<SuccessApiResponse<String>> {
+    if !state.sessions.revoke(&nrp, &path.id).await? {
+        return Err(Error::NotFound);
+    }
+
+    Ok(SuccessApiResponse::new("Session Revoked".to_owned()))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default)]
+struct SessionParamRequest {
+    #[schemars(length(min = 1))]
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct SessionResponse {
+    pub id: String,
+    pub user: String,
+    pub year: u16,
+    pub semester: u8,
+    pub week: u8,
+    /// Unix timestamp, in seconds
+    pub created_at: u64,
+    /// Unix timestamp, in seconds
+    pub last_seen_at: u64,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
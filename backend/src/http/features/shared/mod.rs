@@ -1 +1,2 @@
+pub mod relogin;
 pub mod year_semester_request;
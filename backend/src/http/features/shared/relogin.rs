@@ -0,0 +1,292 @@
+use std::{future::Future, sync::Arc};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{
+        axum_extractor::ValidatedCookieJar,
+        cache::{Cache, Expiry},
+        error::Error,
+        handler::Relogin,
+        single_flight::SingleFlight,
+    },
+    http::{
+        features::auth::{login_cas, LoginRequest},
+        AppContext, Result,
+    },
+};
+
+#[derive(Serialize, Deserialize)]
+struct Credentials {
+    email: String,
+    password: String,
+}
+
+fn credential_key(nrp: &str) -> String {
+    format!("credential:{}", nrp)
+}
+
+/// The Online MIS session the user was last logged in to again with
+fn relogin_key(nrp: &str) -> String {
+    format!("relogin:{}", nrp)
+}
+
+/// How long a re-login is shared with the requests whose session expired along with it
+const RELOGIN_TTL: u64 = 60;
+
+/// Keeps the encrypted credentials of a user who opted in to automatic re-login, or
+/// forgets them when they opted out. Without a choice, the credentials kept for the
+/// user's other sessions are left alone. Returns whether they are kept.
+pub async fn remember_credentials(
+    state: &AppContext,
+    nrp: &str,
+    email: String,
+    password: String,
+    remember: Option<bool>,
+) -> Result<bool> {
+    match (&state.credential_cipher, remember) {
+        (Some(cipher), Some(true)) => {
+            let credentials = serde_json::to_vec(&Credentials { email, password })
+                .map_err(|e| anyhow!(e.to_string()))?;
+            state
+                .cache
+                .set(
+                    &credential_key(nrp),
+                    cipher.encrypt(&credentials)?,
+                    Expiry::In(state.credential_ttl),
+                )
+                .await?;
+            Ok(true)
+        }
+        (_, Some(false)) => {
+            forget_credentials(state, nrp).await?;
+            Ok(false)
+        }
+        (Some(_), None) => Ok(state.cache.get(&credential_key(nrp)).await?.is_some()),
+        (None, _) => Ok(false),
+    }
+}
+
+/// Forgets the stored credentials of the user, turning automatic re-login off
+pub async fn forget_credentials(state: &AppContext, nrp: &str) -> Result<()> {
+    state.cache.del(&credential_key(nrp)).await
+}
+
+/// Runs `request` with the user's Online MIS session.
+///
+/// When the session expired and the user opted in to automatic re-login, logs in to
+/// CAS again with the stored credentials, points the user's session to the new Online
+/// MIS session and retries `request` once with it.
+pub async fn with_relogin<T, F, Fut>(
+    state: &AppContext,
+    ValidatedCookieJar {
+        token,
+        session_id,
+        nrp,
+        ..
+    }: &ValidatedCookieJar,
+    request: F,
+) -> Result<T>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    match request(session_id.to_owned()).await {
+        Err(Error::Unauthorized(message)) => match relogin(state, nrp, session_id).await? {
+            Some(new_session_id) => {
+                state
+                    .sessions
+                    .update_session_id(token, new_session_id.clone())
+                    .await?;
+                request(new_session_id).await
+            }
+            None => Err(Error::Unauthorized(message)),
+        },
+        res => res,
+    }
+}
+
+/// Logs a session in again for the background refresh of a stale page, pointing it to the
+/// new Online MIS session as [`with_relogin`] does
+struct SessionRelogin {
+    state: AppContext,
+    token: String,
+    nrp: String,
+}
+
+#[async_trait]
+impl Relogin for SessionRelogin {
+    async fn relogin(&self, expired: &str) -> Result<Option<String>> {
+        let Some(session_id) = relogin(&self.state, &self.nrp, expired).await? else {
+            return Ok(None);
+        };
+        self.state
+            .sessions
+            .update_session_id(&self.token, session_id.clone())
+            .await?;
+
+        Ok(Some(session_id))
+    }
+}
+
+/// The re-login used when a page of `session` is refreshed in the background
+pub fn background_relogin(state: &AppContext, session: &ValidatedCookieJar) -> Arc<dyn Relogin> {
+    Arc::new(SessionRelogin {
+        state: state.clone(),
+        token: session.token.clone(),
+        nrp: session.nrp.clone(),
+    })
+}
+
+/// Logs the user in again after Online MIS rejected their `expired` session, returning
+/// the new one, or `None` when they can't be
+async fn relogin(state: &AppContext, nrp: &str, expired: &str) -> Result<Option<String>> {
+    coalesce(state.cache.as_ref(), &state.flights, nrp, expired, || {
+        login_again(state, nrp)
+    })
+    .await
+}
+
+/// Runs `login` once for all the requests of `nrp` whose `expired` session was rejected at
+/// the same time, here or on other replicas. The others wait for it and get the session it
+/// logged in to.
+async fn coalesce<F, Fut>(
+    cache: &dyn Cache,
+    flights: &SingleFlight,
+    nrp: &str,
+    expired: &str,
+    login: F,
+) -> Result<Option<String>>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Option<String>>>,
+{
+    let key = relogin_key(nrp);
+    let renewed = |session_id: Option<String>| session_id.filter(|id| id != expired);
+
+    // Logged in again since this request was sent
+    if let Some(session_id) = renewed(cache.get(&key).await?) {
+        return Ok(Some(session_id));
+    }
+
+    let Some(flight) = flights.lead_or_wait(&key).await? else {
+        return Ok(renewed(cache.get(&key).await?));
+    };
+
+    let res = login().await;
+    if let Ok(Some(session_id)) = &res {
+        cache
+            .set(&key, session_id.clone(), Expiry::In(RELOGIN_TTL))
+            .await?;
+    }
+    flight.release().await?;
+
+    res
+}
+
+async fn login_again(state: &AppContext, nrp: &str) -> Result<Option<String>> {
+    let Some(cipher) = &state.credential_cipher else {
+        return Ok(None);
+    };
+    let Some(sealed) = state.cache.get(&credential_key(nrp)).await? else {
+        return Ok(None);
+    };
+    let credentials = cipher.decrypt(&sealed).and_then(|plaintext| {
+        serde_json::from_slice::<Credentials>(&plaintext)
+            .map_err(|e| Error::from(anyhow!(e.to_string())))
+    });
+    let Credentials { email, password } = match credentials {
+        Ok(credentials) => credentials,
+        // e.g. encrypted with a key that was rotated out
+        Err(e) => {
+            tracing::warn!("Failed to read the credentials of '{nrp}', forgetting them: {e}");
+            forget_credentials(state, nrp).await?;
+            return Ok(None);
+        }
+    };
+
+    tracing::debug!("Online MIS session of '{nrp}' expired, logging in again");
+    let res = match login_cas(
+        LoginRequest {
+            email,
+            password,
+            remember: Some(true),
+            tokens: false,
+        },
+        state,
+    )
+    .await
+    {
+        Ok(res) if res.nrp == nrp => res,
+        Ok(res) => {
+            tracing::warn!("Re-login of '{nrp}' returned the session of '{}'", res.nrp);
+            forget_credentials(state, nrp).await?;
+            return Ok(None);
+        }
+        Err(Error::BadRequest(message)) => {
+            tracing::info!("Re-login of '{nrp}' was rejected by CAS: '{message}'");
+            forget_credentials(state, nrp).await?;
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(Some(res.session_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::coalesce;
+    use crate::core::{cache::MemoryCache, single_flight::SingleFlight};
+
+    #[tokio::test]
+    async fn logs_in_again_once_for_concurrent_requests() {
+        let cache = Arc::new(MemoryCache::new(16));
+        let flights = SingleFlight::new(cache.clone(), 5);
+        let logins = AtomicUsize::new(0);
+        let login = |session_id: &'static str| {
+            let logins = &logins;
+            move || async move {
+                logins.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(Some(session_id.to_owned()))
+            }
+        };
+
+        let (a, b) = tokio::join!(
+            coalesce(cache.as_ref(), &flights, "3122600001", "old", login("new")),
+            coalesce(
+                cache.as_ref(),
+                &flights,
+                "3122600001",
+                "old",
+                login("other")
+            ),
+        );
+        assert_eq!(a.unwrap().as_deref(), Some("new"));
+        assert_eq!(b.unwrap().as_deref(), Some("new"));
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+
+        // Once the new session expires too, the user is logged in again
+        let c = coalesce(
+            cache.as_ref(),
+            &flights,
+            "3122600001",
+            "new",
+            login("newer"),
+        )
+        .await;
+        assert_eq!(c.unwrap().as_deref(), Some("newer"));
+        assert_eq!(logins.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use aide::axum::{routing::get_with, ApiRouter};
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::core::{
+    api_response::{ApiResponseTrait, SuccessApiResponse},
+    cache::Expiry,
+    error::Error,
+    generate_openapi_response::generate_response,
+    result::Result,
+};
+
+use super::AppContext;
+
+const OPENAPI_TAG: &str = "Health";
+
+const UPSTREAM_KEY: &str = "readyz:upstream";
+
+pub fn router() -> ApiRouter<AppContext> {
+    ApiRouter::new()
+        .api_route(
+            "/healthz",
+            get_with(
+                health_handler,
+                // Nothing tells which state the handler runs with
+                generate_response::<_, _, _, _, AppContext, _>(health_handler, OPENAPI_TAG, false),
+            ),
+        )
+        .api_route(
+            "/readyz",
+            get_with(
+                ready_handler,
+                generate_response(ready_handler, OPENAPI_TAG, false),
+            ),
+        )
+        .api_route(
+            "/version",
+            get_with(
+                version_handler,
+                // Nothing tells which state the handler runs with
+                generate_response::<_, _, _, _, AppContext, _>(version_handler, OPENAPI_TAG, false),
+            ),
+        )
+}
+
+/// Answers as long as the process does
+#[axum::debug_handler]
+async fn health_handler() -> Result<SuccessApiResponse<String>> {
+    Ok(SuccessApiResponse::new("OK".to_owned()))
+}
+
+/// Answers once the cache, and Online MIS and CAS when `READYZ_UPSTREAM_TTL` is set, can
+/// be reached
+#[axum::debug_handler]
+async fn ready_handler(
+    State(state): State<AppContext>,
+) -> Result<SuccessApiResponse<ReadyResponse>> {
+    state.cache.ping().await.map_err(|e| {
+        tracing::warn!("The cache can't be reached: {e}");
+        Error::NotReady("The cache can't be reached".to_owned())
+    })?;
+
+    let upstream = match state.readyz_upstream_ttl {
+        Some(ttl) => Some(check_upstream(&state, ttl).await?),
+        None => None,
+    };
+
+    match upstream {
+        Some(UpstreamReadiness { mis: false, .. }) => {
+            Err(Error::NotReady("Online MIS can't be reached".to_owned()))
+        }
+        Some(UpstreamReadiness { cas: false, .. }) => {
+            Err(Error::NotReady("CAS can't be reached".to_owned()))
+        }
+        upstream => Ok(SuccessApiResponse::new(ReadyResponse {
+            cache: true,
+            upstream,
+        })),
+    }
+}
+
+/// Checks whether Online MIS and CAS answer, at most once every `ttl` seconds
+async fn check_upstream(state: &AppContext, ttl: u64) -> Result<UpstreamReadiness> {
+    let cached = state
+        .cache
+        .get(UPSTREAM_KEY)
+        .await?
+        .and_then(|value| serde_json::from_str(&value).ok());
+    if let Some(readiness) = cached {
+        return Ok(readiness);
+    }
+
+    let reachable = |url: String| async move {
+        match state
+            .upstream
+            .client()
+            .get(&url)
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+        {
+            Ok(res) => !res.status().is_server_error(),
+            Err(e) => {
+                tracing::warn!("'{url}' can't be reached: {e}");
+                false
+            }
+        }
+    };
+    let (mis, cas) = tokio::join!(
+        reachable(format!("{}/", state.mis_base_url)),
+        reachable(format!("{}/login", state.cas_base_url))
+    );
+
+    let readiness = UpstreamReadiness { mis, cas };
+    let value = serde_json::to_string(&readiness).map_err(|e| anyhow::anyhow!(e))?;
+    state
+        .cache
+        .set(UPSTREAM_KEY, value, Expiry::In(ttl))
+        .await?;
+
+    Ok(readiness)
+}
+
+#[axum::debug_handler]
+async fn version_handler() -> Result<SuccessApiResponse<VersionResponse>> {
+    let built_at = env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|ts| OffsetDateTime::from_unix_timestamp(ts).ok())
+        .and_then(|at| at.format(&Rfc3339).ok())
+        .unwrap_or_default();
+
+    Ok(SuccessApiResponse::new(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        git_hash: env!("GIT_HASH").to_owned(),
+        built_at,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default, Clone, Copy)]
+struct UpstreamReadiness {
+    mis: bool,
+    cas: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+struct ReadyResponse {
+    cache: bool,
+    /// Only checked when `READYZ_UPSTREAM_TTL` is set
+    upstream: Option<UpstreamReadiness>,
+}
+
+#[derive(Debug, Serialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+struct VersionResponse {
+    version: String,
+    git_hash: String,
+    /// When the binary was built, in RFC 3339
+    built_at: String,
+}
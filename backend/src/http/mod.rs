@@ -1,13 +1,36 @@
-use crate::config::AppConfig;
-use crate::core::result::Result;
+use crate::config::{AppConfig, CacheBackend, CookieSameSite};
+use crate::core::{
+    axum_extractor,
+    cache::{Cache, MemoryCache, RedisCache},
+    crypto::{Cipher, Keyring},
+    error::Error,
+    metrics::{self, DURATION_BUCKETS},
+    rate_limit::{self, Limits, RateLimiter, RateLimits},
+    request_id::{self, REQUEST_ID_HEADER},
+    result::Result,
+    selector::{SelectorEntry, SelectorRegistry},
+    session::SessionStore,
+    single_flight::SingleFlight,
+    ttl::{CacheTtl, Ttl},
+    upstream::{Upstream, UpstreamConfig},
+};
 use aide::{axum::ApiRouter, openapi::OpenApi};
 use anyhow::{anyhow, Context};
-use axum::{Extension, Router};
+use axum::{
+    extract::{FromRef, MatchedPath, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::IntoResponse,
+    routing::get,
+    Extension, Router,
+};
+use axum_extra::extract::cookie::SameSite;
 use bb8_redis::RedisConnectionManager;
 use docs::{api_docs, docs_routes};
-use redis::AsyncCommands;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use reqwest::Method;
 use std::{
+    future::IntoFuture,
     net::{Ipv4Addr, SocketAddr},
     str::FromStr,
     sync::Arc,
@@ -21,33 +44,179 @@ use tower_http::{
 
 mod docs;
 mod features;
+mod health;
 
 #[derive(Clone)]
 pub struct AppContext {
-    client: reqwest::Client,
-    redis_pool: bb8::Pool<RedisConnectionManager>,
+    upstream: Arc<Upstream>,
+    cache: Arc<dyn Cache>,
+    cache_ttl: CacheTtl,
+    flights: Arc<SingleFlight>,
+    rate_limiter: Arc<RateLimiter>,
+    readyz_upstream_ttl: Option<u64>,
+    metrics: PrometheusHandle,
+    metrics_token: Option<String>,
     proxy_url: Option<String>,
+    mis_base_url: String,
+    cas_base_url: String,
+    selectors: Arc<SelectorRegistry>,
+    credential_cipher: Option<Cipher>,
+    credential_ttl: u64,
+    sessions: SessionStore,
+    cookie_same_site: SameSite,
+    cookie_secure: bool,
+}
+
+impl FromRef<AppContext> for SessionStore {
+    fn from_ref(ctx: &AppContext) -> Self {
+        ctx.sessions.clone()
+    }
 }
 
 pub async fn serve(cfg: AppConfig) -> anyhow::Result<()> {
-    let redis_pool = connect_redis(cfg.redis_address, cfg.redis_password, cfg.redis_user).await?;
+    let metrics = PrometheusBuilder::new()
+        .set_buckets(DURATION_BUCKETS)?
+        .install_recorder()
+        .context("Failed to install the metrics recorder")?;
+
+    let cache: Arc<dyn Cache> = match cfg.cache_backend {
+        CacheBackend::Redis => {
+            let cache = RedisCache::new(
+                connect_redis(cfg.redis_address, cfg.redis_password, cfg.redis_user).await?,
+            );
+            cache.ping().await.context("Failed to ping redis")?;
+            Arc::new(cache)
+        }
+        CacheBackend::Memory => {
+            tracing::info!(
+                "Using the memory cache, holding {} pages",
+                cfg.cache_capacity
+            );
+            Arc::new(MemoryCache::new(cfg.cache_capacity))
+        }
+    };
+
+    let selectors = SelectorRegistry::load(default_selectors(), cfg.selector_file.as_deref())?;
+
+    let session_keyring = if cfg.session_keys.is_empty() {
+        tracing::warn!("SESSION_KEYS is not set, sessions won't survive a restart");
+        Keyring::generate()
+    } else {
+        tracing::info!("Using {} session key(s)", cfg.session_keys.len());
+        Keyring::from_base64_keys(&cfg.session_keys).context("Invalid SESSION_KEYS")?
+    };
+
+    if cfg.metrics_token.is_none() {
+        tracing::warn!("METRICS_TOKEN is not set, /metrics must be blocked by the reverse proxy");
+    }
+
+    if matches!(cfg.cookie_same_site, CookieSameSite::None) && !cfg.cookie_secure {
+        tracing::warn!("Browsers ignore SameSite=None cookies without COOKIE_SECURE");
+    }
+
+    let credential_cipher = match cfg.credential_key {
+        Some(key) => {
+            tracing::info!("Automatic re-login is enabled");
+            Some(Cipher::from_base64_key(&key).context("Invalid CREDENTIAL_KEY")?)
+        }
+        None => {
+            tracing::info!("Automatic re-login is disabled");
+            None
+        }
+    };
+
+    let upstream_config = UpstreamConfig {
+        concurrency: cfg.upstream_concurrency,
+        concurrency_per_user: cfg.upstream_concurrency_per_nrp,
+        retries: cfg.upstream_retries,
+        breaker_threshold: cfg.upstream_breaker_threshold,
+        breaker_cooldown: Duration::from_secs(cfg.upstream_breaker_cooldown),
+        connect_timeout: Duration::from_secs(cfg.upstream_connect_timeout),
+        read_timeout: Duration::from_secs(cfg.upstream_read_timeout),
+        deadline: Duration::from_secs(cfg.upstream_deadline),
+    };
 
     let api_context = AppContext {
-        client: match cfg.proxy_url.clone() {
-            Some(url) => {
-                tracing::info!("Using proxy: '{url}'");
-                reqwest::Client::builder()
-                    .proxy(reqwest::Proxy::all(url)?)
-                    .build()?
-            }
-            None => {
-                tracing::info!("Not using proxy");
-                reqwest::Client::new()
+        upstream: Arc::new(Upstream::new(
+            match cfg.proxy_url.clone() {
+                Some(url) => {
+                    tracing::info!("Using proxy: '{url}'");
+                    upstream_config
+                        .client_builder()
+                        .proxy(reqwest::Proxy::all(url)?)
+                        .build()?
+                }
+                None => {
+                    tracing::info!("Not using proxy");
+                    upstream_config.client_builder().build()?
+                }
+            },
+            upstream_config,
+        )),
+        readyz_upstream_ttl: cfg.readyz_upstream_ttl,
+        metrics,
+        metrics_token: cfg.metrics_token,
+        proxy_url: cfg.proxy_url,
+        mis_base_url: cfg.mis_base_url.trim_end_matches('/').to_owned(),
+        cas_base_url: cfg.cas_base_url.trim_end_matches('/').to_owned(),
+        selectors: Arc::new(selectors),
+        credential_cipher,
+        credential_ttl: cfg.credential_ttl,
+        sessions: SessionStore::new(
+            cache.clone(),
+            session_keyring,
+            cfg.session_ttl,
+            cfg.access_token_ttl,
+        ),
+        cookie_same_site: match cfg.cookie_same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        },
+        cookie_secure: cfg.cookie_secure,
+        flights: Arc::new(SingleFlight::new(
+            cache.clone(),
+            cfg.cache_lock_ttl.unwrap_or(5 * cfg.upstream_deadline + 5),
+        )),
+        rate_limiter: Arc::new(RateLimiter::new(
+            cache.clone(),
+            RateLimits {
+                login: Limits {
+                    ip: cfg.rate_limit_login_ip,
+                    nrp: cfg.rate_limit_login_nrp,
+                },
+                academic: Limits {
+                    ip: cfg.rate_limit_academic_ip,
+                    nrp: cfg.rate_limit_academic_nrp,
+                },
+                default: Limits {
+                    ip: cfg.rate_limit_default_ip,
+                    nrp: cfg.rate_limit_default_nrp,
+                },
+            },
+            cfg.rate_limit_forwarded_for,
+        )),
+        cache,
+        cache_ttl: {
+            let ttl = |policy| Ttl {
+                policy,
+                utc_offset: cfg.cache_utc_offset,
+                stale: cfg.cache_stale_ttl,
+            };
+            CacheTtl {
+                absen: ttl(cfg.cache_ttl_absen),
+                frs: ttl(cfg.cache_ttl_frs),
+                jadwal: ttl(cfg.cache_ttl_jadwal),
+                nilai: ttl(cfg.cache_ttl_nilai),
+                logbook: ttl(cfg.cache_ttl_logbook),
             }
         },
-        redis_pool,
-        proxy_url: cfg.proxy_url,
     };
+    tracing::info!(
+        "Using online mis at '{}' and cas at '{}'",
+        api_context.mis_base_url,
+        api_context.cas_base_url
+    );
 
     let app = api_router(api_context);
 
@@ -57,13 +226,114 @@ pub async fn serve(cfg: AppConfig) -> anyhow::Result<()> {
     };
     tracing::debug!("server address {}", addr);
 
-    let addr = SocketAddr::from((addr, cfg.server_port.unwrap_or_else(|| 8080)));
+    let addr = SocketAddr::from((addr, cfg.server_port.unwrap_or(8080)));
 
     let listener = TcpListener::bind(addr).await?;
     tracing::info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app.into_make_service())
-        .await
-        .context("Error running HTTP server")
+    let (draining, drained) = tokio::sync::oneshot::channel();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        let _ = draining.send(());
+    });
+
+    let drain_timeout = Duration::from_secs(cfg.shutdown_timeout);
+    tokio::select! {
+        res = server.into_future() => res.context("Error running HTTP server")?,
+        _ = async {
+            let _ = drained.await;
+            tokio::time::sleep(drain_timeout).await;
+        } => {
+            tracing::warn!("Requests still in flight after {drain_timeout:?}, dropping them");
+        }
+    }
+
+    tracing::info!("Server stopped");
+    Ok(())
+}
+
+/// The span every log line of a request is in, tagged with the id `request_id::propagate`
+/// gave it to tell concurrent requests apart.
+///
+/// Records the matched route rather than the uri, the feed routes carry their secret
+/// token in the path and spans end up in the logs and the exported traces
+fn request_span(req: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str);
+
+    tracing::info_span!(
+        "request",
+        method = %req.method(),
+        route,
+        version = ?req.version(),
+        request_id,
+    )
+}
+
+/// Resolves once the process is asked to stop with SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutting down, waiting for the requests in flight");
+}
+
+/// Renders the metrics in the Prometheus text format, to those sending `METRICS_TOKEN`
+async fn metrics_handler(
+    State(state): State<AppContext>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    if !metrics::authorized(&headers, state.metrics_token.as_deref()) {
+        return Err(Error::Unauthorized("Invalid metrics token".to_owned()));
+    }
+    state.cache.record_metrics();
+
+    Ok((
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        state.metrics.render(),
+    ))
+}
+
+pub(crate) fn default_selectors() -> impl Iterator<Item = &'static SelectorEntry> {
+    axum_extractor::SELECTORS
+        .iter()
+        .chain(features::selectors())
 }
 
 async fn connect_redis(
@@ -80,25 +350,13 @@ async fn connect_redis(
         .await
         .map_err(|e| anyhow!(e.to_string()))?;
 
-    {
-        tracing::debug!("pinging redis");
-        let mut conn = pool.get().await.map_err(|e| anyhow!(e.to_string()))?;
-        conn.set::<&str, &str, ()>("foo", "bar")
-            .await
-            .map_err(|e| anyhow!(e.to_string()))?;
-        let result: String = conn.get("foo").await.map_err(|e| anyhow!(e.to_string()))?;
-        assert_eq!(result, "bar");
-        conn.del::<&str, ()>("foo")
-            .await
-            .map_err(|e| anyhow!(e.to_string()))?;
-    }
-
-    tracing::debug!("successfully connected to redis and pinged it");
-    return Ok(pool);
+    tracing::debug!("successfully connected to redis");
+    Ok(pool)
 }
 
 fn api_router(ctx: AppContext) -> Router {
     let mut api = OpenApi::default();
+    let rate_limiter = ctx.rate_limiter.clone();
 
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::DELETE])
@@ -107,6 +365,12 @@ fn api_router(ctx: AppContext) -> Router {
             axum::http::header::AUTHORIZATION,
             axum::http::header::ACCEPT,
         ])
+        .expose_headers([
+            axum::http::header::AGE,
+            axum::http::header::RETRY_AFTER,
+            axum::http::HeaderName::from_static("cache-status"),
+            REQUEST_ID_HEADER.clone(),
+        ])
         .allow_credentials(true)
         .allow_origin(
             "http://localhost:3000"
@@ -114,11 +378,20 @@ fn api_router(ctx: AppContext) -> Router {
                 .unwrap(),
         );
 
+    // Nested rather than nested as a service, so `MatchedPath` holds the whole route
     ApiRouter::new()
-        .nest_api_service("/api/v1", features::router().with_state(ctx))
+        .merge(health::router())
+        .route("/metrics", get(metrics_handler))
+        .nest("/api/v1", features::router())
+        .with_state(ctx)
         .nest_api_service("/docs", docs_routes())
         .finish_api_with(&mut api, api_docs)
         .layer(Extension(Arc::new(api)))
+        .layer(middleware::from_fn_with_state(
+            rate_limiter,
+            rate_limit::limit,
+        ))
+        .layer(middleware::from_fn(metrics::track))
         .layer(cors)
         // Enables logging. Use `RUST_LOG=tower_http=debug`
         .layer((
@@ -127,8 +400,70 @@ fn api_router(ctx: AppContext) -> Router {
                 axum::http::HeaderValue::from_static("application/json"),
             ),
             CompressionLayer::new(),
-            TraceLayer::new_for_http(),
-            TimeoutLayer::new(Duration::from_secs(60)),
+            // At info so the request spans are exported along with the spans inside them
+            TraceLayer::new_for_http().make_span_with(request_span),
+            TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(60)),
             CatchPanicLayer::new(),
         ))
+        // Outermost, so the request span and every response carry the id
+        .layer(middleware::from_fn(request_id::propagate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::request_span;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+    /// Writes down the fields of every span opened
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<String>>);
+
+    impl Visit for SpanFields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let mut fields = self.0.lock().unwrap();
+            fields.push_str(&format!("{}={value:?} ", field.name()));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for SpanFields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_feed_tokens_out_of_the_request_span() {
+        let fields = SpanFields::default();
+        let _guard = tracing_subscriber::registry()
+            .with(fields.clone())
+            .set_default();
+
+        let router = Router::new()
+            .route("/api/v1/feeds/{token}/jadwal.ics", get(|| async { "" }))
+            .layer(TraceLayer::new_for_http().make_span_with(request_span));
+        router
+            .oneshot(
+                Request::get("/api/v1/feeds/s3cr3t-t0k3n/jadwal.ics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let fields = fields.0.lock().unwrap();
+        assert!(
+            fields.contains("/api/v1/feeds/{token}/jadwal.ics"),
+            "{fields}"
+        );
+        assert!(!fields.contains("s3cr3t-t0k3n"), "{fields}");
+    }
 }
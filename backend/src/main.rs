@@ -1,22 +1,20 @@
 use clap::Parser;
-use online_mis_backend::config::AppConfig;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use online_mis_backend::{config::AppConfig, telemetry};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                format!("{}=debug,tower_http=info", env!("CARGO_CRATE_NAME")).into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer().with_file(false))
-        .init();
-
     let cfg = AppConfig::parse();
-    online_mis_backend::http::serve(cfg).await?;
+    let tracer_provider = telemetry::init(&cfg)?;
+
+    let res = online_mis_backend::http::serve(cfg).await;
+
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            tracing::warn!("Failed to flush the remaining spans: {e}");
+        }
+    }
 
-    Ok(())
+    res
 }
@@ -0,0 +1,108 @@
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use anyhow::Context;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use sha2::{Digest, Sha256};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::config::{AppConfig, LogFormat};
+
+static NRP_SALT: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Sets up logging in `LOG_FORMAT`, and exports traces over OTLP when `OTLP_ENDPOINT` is
+/// set. The returned provider flushes the remaining spans when shut down.
+pub fn init(cfg: &AppConfig) -> anyhow::Result<Option<SdkTracerProvider>> {
+    if let Some(salt) = &cfg.otlp_nrp_salt {
+        let _ = NRP_SALT.set(salt.as_bytes().to_vec());
+    }
+
+    let provider = match &cfg.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                .build()
+                .context("Failed to build the OTLP exporter")?;
+
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(
+                        Resource::builder()
+                            .with_service_name(cfg.otlp_service_name.clone())
+                            .build(),
+                    )
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                format!("{}=debug,tower_http=info", env!("CARGO_CRATE_NAME")).into()
+            }),
+        )
+        .with(
+            matches!(cfg.log_format, LogFormat::Pretty)
+                .then(|| tracing_subscriber::fmt::layer().with_file(false)),
+        )
+        // Every span the event is in, so the id of the request shows up on each line
+        .with(matches!(cfg.log_format, LogFormat::Json).then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(false)
+                .with_span_list(true)
+        }))
+        .with(provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_CRATE_NAME")))
+        }))
+        .init();
+
+    match &cfg.otlp_endpoint {
+        Some(endpoint) => {
+            if cfg.otlp_nrp_salt.is_none() {
+                tracing::warn!("OTLP_NRP_SALT is not set, nrps are hashed differently on restart");
+            }
+            tracing::info!("Exporting traces to '{endpoint}'");
+        }
+        None => tracing::debug!("Not exporting traces"),
+    }
+
+    Ok(provider)
+}
+
+/// Hashes an nrp to tell the traces of a student apart without exporting it
+pub fn hash_nrp(nrp: &str) -> String {
+    let salt = NRP_SALT.get_or_init(|| {
+        let mut salt = vec![0; 32];
+        OsRng.fill_bytes(&mut salt);
+        salt
+    });
+
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(nrp.as_bytes())
+        .finalize();
+
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_nrp;
+
+    #[test]
+    fn hashes_nrps_consistently() {
+        let hash = hash_nrp("3122600001");
+
+        assert_eq!(hash.len(), 16);
+        assert_eq!(hash, hash_nrp("3122600001"));
+        assert_ne!(hash, hash_nrp("3122600002"));
+        assert!(!hash.contains("3122600001"));
+    }
+}